@@ -0,0 +1,310 @@
+//! Throughput of the engine, from parsing CSV rows to processing transactions.
+//!
+//! End-to-end runs use a synthetic dataset unless `KITHMONITE_BENCH_CSV` points to a
+//! file produced by the generator.
+
+use std::time::Duration;
+
+use criterion::{criterion_group, criterion_main, BatchSize, BenchmarkId, Criterion, Throughput};
+use csv::Trim;
+use kithmonite::{
+    account::{MonetaryValue, Transaction, TransactionKind},
+    cli::{self, AmountPolicy, ErrorPolicy, TransactionRow},
+    processor::PaymentProcessor,
+    schema::ColumnMapping,
+};
+use rust_decimal::Decimal;
+
+/// Number of rows of the synthetic dataset.
+const ROWS: u32 = 100_000;
+/// Number of clients the synthetic transactions are spread over.
+const CLIENTS: u32 = 1_000;
+
+fn client(tx: u32) -> u16 {
+    (tx % CLIENTS) as u16
+}
+
+/// Builds a CSV dataset of deposits, withdrawals, transfers, disputes and card
+/// payments, padded with whitespace like the generator output. One deposit out of four
+/// is disputed, then resolved, and authorizations are alternately captured and voided.
+fn synthetic_csv() -> String {
+    let mut csv = String::from("type, client, tx, amount, counterparty\n");
+
+    for tx in 0..ROWS {
+        // Each client makes twelve transactions in a row, and is credited the next
+        // client's transfer.
+        let client = (tx / 12) % CLIENTS;
+        let recipient = (client + 1) % CLIENTS;
+        // Debits are small enough to be covered by the deposits of the same client.
+        let credit = format!("{}.{:04}", 1 + tx % 1000, tx % 10_000);
+        let debit = format!("{}.{:04}", 1 + tx % 1000 / 8, tx % 10_000);
+
+        let row = match tx % 12 {
+            0..=3 => format!("deposit, {client}, {tx}, {credit}, "),
+            4 => format!("withdrawal, {client}, {tx}, {debit}, "),
+            5 => format!("transfer, {client}, {tx}, {debit}, {recipient}"),
+            6 => format!("dispute, {client}, {}, , ", tx - 6),
+            7 => format!("resolve, {client}, {}, , ", tx - 7),
+            8 | 10 => format!("authorize, {client}, {tx}, {debit}, "),
+            9 => format!("capture, {client}, {}, , ", tx - 1),
+            _ => format!("void, {client}, {}, , ", tx - 1),
+        };
+
+        csv.push_str(&row);
+        csv.push('\n');
+    }
+
+    csv
+}
+
+fn dataset() -> String {
+    match std::env::var("KITHMONITE_BENCH_CSV") {
+        Ok(path) => std::fs::read_to_string(&path)
+            .unwrap_or_else(|error| panic!("unable to read {path}: {error}")),
+        Err(_) => synthetic_csv(),
+    }
+}
+
+fn parse_rows(csv: &str, trim: Trim) -> Vec<TransactionRow> {
+    csv::ReaderBuilder::new()
+        .trim(trim)
+        .from_reader(csv.as_bytes())
+        .deserialize()
+        .filter_map(Result::ok)
+        .collect()
+}
+
+fn amount(value: i64) -> MonetaryValue {
+    Decimal::new(value, 4)
+        .try_into()
+        .expect("positive amounts are valid")
+}
+
+/// Builds the transaction applied at a given step of a workload.
+type Workload = fn(u32) -> Transaction;
+/// Builds the processor a workload starts from.
+type Setup = fn() -> PaymentProcessor;
+
+// ----------------------------------------------------------------------------
+
+fn csv_parsing(c: &mut Criterion) {
+    let csv = synthetic_csv();
+    let trimmed_csv = csv.replace(", ", ",");
+
+    let mut group = c.benchmark_group("csv_parsing");
+    group.throughput(Throughput::Elements(ROWS.into()));
+
+    group.bench_function("trim_all", |b| b.iter(|| parse_rows(&csv, Trim::All)));
+    group.bench_function("no_trim", |b| {
+        b.iter(|| parse_rows(&trimmed_csv, Trim::None))
+    });
+
+    group.finish();
+}
+
+fn conversion(c: &mut Criterion) {
+    let rows = parse_rows(&synthetic_csv(), Trim::All);
+
+    let mut group = c.benchmark_group("conversion");
+    group.throughput(Throughput::Elements(rows.len() as u64));
+
+    group.bench_function("transaction_try_from", |b| {
+        b.iter_batched(
+            || rows.clone(),
+            |rows| {
+                rows.into_iter()
+                    .filter_map(|row| Transaction::try_from(row).ok())
+                    .count()
+            },
+            BatchSize::LargeInput,
+        )
+    });
+
+    group.finish();
+}
+
+fn process_by_kind(c: &mut Criterion) {
+    const TRANSACTIONS: u32 = 10_000;
+
+    // Each workload starts from accounts holding one deposit per transaction to apply,
+    // so that every transaction under measurement is accepted.
+    fn funded() -> PaymentProcessor {
+        let mut processor = PaymentProcessor::new();
+        for tx in 0..TRANSACTIONS {
+            let deposit = Transaction::new(tx, TransactionKind::Deposit(amount(100_000)));
+            processor
+                .process(client(tx), deposit)
+                .expect("deposits are accepted");
+        }
+        processor
+    }
+
+    // Captures and voids settle one pending authorization per transaction to apply.
+    fn authorized() -> PaymentProcessor {
+        let mut processor = funded();
+        for tx in 0..TRANSACTIONS {
+            let authorization = Transaction::new(
+                tx + TRANSACTIONS,
+                TransactionKind::Authorize(amount(10_000)),
+            );
+            processor
+                .process(client(tx), authorization)
+                .expect("authorizations are accepted");
+        }
+        processor
+    }
+
+    let mut group = c.benchmark_group("process");
+    group.throughput(Throughput::Elements(TRANSACTIONS.into()));
+
+    let workloads: [(&str, Setup, Workload); 8] = [
+        ("deposit", funded, |tx| {
+            Transaction::new(tx + TRANSACTIONS, TransactionKind::Deposit(amount(10_000)))
+        }),
+        ("withdrawal", funded, |tx| {
+            Transaction::new(
+                tx + TRANSACTIONS,
+                TransactionKind::Withdrawal(amount(10_000)),
+            )
+        }),
+        ("transfer", funded, |tx| {
+            Transaction::new(
+                tx + TRANSACTIONS,
+                TransactionKind::Transfer {
+                    recipient: client(tx + 1).into(),
+                    amount: amount(10_000),
+                },
+            )
+        }),
+        ("dispute", funded, |tx| {
+            Transaction::new(tx, TransactionKind::Dispute(None))
+        }),
+        ("refund", funded, |tx| {
+            Transaction::new(tx, TransactionKind::Refund(None))
+        }),
+        ("authorize", funded, |tx| {
+            Transaction::new(
+                tx + TRANSACTIONS,
+                TransactionKind::Authorize(amount(10_000)),
+            )
+        }),
+        ("capture", authorized, |tx| {
+            Transaction::new(tx + TRANSACTIONS, TransactionKind::Capture(None))
+        }),
+        ("void", authorized, |tx| {
+            Transaction::new(tx + TRANSACTIONS, TransactionKind::Void)
+        }),
+    ];
+
+    for (kind, setup, transaction) in workloads {
+        group.bench_function(BenchmarkId::from_parameter(kind), |b| {
+            b.iter_batched(
+                setup,
+                |mut processor| {
+                    for tx in 0..TRANSACTIONS {
+                        let _ = processor.process(client(tx), transaction(tx));
+                    }
+                    processor
+                },
+                BatchSize::LargeInput,
+            )
+        });
+    }
+
+    group.finish();
+}
+
+fn dispute_heavy(c: &mut Criterion) {
+    const DEPOSITS: u32 = 10_000;
+
+    let mut group = c.benchmark_group("dispute_heavy");
+    group.throughput(Throughput::Elements((DEPOSITS * 3).into()));
+
+    // Every deposit is disputed, then alternately resolved or charged back.
+    group.bench_function("dispute_chains", |b| {
+        b.iter(|| {
+            let mut processor = PaymentProcessor::new();
+
+            for tx in 0..DEPOSITS {
+                let client = client(tx);
+                let settlement = match tx % 2 {
+                    0 => TransactionKind::Resolve,
+                    _ => TransactionKind::Chargeback,
+                };
+
+                let _ = processor.process(
+                    client,
+                    Transaction::new(tx, TransactionKind::Deposit(amount(10_000))),
+                );
+                let _ =
+                    processor.process(client, Transaction::new(tx, TransactionKind::Dispute(None)));
+                let _ = processor.process(client, Transaction::new(tx, settlement));
+            }
+
+            processor
+        })
+    });
+
+    group.finish();
+}
+
+fn end_to_end(c: &mut Criterion) {
+    let csv = dataset();
+    let rows = csv.lines().count().saturating_sub(1) as u64;
+
+    let mut group = c.benchmark_group("end_to_end");
+    group.throughput(Throughput::Elements(rows));
+    group.sample_size(10);
+    group.measurement_time(Duration::from_secs(20));
+
+    // Mirrors the main binary with its default options, writing the accounts to memory
+    // instead of stdout and the skipped rows nowhere instead of stderr.
+    group.bench_function("csv_to_accounts", |b| {
+        b.iter(|| {
+            let mut reader = csv::ReaderBuilder::new()
+                .trim(Trim::All)
+                .from_reader(csv.as_bytes());
+            let header = reader.headers().expect("the header is readable").clone();
+            let mapper = ColumnMapping::default()
+                .resolve(&header)
+                .expect("the columns are recognized");
+            let error_policy = ErrorPolicy::Lenient;
+            let mut processor = PaymentProcessor::new();
+            let mut errors = 0;
+
+            for record in reader.records() {
+                let accepted = match record.and_then(|record| mapper.deserialize(&record)) {
+                    Ok(row) => {
+                        cli::apply_with_policy(&mut processor, row, AmountPolicy::Reject).accepted
+                    }
+                    Err(_) => false,
+                };
+
+                if !accepted {
+                    errors += 1;
+                    assert!(!error_policy.should_abort(errors));
+                }
+            }
+
+            let mut writer = csv::Writer::from_writer(Vec::new());
+            for log in processor.account_logs().filter_map(Result::ok) {
+                writer
+                    .serialize(cli::Output::from(log.state().clone()))
+                    .expect("accounts are serializable");
+            }
+            writer.into_inner().expect("writing to memory cannot fail")
+        })
+    });
+
+    group.finish();
+}
+
+criterion_group!(
+    benches,
+    csv_parsing,
+    conversion,
+    process_by_kind,
+    dispute_heavy,
+    end_to_end
+);
+criterion_main!(benches);
@@ -0,0 +1,12 @@
+fn main() {
+    println!("cargo:rerun-if-changed=build.rs");
+
+    #[cfg(feature = "grpc")]
+    {
+        let protoc = protoc_bin_vendored::protoc_bin_path().expect("protoc is vendored");
+        std::env::set_var("PROTOC", protoc);
+
+        tonic_build::compile_protos("proto/kithmonite.proto")
+            .expect("unable to compile protobuf definitions");
+    }
+}
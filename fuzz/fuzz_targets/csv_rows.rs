@@ -0,0 +1,16 @@
+//! Arbitrary bytes read as a transactions file, the way the binary reads it. Rows may
+//! fail to deserialize or convert, but never panic.
+
+#![no_main]
+
+use csv::Trim;
+use kithmonite::{account::Transaction, cli::TransactionRow};
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    let mut reader = csv::ReaderBuilder::new().trim(Trim::All).from_reader(data);
+
+    for row in reader.deserialize::<TransactionRow>().flatten() {
+        let _ = Transaction::try_from(row);
+    }
+});
@@ -0,0 +1,67 @@
+//! Arbitrary bytes read as a transactions file and applied the way the binary does:
+//! columns are mapped from the header, amounts are validated according to the policy
+//! picked by the first byte, and rejected rows are counted on their account. Every
+//! rejected row must be counted once, and no balance may become negative.
+
+#![no_main]
+
+use std::collections::HashMap;
+
+use csv::Trim;
+use kithmonite::{
+    cli::{self, AmountPolicy},
+    processor::PaymentProcessor,
+    schema::ColumnMapping,
+};
+use libfuzzer_sys::fuzz_target;
+use rust_decimal::Decimal;
+
+fuzz_target!(|data: &[u8]| {
+    let Some((policy, data)) = data.split_first() else {
+        return;
+    };
+    let policy = match policy % 2 {
+        0 => AmountPolicy::Reject,
+        _ => AmountPolicy::Warn,
+    };
+
+    let mut reader = csv::ReaderBuilder::new().trim(Trim::All).from_reader(data);
+    let Ok(header) = reader.headers().cloned() else {
+        return;
+    };
+    let Ok(mapper) = ColumnMapping::default().resolve(&header) else {
+        return;
+    };
+
+    let mut processor = PaymentProcessor::new();
+    let mut rejected: HashMap<u16, u64> = HashMap::new();
+
+    for record in reader.records().flatten() {
+        let Ok(row) = mapper.deserialize(&record) else {
+            continue;
+        };
+
+        let result = cli::apply_with_policy(&mut processor, row, policy);
+        assert_eq!(result.accepted, result.error.is_none());
+        if !result.accepted {
+            *rejected.entry(result.client).or_default() += 1;
+        }
+    }
+
+    for log in processor.account_logs() {
+        let log = log.expect("memory storage cannot fail");
+        let account = log.state();
+        for balance in [account.available, account.held, account.authorized].map(Decimal::from) {
+            assert!(!balance.is_sign_negative(), "negative balance {balance}");
+        }
+
+        let client = u16::from(account.client_id);
+        assert_eq!(
+            log.rejected(),
+            rejected.remove(&client).unwrap_or_default(),
+            "rejections of client {client}"
+        );
+    }
+
+    assert!(rejected.is_empty(), "rejections without an account: {rejected:?}");
+});
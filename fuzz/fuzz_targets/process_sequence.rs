@@ -0,0 +1,69 @@
+//! Arbitrary bytes read as a transactions file and processed row by row. After every
+//! row, no balance may be negative and the funds held by all accounts may only change
+//! by the amount the transaction brings in or takes out.
+
+#![no_main]
+
+use std::time::Duration;
+
+use csv::Trim;
+use kithmonite::{
+    account::{Transaction, TransactionKind},
+    cli::TransactionRow,
+    processor::PaymentProcessor,
+};
+use libfuzzer_sys::fuzz_target;
+use rust_decimal::Decimal;
+
+const DAY: Duration = Duration::from_secs(24 * 60 * 60);
+
+fn total_funds(processor: &PaymentProcessor) -> Decimal {
+    processor
+        .accounts()
+        .map(|account| {
+            let account = account.expect("memory storage cannot fail");
+            let balances = [account.available, account.held, account.authorized].map(Decimal::from);
+            for balance in balances {
+                assert!(!balance.is_sign_negative(), "negative balance {balance}");
+            }
+
+            balances.into_iter().sum::<Decimal>()
+        })
+        .sum()
+}
+
+fuzz_target!(|data: &[u8]| {
+    let mut reader = csv::ReaderBuilder::new().trim(Trim::All).from_reader(data);
+    // Timestamped rows also go through the dispute window and authorization expiry.
+    let mut processor = PaymentProcessor::new()
+        .with_dispute_window(30 * DAY)
+        .with_authorization_expiry(7 * DAY);
+
+    for row in reader.deserialize::<TransactionRow>().flatten() {
+        let client = row.client;
+        let Ok(transaction) = Transaction::try_from(row) else {
+            continue;
+        };
+
+        let before = total_funds(&processor);
+        let accepted = processor.process(client, transaction).is_ok();
+        let after = total_funds(&processor);
+
+        match (accepted, transaction.kind) {
+            (true, TransactionKind::Deposit(amount)) => {
+                assert_eq!(after - before, Decimal::from(amount))
+            }
+            (true, TransactionKind::Withdrawal(amount)) => {
+                assert_eq!(before - after, Decimal::from(amount))
+            }
+            // Money only leaves the accounts through these transactions.
+            (
+                true,
+                TransactionKind::Refund(_)
+                | TransactionKind::Capture(_)
+                | TransactionKind::Chargeback,
+            ) => assert!(after <= before),
+            _ => assert_eq!(before, after),
+        }
+    }
+});
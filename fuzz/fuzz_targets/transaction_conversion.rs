@@ -0,0 +1,74 @@
+//! Structured rows converted to transactions. Accepted amounts must be positive with at
+//! most 4 decimal places, whatever the row contains.
+
+#![no_main]
+
+use arbitrary::Arbitrary;
+use kithmonite::{
+    account::{MonetaryValue, Transaction, TransactionKind},
+    cli::{self, TransactionRow},
+};
+use libfuzzer_sys::fuzz_target;
+use rust_decimal::Decimal;
+
+const KINDS: [cli::TransactionKind; 10] = [
+    cli::TransactionKind::Deposit,
+    cli::TransactionKind::Withdrawal,
+    cli::TransactionKind::Dispute,
+    cli::TransactionKind::Resolve,
+    cli::TransactionKind::Chargeback,
+    cli::TransactionKind::Transfer,
+    cli::TransactionKind::Refund,
+    cli::TransactionKind::Authorize,
+    cli::TransactionKind::Capture,
+    cli::TransactionKind::Void,
+];
+
+#[derive(Arbitrary, Debug)]
+struct Row {
+    kind: u8,
+    /// Mantissa and scale of the amount.
+    amount: Option<(i64, u8)>,
+    client: u16,
+    tx: u32,
+    timestamp: Option<u64>,
+    counterparty: Option<u16>,
+}
+
+fn check(amount: MonetaryValue) {
+    let amount = Decimal::from(amount);
+    assert!(!amount.is_sign_negative(), "negative amount {amount}");
+    assert!(
+        amount.scale() <= 4,
+        "amount {amount} has more than 4 decimal places"
+    );
+}
+
+fuzz_target!(|row: Row| {
+    let row = TransactionRow {
+        r#type: KINDS[usize::from(row.kind) % KINDS.len()],
+        // Decimals have at most 28 decimal places.
+        amount: row
+            .amount
+            .map(|(mantissa, scale)| Decimal::new(mantissa, u32::from(scale % 29))),
+        client: row.client,
+        tx: row.tx,
+        timestamp: row.timestamp,
+        counterparty: row.counterparty,
+    };
+
+    let Ok(transaction) = Transaction::try_from(row) else {
+        return;
+    };
+
+    match transaction.kind {
+        TransactionKind::Deposit(amount)
+        | TransactionKind::Withdrawal(amount)
+        | TransactionKind::Authorize(amount)
+        | TransactionKind::Transfer { amount, .. } => check(amount),
+        TransactionKind::Dispute(amount)
+        | TransactionKind::Refund(amount)
+        | TransactionKind::Capture(amount) => amount.into_iter().for_each(check),
+        TransactionKind::Resolve | TransactionKind::Chargeback | TransactionKind::Void => {}
+    }
+});
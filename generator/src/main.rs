@@ -1,115 +1,507 @@
+mod oracle;
+mod profile;
+
+use std::path::{Path, PathBuf};
+
+use anyhow::{bail, Context, Result};
 use clap::Parser;
 use kithmonite::cli::{TransactionKind, TransactionRow};
+use oracle::Oracle;
+use profile::{AmountDistribution, KindMix, KindSampler, Profile};
 use rand::prelude::*;
-use rust_decimal::Decimal;
+use rust_decimal::{prelude::ToPrimitive, Decimal};
 
-/// Random test transaction history generator. Data correctness is not guaranteed.
-/// This serves as a chaos generator to optimize system load through benchmarks.
+/// Random test transaction history generator. Transactions reference the real history
+/// of their client, so that disputes, refunds and card payments are exercised.
 #[derive(Parser)]
 #[clap(author, version, about, long_about = None)]
 struct Args {
     /// Number of rows to generate.
     #[clap(short, long, default_value_t = 1_000_000)]
     rows: u64,
+    /// Scenario setting the defaults of the options below.
+    #[clap(long, arg_enum, default_value = "uniform")]
+    profile: Profile,
+    /// Number of clients, up to 65536.
+    #[clap(long)]
+    clients: Option<u32>,
+    /// Relative weights of the kinds of transactions, such as `deposit=4,dispute=1`.
+    /// Kinds that are not listed are never generated.
+    #[clap(long)]
+    mix: Option<KindMix>,
+    /// How amounts are spread up to the maximum amount.
+    #[clap(long, arg_enum)]
+    amounts: Option<AmountDistribution>,
+    /// Largest generated amount.
+    #[clap(long)]
+    max_amount: Option<Decimal>,
+    /// Share of rows that the engine must reject, such as missing or negative amounts
+    /// and references to unknown transactions.
+    #[clap(long, default_value_t = 0.0)]
+    invalid_rate: f64,
+    /// Pad fields with a leading space.
+    #[clap(long)]
+    padding: bool,
+    /// Seed of the random generator, to generate the same rows again.
+    #[clap(long)]
+    seed: Option<u64>,
+    /// Path of a file where the accounts the engine should output are written, as
+    /// computed by a model of the engine with its default options.
+    #[clap(long)]
+    expected_accounts: Option<PathBuf>,
+    /// Path of a file where the rows the engine should reject are written.
+    #[clap(long)]
+    expected_rejections: Option<PathBuf>,
 }
 
-/// Generates a transaction that can refer to past transactions for dispute resolution
-fn generate_random_transaction<'a>(
-    client_id: u16,
-    transaction_history: impl Iterator<Item = &'a TransactionRow>,
-) -> TransactionRow {
-    let rng = &mut rand::thread_rng();
-
-    use TransactionKind::*;
-    let random_transaction_type = [Deposit, Withdrawal, Dispute, Resolve, Chargeback]
-        .choose(rng)
-        .expect("cannot fail because slice is not empty");
-
-    match random_transaction_type {
-        Deposit => {
-            let transaction_id: u32 = rand::random();
-            let deposit_amount = Decimal::new(rand::random::<i64>(), 5).round_dp(4);
-
-            TransactionRow {
-                r#type: Deposit,
-                amount: Some(deposit_amount),
-                client: client_id,
-                tx: transaction_id,
-            }
+/// Columns of the generated file, in the order of `record`.
+const HEADER: [&str; 6] = [
+    "type",
+    "amount",
+    "client",
+    "tx",
+    "timestamp",
+    "counterparty",
+];
+
+fn record(row: &TransactionRow) -> [String; 6] {
+    let optional = |value: Option<String>| value.unwrap_or_default();
+
+    [
+        row.r#type.to_string(),
+        optional(row.amount.map(|amount| amount.to_string())),
+        row.client.to_string(),
+        row.tx.to_string(),
+        optional(row.timestamp.map(|timestamp| timestamp.to_string())),
+        optional(row.counterparty.map(|client| client.to_string())),
+    ]
+}
+
+// ----------------------------------------------------------------------------
+
+/// Options of the generator, once the profile defaults are applied.
+struct Config {
+    mix: KindMix,
+    amounts: AmountDistribution,
+    /// In ten-thousandths.
+    max_amount: i64,
+    clients: u32,
+    hot_client_share: f64,
+    invalid_rate: f64,
+}
+
+impl TryFrom<&Args> for Config {
+    type Error = anyhow::Error;
+
+    fn try_from(args: &Args) -> Result<Self> {
+        let profile = args.profile;
+
+        let clients = args.clients.unwrap_or_else(|| profile.clients());
+        if !(1..=1 << u16::BITS).contains(&clients) {
+            bail!("the number of clients must be between 1 and 65536");
         }
-        Withdrawal => {
-            let transaction_id: u32 = rand::random();
-            let withdrawal_amount = Decimal::new(rand::random::<i64>(), 5).round_dp(4);
 
-            TransactionRow {
-                r#type: Withdrawal,
-                amount: Some(withdrawal_amount),
-                client: client_id,
-                tx: transaction_id,
-            }
+        let max_amount = (args.max_amount.unwrap_or_else(|| profile.max_amount())
+            * Decimal::new(10_000, 0))
+        .trunc()
+        .to_i64()
+        .filter(|max| *max >= 1)
+        .context("the maximum amount must be at least 0.0001")?;
+
+        if !(0.0..=1.0).contains(&args.invalid_rate) {
+            bail!("the invalid row rate must be between 0 and 1");
         }
-        Dispute => {
-            let disputed_transaction_id = transaction_history
-                .filter(|tx| matches!(tx.r#type, Deposit))
-                .map(|tx| tx.tx)
-                .choose(rng)
-                .unwrap_or_default();
-
-            TransactionRow {
-                r#type: Dispute,
-                amount: None,
-                client: client_id,
-                tx: disputed_transaction_id,
-            }
+
+        Ok(Self {
+            mix: args.mix.unwrap_or_else(|| profile.mix()),
+            amounts: args.amounts.unwrap_or_else(|| profile.amounts()),
+            max_amount,
+            clients,
+            hot_client_share: profile.hot_client_share(),
+            invalid_rate: args.invalid_rate,
+        })
+    }
+}
+
+#[derive(Default)]
+/// What a client did so far that later transactions can refer to.
+struct ClientHistory {
+    /// Deposits that are not under dispute, charged back nor refunded.
+    deposits: Vec<u32>,
+    /// Deposits under an open dispute.
+    disputes: Vec<u32>,
+    /// Authorizations that were neither captured nor voided.
+    authorizations: Vec<u32>,
+    /// Whether a chargeback locked the account.
+    locked: bool,
+}
+
+/// Picks a random element of the list, along with its position.
+fn pick_random(ids: &[u32], rng: &mut impl Rng) -> Option<(usize, u32)> {
+    if ids.is_empty() {
+        return None;
+    }
+
+    let index = rng.gen_range(0..ids.len());
+    Some((index, ids[index]))
+}
+
+// ----------------------------------------------------------------------------
+
+/// Generates transactions for clients picked at random, keeping track of their history
+/// so that every reference points to a transaction that can still be referenced, and
+/// of their balances so that every valid row is accepted by the engine.
+struct Generator<R: Rng> {
+    rng: R,
+    config: Config,
+    kinds: KindSampler,
+    clients: Vec<ClientHistory>,
+    /// Clients whose account is not locked.
+    active_clients: Vec<u16>,
+    /// Accounts as the engine sees them after the valid rows generated so far.
+    model: Oracle,
+    next_tx: u32,
+}
+
+impl<R: Rng> Generator<R> {
+    fn new(rng: R, config: Config) -> Self {
+        let last_client = (config.clients - 1) as u16;
+
+        Self {
+            rng,
+            kinds: config.mix.sampler(),
+            clients: (0..=last_client)
+                .map(|_| ClientHistory::default())
+                .collect(),
+            active_clients: (0..=last_client).collect(),
+            model: Oracle::default(),
+            next_tx: 0,
+            config,
         }
-        Resolve => {
-            let disputed_transaction_id = transaction_history
-                .filter(|tx| matches!(tx.r#type, Dispute))
-                .map(|tx| tx.tx)
-                .choose(rng)
-                .unwrap_or_default();
-
-            TransactionRow {
-                r#type: Resolve,
-                amount: None,
-                client: client_id,
-                tx: disputed_transaction_id,
-            }
+    }
+
+    /// Allocates a transaction id that was never used before.
+    fn allocate_tx(&mut self) -> u32 {
+        let tx = self.next_tx;
+        self.next_tx = self
+            .next_tx
+            .checked_add(1)
+            .expect("transaction ids are exhausted");
+        tx
+    }
+
+    /// Amounts are written without trailing zeros, which the engine drops when parsing
+    /// them, so that expected balances are displayed the same way as its own.
+    fn random_amount(&mut self) -> Decimal {
+        self.config
+            .amounts
+            .sample(self.config.max_amount, &mut self.rng)
+            .normalize()
+    }
+
+    /// Picks the first client for its share of the transactions, and any active client
+    /// otherwise.
+    fn random_client(&mut self) -> Option<u16> {
+        if !self.clients[0].locked && self.rng.gen_bool(self.config.hot_client_share) {
+            return Some(0);
+        }
+
+        self.active_clients.choose(&mut self.rng).copied()
+    }
+
+    /// Picks any client other than the given one, locked or not.
+    fn other_client(&mut self, client: u16) -> u16 {
+        let clients = self.config.clients;
+        if clients == 1 {
+            return client;
+        }
+
+        let offset = self.rng.gen_range(1..clients);
+        ((u32::from(client) + offset) % clients) as u16
+    }
+
+    /// Generates the next transaction, or nothing once every account got locked.
+    fn next_row(&mut self) -> Option<TransactionRow> {
+        let client = self.random_client()?;
+
+        if self.rng.gen_bool(self.config.invalid_rate) {
+            return Some(self.invalid_row(client));
         }
-        Chargeback => {
-            let disputed_transaction_id = transaction_history
-                .filter(|tx| matches!(tx.r#type, Chargeback))
-                .map(|tx| tx.tx)
-                .choose(rng)
-                .unwrap_or_default();
-
-            TransactionRow {
-                r#type: Chargeback,
-                amount: None,
-                client: client_id,
-                tx: disputed_transaction_id,
+
+        Some(self.valid_row(client))
+    }
+
+    /// Generates a transaction that the engine accepts. Transactions that have nothing
+    /// to reference, or that the account cannot afford, fall back to a deposit.
+    fn valid_row(&mut self, client: u16) -> TransactionRow {
+        use TransactionKind::*;
+
+        let kind = self.kinds.sample(&mut self.rng);
+        let history = &self.clients[usize::from(client)];
+        let references = match kind {
+            Dispute | Refund => &history.deposits[..],
+            Resolve | Chargeback => &history.disputes[..],
+            Capture | Void => &history.authorizations[..],
+            Deposit | Withdrawal | Transfer | Authorize => &[],
+        };
+        let reference = pick_random(references, &mut self.rng);
+
+        let row = match (kind, reference) {
+            (Deposit, _) => Some(self.deposit(client)),
+            (Withdrawal | Transfer | Authorize, _) => self.debit(kind, client),
+            (_, Some((_, tx))) => Some(row(kind, client, tx, None, None)),
+            (_, None) => None,
+        };
+
+        let Some(row) = row.filter(|row| self.model.try_accept(row)) else {
+            let row = self.deposit(client);
+            assert!(
+                self.model.try_accept(&row),
+                "deposits of active clients are accepted"
+            );
+            self.clients[usize::from(client)].deposits.push(row.tx);
+            return row;
+        };
+
+        let history = &mut self.clients[usize::from(client)];
+        let index = reference.map_or(0, |(index, _)| index);
+        match kind {
+            Deposit => history.deposits.push(row.tx),
+            Authorize => history.authorizations.push(row.tx),
+            Dispute => {
+                history.deposits.swap_remove(index);
+                history.disputes.push(row.tx);
+            }
+            // A resolved deposit can be disputed again.
+            Resolve => {
+                history.disputes.swap_remove(index);
+                history.deposits.push(row.tx);
+            }
+            Chargeback => {
+                history.disputes.swap_remove(index);
+                history.locked = true;
+                self.active_clients.retain(|active| *active != client);
+            }
+            Refund => {
+                history.deposits.swap_remove(index);
             }
+            Capture | Void => {
+                history.authorizations.swap_remove(index);
+            }
+            Withdrawal | Transfer => {}
         }
+
+        row
+    }
+
+    fn deposit(&mut self, client: u16) -> TransactionRow {
+        let tx = self.allocate_tx();
+        let amount = self.random_amount();
+        row(TransactionKind::Deposit, client, tx, Some(amount), None)
+    }
+
+    /// Generates a transaction taking at most the available funds of the client, or
+    /// nothing if it has none.
+    fn debit(&mut self, kind: TransactionKind, client: u16) -> Option<TransactionRow> {
+        let available = self.model.available(client);
+        if available.is_zero() {
+            return None;
+        }
+
+        let tx = self.allocate_tx();
+        let amount = self.random_amount().min(available);
+        let counterparty =
+            matches!(kind, TransactionKind::Transfer).then(|| self.other_client(client));
+        Some(row(kind, client, tx, Some(amount), counterparty))
+    }
+
+    /// Generates a transaction that is rejected whatever the state of the account,
+    /// without changing the history.
+    fn invalid_row(&mut self, client: u16) -> TransactionRow {
+        use TransactionKind::*;
+
+        let tx = self.allocate_tx();
+        let (kind, amount, counterparty) = match self.rng.gen_range(0..5) {
+            0 => (Deposit, None, None),
+            1 => (Withdrawal, Some(-self.random_amount()), None),
+            // Freshly allocated ids reference nothing.
+            2 => (Dispute, None, None),
+            3 => (Capture, None, None),
+            _ => (Transfer, Some(self.random_amount()), Some(client)),
+        };
+
+        row(kind, client, tx, amount, counterparty)
+    }
+}
+
+fn row(
+    kind: TransactionKind,
+    client: u16,
+    tx: u32,
+    amount: Option<Decimal>,
+    counterparty: Option<u16>,
+) -> TransactionRow {
+    TransactionRow {
+        r#type: kind,
+        amount,
+        client,
+        tx,
+        timestamp: None,
+        counterparty,
     }
 }
 
-fn main() {
+fn main() -> Result<()> {
     let args = Args::parse();
+    let config = Config::try_from(&args)?;
+
+    let rng = match args.seed {
+        Some(seed) => StdRng::seed_from_u64(seed),
+        None => StdRng::from_entropy(),
+    };
+    let mut generator = Generator::new(rng, config);
+    let mut writer = csv::WriterBuilder::new()
+        .has_headers(false)
+        .from_writer(std::io::stdout());
+
+    let pad = |fields: &mut [String]| {
+        if args.padding {
+            for field in fields.iter_mut().skip(1) {
+                field.insert(0, ' ');
+            }
+        }
+    };
+
+    let mut header = HEADER.map(str::to_owned);
+    pad(&mut header);
+    writer
+        .write_record(&header)
+        .context("unable to write header")?;
+
+    let mut oracle = (args.expected_accounts.is_some() || args.expected_rejections.is_some())
+        .then(Oracle::default);
 
-    let client_ids = 0..u16::MAX;
-    let transactions_per_client = args.rows / u16::MAX as u64;
+    for _ in 0..args.rows {
+        let Some(row) = generator.next_row() else {
+            break;
+        };
 
-    let mut current_client_transactions_buf: Vec<TransactionRow> =
-        Vec::with_capacity(transactions_per_client as usize);
-    let mut writer = csv::Writer::from_writer(std::io::stdout());
+        if let Some(oracle) = &mut oracle {
+            oracle.apply(&row);
+        }
+
+        let mut fields = record(&row);
+        pad(&mut fields);
+        writer
+            .write_record(&fields)
+            .context("unable to write record")?;
+    }
+
+    writer.flush().context("unable to write records")?;
+
+    if let Some(oracle) = &oracle {
+        if let Some(path) = &args.expected_accounts {
+            write_csv(path, oracle.accounts()).context("unable to write expected accounts")?;
+        }
+
+        if let Some(path) = &args.expected_rejections {
+            write_csv(path, oracle.rejections()).context("unable to write expected rejections")?;
+        }
+    }
+
+    Ok(())
+}
+
+fn write_csv<T: serde::Serialize>(path: &Path, records: impl IntoIterator<Item = T>) -> Result<()> {
+    let mut writer = csv::Writer::from_path(path)?;
+    for record in records {
+        writer.serialize(record)?;
+    }
+    writer.flush()?;
 
-    for client_id in client_ids {
-        current_client_transactions_buf.clear();
+    Ok(())
+}
+
+// ----------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use csv::Trim;
+    use kithmonite::{
+        cli::{self, AmountPolicy},
+        processor::PaymentProcessor,
+        schema::ColumnMapping,
+    };
+    use rand::prelude::*;
+
+    use super::{record, Config, Generator, HEADER};
+    use crate::profile::Profile;
+
+    /// Writes a file of valid rows and feeds it to the engine the way the binary does,
+    /// which must accept every row.
+    fn check_valid_rows(profile: Profile) {
+        let config = Config {
+            mix: profile.mix(),
+            amounts: profile.amounts(),
+            max_amount: 1_000 * 10_000,
+            clients: 50,
+            hot_client_share: profile.hot_client_share(),
+            invalid_rate: 0.0,
+        };
+        let mut generator = Generator::new(StdRng::seed_from_u64(1), config);
+
+        let mut writer = csv::Writer::from_writer(Vec::new());
+        writer
+            .write_record(HEADER)
+            .expect("the header should be written");
+        for _ in 0..20_000 {
+            let Some(row) = generator.next_row() else {
+                break;
+            };
+            writer
+                .write_record(record(&row))
+                .expect("the row should be written");
+        }
+        let file = writer.into_inner().expect("the rows should be written");
+
+        let mut reader = csv::ReaderBuilder::new()
+            .trim(Trim::All)
+            .from_reader(file.as_slice());
+        let header = reader.headers().expect("the header should be read").clone();
+        let mapper = ColumnMapping::default()
+            .resolve(&header)
+            .expect("the columns should be recognized");
+        let mut processor = PaymentProcessor::new();
+
+        for (index, record) in reader.records().enumerate() {
+            let row = mapper
+                .deserialize(&record.expect("the row should be read"))
+                .expect("the row should be parsed");
+            let result = cli::apply_with_policy(&mut processor, row, AmountPolicy::Reject);
+            assert!(
+                result.accepted,
+                "{profile:?}: row {} was rejected: {:?}",
+                index + 1,
+                result.error
+            );
+        }
+
+        for account in processor.account_logs() {
+            let account = account.expect("memory storage cannot fail");
+            assert_eq!(account.rejected(), 0);
+        }
+    }
 
-        for _ in 0..transactions_per_client {
-            let row =
-                generate_random_transaction(client_id, current_client_transactions_buf.iter());
-            writer.serialize(&row).expect("unable to serialize record")
+    #[test]
+    fn engine_accepts_valid_rows() {
+        for profile in [
+            Profile::Uniform,
+            Profile::Retail,
+            Profile::FraudHeavy,
+            Profile::DisputeStorm,
+            Profile::SingleHotClient,
+        ] {
+            check_valid_rows(profile);
         }
     }
 }
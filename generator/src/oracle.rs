@@ -0,0 +1,360 @@
+use std::collections::{BTreeMap, HashMap};
+
+use kithmonite::cli::{Output, TransactionKind, TransactionRow};
+use rust_decimal::Decimal;
+use serde::Serialize;
+
+/// Largest amount the engine can process, as it keeps amounts in 61 bits at a scale of 4.
+const MAX_AMOUNT: Decimal = Decimal::from_parts(0xFFFF_FFFF, 0x1FFF_FFFF, 0, false, 4);
+
+#[derive(Default)]
+struct ModelAccount {
+    available: Decimal,
+    held: Decimal,
+    authorized: Decimal,
+    locked: bool,
+}
+
+struct ModelDeposit {
+    /// Part of the deposit that is neither under dispute, charged back nor refunded.
+    remaining: Decimal,
+    /// Amount under an open dispute.
+    disputed: Option<Decimal>,
+}
+
+#[derive(Serialize)]
+/// A row the engine is expected to reject.
+pub struct Rejection {
+    /// Position of the row in the generated file, starting at 1 after the header.
+    pub row: u64,
+    pub r#type: TransactionKind,
+    pub client: u16,
+    pub tx: u32,
+}
+
+/// Why a row is rejected. The model only needs to know whether it is.
+struct Rejected;
+
+type ModelResult<T = ()> = Result<T, Rejected>;
+
+/// Subtracts `rhs` from a balance, which must not become negative.
+fn debit(balance: &mut Decimal, rhs: Decimal) -> ModelResult {
+    if *balance < rhs {
+        return Err(Rejected);
+    }
+
+    *balance -= rhs;
+    Ok(())
+}
+
+/// Validates an amount the way the engine parses it: negative amounts are rejected,
+/// and amounts with more than 4 decimal places are rounded.
+fn amount(amount: Option<Decimal>) -> ModelResult<Option<Decimal>> {
+    match amount {
+        Some(amount) if amount.is_sign_negative() || amount.round_dp(4) > MAX_AMOUNT => {
+            Err(Rejected)
+        }
+        Some(amount) if amount.scale() > 4 => Ok(Some(amount.round_dp(4))),
+        amount => Ok(amount),
+    }
+}
+
+fn required(amount: Option<Decimal>) -> ModelResult<Decimal> {
+    amount.ok_or(Rejected)
+}
+
+// ----------------------------------------------------------------------------
+
+#[derive(Default)]
+/// Expected outcome of the generated rows, computed by a model of the engine that is
+/// written independently from `PaymentProcessor`. The model matches a processor with
+/// the default options: no dispute window, authorization expiry or deposit retention.
+pub struct Oracle {
+    accounts: BTreeMap<u16, ModelAccount>,
+    deposits: HashMap<(u16, u32), ModelDeposit>,
+    authorizations: HashMap<(u16, u32), Decimal>,
+    rows: u64,
+    rejections: Vec<Rejection>,
+}
+
+impl Oracle {
+    /// Applies the next row of the generated file.
+    pub fn apply(&mut self, row: &TransactionRow) {
+        self.rows += 1;
+
+        if self.try_apply(row).is_err() {
+            self.rejections.push(Rejection {
+                row: self.rows,
+                r#type: row.r#type,
+                client: row.client,
+                tx: row.tx,
+            });
+        }
+    }
+
+    /// Applies a row if the engine would accept it, without recording it as a row of
+    /// the file. Like in the engine, a rejected row only opens the account of its client.
+    /// Returns whether the row was accepted.
+    pub fn try_accept(&mut self, row: &TransactionRow) -> bool {
+        self.try_apply(row).is_ok()
+    }
+
+    /// Funds of a client that are neither held nor authorized.
+    pub fn available(&self, client: u16) -> Decimal {
+        self.accounts
+            .get(&client)
+            .map_or(Decimal::ZERO, |account| account.available)
+    }
+
+    /// Expected accounts, ordered by client.
+    pub fn accounts(&self) -> impl Iterator<Item = Output> + '_ {
+        self.accounts.iter().map(|(client, account)| Output {
+            client: *client,
+            available: account.available,
+            held: account.held,
+            authorized: account.authorized,
+            total: account.available + account.held + account.authorized,
+            locked: account.locked,
+            rejected: None,
+            rejected_amount: None,
+        })
+    }
+
+    /// Expected rejections, in the order of the rows.
+    pub fn rejections(&self) -> &[Rejection] {
+        &self.rejections
+    }
+
+    fn try_apply(&mut self, row: &TransactionRow) -> ModelResult {
+        use TransactionKind::*;
+
+        let client = row.client;
+        let key = (client, row.tx);
+        // Any row that can be parsed opens the account of its client, even if it is
+        // rejected.
+        self.accounts.entry(client).or_default();
+        let amount = match (row.r#type, row.amount) {
+            (Resolve | Chargeback | Void, Some(_)) => return Err(Rejected),
+            _ => amount(row.amount)?,
+        };
+        if matches!(row.r#type, Deposit | Withdrawal | Authorize | Transfer) {
+            required(amount)?;
+        }
+        if amount.is_some_and(|amount| amount.is_zero()) {
+            return Err(Rejected);
+        }
+
+        if let Transfer = row.r#type {
+            let recipient = row.counterparty.ok_or(Rejected)?;
+            return self.transfer(client, recipient, required(amount)?);
+        }
+
+        let account = self.accounts.entry(client).or_default();
+        let unlocked = |account: &ModelAccount| match account.locked {
+            true => Err(Rejected),
+            false => Ok(()),
+        };
+
+        match row.r#type {
+            Deposit => {
+                unlocked(account)?;
+                let amount = required(amount)?;
+                account.available += amount;
+                self.deposits.entry(key).or_insert(ModelDeposit {
+                    remaining: amount,
+                    disputed: None,
+                });
+            }
+            Withdrawal => {
+                unlocked(account)?;
+                debit(&mut account.available, required(amount)?)?;
+            }
+            Dispute => {
+                let deposit = self.deposits.get_mut(&key).ok_or(Rejected)?;
+                if deposit.disputed.is_some() {
+                    return Err(Rejected);
+                }
+
+                let disputed = amount.unwrap_or(deposit.remaining);
+                if disputed > deposit.remaining || (amount.is_none() && disputed.is_zero()) {
+                    return Err(Rejected);
+                }
+
+                unlocked(account)?;
+                debit(&mut account.available, disputed)?;
+                account.held += disputed;
+                deposit.remaining -= disputed;
+                deposit.disputed = Some(disputed);
+            }
+            Resolve => {
+                let deposit = self.deposits.get_mut(&key).ok_or(Rejected)?;
+                let disputed = deposit.disputed.ok_or(Rejected)?;
+
+                unlocked(account)?;
+                account.available += disputed;
+                account.held -= disputed;
+                deposit.remaining += disputed;
+                deposit.disputed = None;
+            }
+            Chargeback => {
+                // A chargeback goes through even if the account is already locked.
+                let deposit = self.deposits.get_mut(&key).ok_or(Rejected)?;
+                let disputed = deposit.disputed.take().ok_or(Rejected)?;
+
+                account.held -= disputed;
+                account.locked = true;
+            }
+            Refund => {
+                let deposit = self.deposits.get_mut(&key).ok_or(Rejected)?;
+                let refunded = amount.unwrap_or(deposit.remaining);
+                if refunded > deposit.remaining || (amount.is_none() && refunded.is_zero()) {
+                    return Err(Rejected);
+                }
+
+                unlocked(account)?;
+                debit(&mut account.available, refunded)?;
+                deposit.remaining -= refunded;
+            }
+            Authorize => {
+                if self.authorizations.contains_key(&key) {
+                    return Err(Rejected);
+                }
+
+                unlocked(account)?;
+                let amount = required(amount)?;
+                debit(&mut account.available, amount)?;
+                account.authorized += amount;
+                self.authorizations.insert(key, amount);
+            }
+            Capture => {
+                let authorized = *self.authorizations.get(&key).ok_or(Rejected)?;
+                let captured = amount.unwrap_or(authorized);
+                if captured > authorized {
+                    return Err(Rejected);
+                }
+
+                unlocked(account)?;
+                // Whatever is not captured goes back to the available funds.
+                let released = authorized - captured;
+                account.authorized -= captured;
+                account.authorized -= released;
+                account.available += released;
+                self.authorizations.remove(&key);
+            }
+            Void => {
+                // Voids are allowed on locked accounts as they never move money out.
+                let authorized = self.authorizations.remove(&key).ok_or(Rejected)?;
+                account.authorized -= authorized;
+                account.available += authorized;
+            }
+            Transfer => unreachable!("transfers are applied by `Oracle::transfer`"),
+        }
+
+        Ok(())
+    }
+
+    fn transfer(&mut self, sender: u16, recipient: u16, amount: Decimal) -> ModelResult {
+        if sender == recipient {
+            return Err(Rejected);
+        }
+
+        let sender_account = &self.accounts[&sender];
+        if sender_account.locked || sender_account.available < amount {
+            return Err(Rejected);
+        }
+
+        // The recipient account is only opened once it is credited.
+        if self
+            .accounts
+            .get(&recipient)
+            .is_some_and(|account| account.locked)
+        {
+            return Err(Rejected);
+        }
+
+        self.accounts
+            .get_mut(&sender)
+            .expect("the sender account is opened by the row")
+            .available -= amount;
+        self.accounts.entry(recipient).or_default().available += amount;
+
+        Ok(())
+    }
+}
+
+// ----------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+
+    use kithmonite::{
+        cli::{self, Output},
+        processor::PaymentProcessor,
+    };
+    use rand::prelude::*;
+
+    use super::Oracle;
+    use crate::{profile::Profile, Config, Generator};
+
+    /// Runs the generated rows through the engine and checks that it agrees with the
+    /// oracle on every account and every rejected row.
+    fn check_profile(profile: Profile, invalid_rate: f64) {
+        let config = Config {
+            mix: profile.mix(),
+            amounts: profile.amounts(),
+            max_amount: 1_000 * 10_000,
+            clients: 50,
+            hot_client_share: profile.hot_client_share(),
+            invalid_rate,
+        };
+        let mut generator = Generator::new(StdRng::seed_from_u64(7), config);
+        let mut oracle = Oracle::default();
+        let mut processor = PaymentProcessor::new();
+        let mut rejected_rows = Vec::new();
+
+        for row_number in 1..=20_000 {
+            let Some(row) = generator.next_row() else {
+                break;
+            };
+            oracle.apply(&row);
+
+            if !cli::apply(&mut processor, row).accepted {
+                rejected_rows.push(row_number);
+            }
+        }
+
+        let expected_rows: Vec<u64> = oracle
+            .rejections()
+            .iter()
+            .map(|rejection| rejection.row)
+            .collect();
+        assert_eq!(rejected_rows, expected_rows);
+
+        let accounts: BTreeMap<u16, Output> = processor
+            .accounts()
+            .map(|account| {
+                let output = Output::from(account.expect("memory storage cannot fail"));
+                (output.client, output)
+            })
+            .collect();
+        let expected: BTreeMap<u16, Output> = oracle
+            .accounts()
+            .map(|output| (output.client, output))
+            .collect();
+        assert_eq!(accounts, expected);
+    }
+
+    #[test]
+    fn engine_matches_oracle() {
+        for profile in [
+            Profile::Uniform,
+            Profile::Retail,
+            Profile::FraudHeavy,
+            Profile::DisputeStorm,
+            Profile::SingleHotClient,
+        ] {
+            check_profile(profile, 0.05);
+        }
+    }
+}
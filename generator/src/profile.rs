@@ -0,0 +1,185 @@
+use std::str::FromStr;
+
+use anyhow::{anyhow, bail, Context};
+use kithmonite::cli::TransactionKind;
+use rand::{distributions::WeightedIndex, prelude::*};
+use rust_decimal::Decimal;
+
+/// Every kind of transaction, in the order used by `KindMix`.
+const KINDS: [TransactionKind; 10] = [
+    TransactionKind::Deposit,
+    TransactionKind::Withdrawal,
+    TransactionKind::Dispute,
+    TransactionKind::Resolve,
+    TransactionKind::Chargeback,
+    TransactionKind::Transfer,
+    TransactionKind::Refund,
+    TransactionKind::Authorize,
+    TransactionKind::Capture,
+    TransactionKind::Void,
+];
+
+// ----------------------------------------------------------------------------
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+/// Relative weights of each kind of transaction, in the order of `KINDS`.
+pub struct KindMix([u32; KINDS.len()]);
+
+impl KindMix {
+    pub const UNIFORM: Self = Self([1; KINDS.len()]);
+
+    /// Builds a sampler of transaction kinds following the weights.
+    pub fn sampler(&self) -> KindSampler {
+        KindSampler(WeightedIndex::new(self.0).expect("mixes have a positive weight"))
+    }
+}
+
+/// Parses comma separated `kind=weight` pairs, such as `deposit=4,dispute=1`. Kinds
+/// that are not listed are never generated.
+impl FromStr for KindMix {
+    type Err = anyhow::Error;
+
+    fn from_str(mix: &str) -> Result<Self, Self::Err> {
+        let mut weights = [0; KINDS.len()];
+
+        for pair in mix.split(',') {
+            let (name, weight) = pair
+                .split_once('=')
+                .ok_or_else(|| anyhow!("expected kind=weight, got {pair:?}"))?;
+            let index = KINDS
+                .iter()
+                .position(|kind| kind.to_string() == name.trim())
+                .ok_or_else(|| anyhow!("unknown transaction kind {name:?}"))?;
+
+            weights[index] = weight
+                .trim()
+                .parse()
+                .with_context(|| format!("invalid weight for {name}"))?;
+        }
+
+        if weights.iter().all(|weight| *weight == 0) {
+            bail!("at least one kind must have a positive weight");
+        }
+
+        Ok(Self(weights))
+    }
+}
+
+pub struct KindSampler(WeightedIndex<u32>);
+
+impl Distribution<TransactionKind> for KindSampler {
+    fn sample<R: Rng + ?Sized>(&self, rng: &mut R) -> TransactionKind {
+        KINDS[self.0.sample(rng)]
+    }
+}
+
+// ----------------------------------------------------------------------------
+
+#[derive(clap::ArgEnum, Debug, Clone, Copy, PartialEq, Eq)]
+/// How amounts are spread between the smallest amount and the maximum.
+pub enum AmountDistribution {
+    /// Every amount is as likely.
+    Uniform,
+    /// Each order of magnitude is as likely, so most amounts are small.
+    LogUniform,
+}
+
+impl AmountDistribution {
+    /// Samples an amount of at most `max` ten-thousandths, and at least one.
+    pub fn sample(self, max: i64, rng: &mut impl Rng) -> Decimal {
+        let mantissa = match self {
+            Self::Uniform => rng.gen_range(1..=max),
+            Self::LogUniform => {
+                let exponent = rng.gen_range(0.0..=(max as f64).ln());
+                (exponent.exp() as i64).clamp(1, max)
+            }
+        };
+
+        Decimal::new(mantissa, 4)
+    }
+}
+
+// ----------------------------------------------------------------------------
+
+#[derive(clap::ArgEnum, Debug, Clone, Copy, PartialEq, Eq)]
+/// Named scenarios, setting the defaults of the other options.
+pub enum Profile {
+    /// Every kind of transaction is as likely, with amounts up to 10000.
+    Uniform,
+    /// Mostly deposits, withdrawals and card payments of small amounts, with rare
+    /// disputes.
+    Retail,
+    /// Frequent disputes ending in chargebacks, and withdrawals draining accounts.
+    FraudHeavy,
+    /// Few clients disputing most of their deposits.
+    DisputeStorm,
+    /// Retail traffic where one client makes nine transactions out of ten.
+    SingleHotClient,
+}
+
+impl Profile {
+    /// Weights of deposit, withdrawal, dispute, resolve, chargeback, transfer, refund,
+    /// authorize, capture and void.
+    pub fn mix(self) -> KindMix {
+        match self {
+            Self::Uniform => KindMix::UNIFORM,
+            Self::Retail => KindMix([300, 200, 10, 8, 2, 50, 20, 150, 120, 20]),
+            Self::FraudHeavy => KindMix([30, 25, 15, 5, 10, 10, 5, 0, 0, 0]),
+            Self::DisputeStorm => KindMix([30, 10, 35, 20, 5, 0, 0, 0, 0, 0]),
+            Self::SingleHotClient => KindMix([300, 200, 10, 10, 0, 50, 20, 150, 120, 20]),
+        }
+    }
+
+    pub fn amounts(self) -> AmountDistribution {
+        match self {
+            Self::Uniform | Self::DisputeStorm => AmountDistribution::Uniform,
+            Self::Retail | Self::FraudHeavy | Self::SingleHotClient => {
+                AmountDistribution::LogUniform
+            }
+        }
+    }
+
+    pub fn max_amount(self) -> Decimal {
+        match self {
+            Self::Uniform | Self::FraudHeavy => Decimal::new(10_000, 0),
+            Self::Retail | Self::SingleHotClient => Decimal::new(500, 0),
+            Self::DisputeStorm => Decimal::new(1_000, 0),
+        }
+    }
+
+    pub fn clients(self) -> u32 {
+        match self {
+            Self::DisputeStorm => 1_000,
+            _ => 1 << u16::BITS,
+        }
+    }
+
+    /// Share of the transactions made by the first client.
+    pub fn hot_client_share(self) -> f64 {
+        match self {
+            Self::SingleHotClient => 0.9,
+            _ => 0.0,
+        }
+    }
+}
+
+// ----------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::{KindMix, KINDS};
+
+    #[test]
+    fn parse_kind_mix() {
+        let mix: KindMix = "deposit=4, dispute = 1".parse().expect("mix is valid");
+        let mut expected = [0; KINDS.len()];
+        expected[0] = 4;
+        expected[2] = 1;
+        assert_eq!(mix, KindMix(expected));
+
+        assert!("deposit".parse::<KindMix>().is_err());
+        assert!("deposit=-1".parse::<KindMix>().is_err());
+        assert!("loan=1".parse::<KindMix>().is_err());
+        assert!("deposit=0".parse::<KindMix>().is_err());
+    }
+}
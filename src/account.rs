@@ -1,12 +1,16 @@
-use anyhow::{Context, Error as AnyError};
+use std::time::Duration;
+
 use rust_decimal::Decimal;
 use serde::{Deserialize, Serialize};
 
-use crate::cli::TransactionRow;
+use crate::{
+    cli::{self, TransactionRow},
+    error::ErrorCode,
+};
 
 // ----------------------------------------------------------------------------
 
-#[derive(Debug, Default, PartialEq, Eq, Hash, PartialOrd, Clone, Copy)]
+#[derive(Debug, Default, PartialEq, Eq, Hash, PartialOrd, Clone, Copy, Serialize, Deserialize)]
 pub struct ClientId(u16);
 
 impl From<u16> for ClientId {
@@ -23,7 +27,7 @@ impl From<ClientId> for u16 {
 
 // ----------------------------------------------------------------------------
 
-#[derive(Debug, Default, PartialEq)]
+#[derive(Debug, Default, PartialEq, Eq, Hash, Clone, Copy, Serialize, Deserialize)]
 pub struct TransactionId(u32);
 
 impl From<u32> for TransactionId {
@@ -32,6 +36,44 @@ impl From<u32> for TransactionId {
     }
 }
 
+impl From<TransactionId> for u32 {
+    fn from(id: TransactionId) -> Self {
+        id.0
+    }
+}
+
+// ----------------------------------------------------------------------------
+
+const SECONDS_PER_DAY: u64 = 24 * 60 * 60;
+
+#[derive(Debug, Default, PartialEq, Eq, PartialOrd, Ord, Clone, Copy, Serialize, Deserialize)]
+/// A point in time, expressed in seconds since the Unix epoch.
+pub struct Timestamp(u64);
+
+impl Timestamp {
+    /// Returns the number of days since the Unix epoch, in UTC.
+    pub fn day(self) -> u64 {
+        self.0 / SECONDS_PER_DAY
+    }
+
+    /// Returns the time elapsed since `earlier`, or `None` if `earlier` is in the future.
+    pub fn duration_since(self, earlier: Timestamp) -> Option<Duration> {
+        self.0.checked_sub(earlier.0).map(Duration::from_secs)
+    }
+}
+
+impl From<u64> for Timestamp {
+    fn from(value: u64) -> Self {
+        Self(value)
+    }
+}
+
+impl From<Timestamp> for u64 {
+    fn from(timestamp: Timestamp) -> Self {
+        timestamp.0
+    }
+}
+
 // ----------------------------------------------------------------------------
 
 #[derive(thiserror::Error, Debug)]
@@ -40,13 +82,38 @@ pub enum TransactionError {
     NegativeBalance(Decimal),
     #[error("account is locked")]
     AccountLocked,
-    #[error(transparent)]
-    Other(#[from] AnyError),
+    #[error("a {0} transaction should not contain an amount")]
+    UnexpectedAmount(cli::TransactionKind),
+    #[error("a {0} transaction should contain a positive amount")]
+    ZeroAmount(cli::TransactionKind),
+    #[error("a {0} transaction should contain an amount")]
+    MissingAmount(cli::TransactionKind),
+    #[error("a {0} transaction cannot have a negative amount")]
+    NegativeAmount(cli::TransactionKind),
+    #[error("a transfer transaction should contain a counterparty")]
+    MissingCounterparty,
+    #[error("a dispute cannot exceed what remains of the deposit: {0}")]
+    DisputeExceedsDeposit(Decimal),
+}
+
+impl TransactionError {
+    pub fn code(&self) -> ErrorCode {
+        match self {
+            Self::NegativeBalance(_) => ErrorCode::InsufficientFunds,
+            Self::AccountLocked => ErrorCode::AccountLocked,
+            Self::UnexpectedAmount(_) => ErrorCode::UnexpectedAmount,
+            Self::ZeroAmount(_) => ErrorCode::ZeroAmount,
+            Self::MissingAmount(_) => ErrorCode::MissingAmount,
+            Self::NegativeAmount(_) => ErrorCode::NegativeAmount,
+            Self::MissingCounterparty => ErrorCode::MissingCounterparty,
+            Self::DisputeExceedsDeposit(_) => ErrorCode::DisputeExceedsDeposit,
+        }
+    }
 }
 
 // ----------------------------------------------------------------------------
 
-#[derive(Debug, Default, PartialEq, Eq, PartialOrd, Serialize, Clone, Copy)]
+#[derive(Debug, Default, PartialEq, Eq, PartialOrd, Clone, Copy)]
 /// Represents an arbitrary, positive amount of money.
 /// This type strives to be as restrictive as possible to avoid potential errors.
 pub struct MonetaryValue(Decimal);
@@ -61,6 +128,11 @@ impl MonetaryValue {
         Ok(Self(self.0 + rhs.0))
     }
 
+    /// Calculates self + rhs, bounded by the largest representable value
+    pub fn saturating_add(self, rhs: MonetaryValue) -> MonetaryValue {
+        Self(self.0.saturating_add(rhs.0))
+    }
+
     /// Calculates self - rhs, returning an error if there's an overdraft
     pub fn overdrawing_sub(self, rhs: MonetaryValue) -> Result<MonetaryValue, TransactionError> {
         if self < rhs {
@@ -79,7 +151,13 @@ impl TryFrom<Decimal> for MonetaryValue {
             return Err(TransactionError::NegativeBalance(value));
         }
 
-        Ok(Self(value.round_dp(4)))
+        // Rounding zero would give it a scale of 4 even if it had none, which would
+        // change how restored values are displayed.
+        if value.scale() > 4 {
+            return Ok(Self(value.round_dp(4)));
+        }
+
+        Ok(Self(value))
     }
 }
 
@@ -89,22 +167,43 @@ impl From<MonetaryValue> for Decimal {
     }
 }
 
+/// Human-readable formats get a decimal string, binary formats the 16 bytes of the
+/// underlying `Decimal`.
+impl Serialize for MonetaryValue {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        if serializer.is_human_readable() {
+            Serialize::serialize(&self.0, serializer)
+        } else {
+            Serialize::serialize(&self.0.serialize(), serializer)
+        }
+    }
+}
+
 impl<'de> Deserialize<'de> for MonetaryValue {
     fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
     where
         D: serde::Deserializer<'de>,
     {
-        let decimal: Decimal = Deserialize::deserialize(deserializer)?;
-        decimal
-            .try_into()
-            .context("unable to convert a `Decimal` to a monetary value")
-            .map_err(serde::de::Error::custom)
+        let decimal = if deserializer.is_human_readable() {
+            <Decimal as Deserialize>::deserialize(deserializer)?
+        } else {
+            Decimal::deserialize(<[u8; 16]>::deserialize(deserializer)?)
+        };
+
+        decimal.try_into().map_err(|error| {
+            serde::de::Error::custom(format!(
+                "unable to convert a `Decimal` to a monetary value: {error}"
+            ))
+        })
     }
 }
 
 // ----------------------------------------------------------------------------
 
-#[derive(PartialEq, Clone, Copy)]
+#[derive(Debug, PartialEq, Clone, Copy, Serialize, Deserialize)]
 /// Any type of transaction that can happen within the system.
 pub enum TransactionKind {
     /// A credit to the client's asset account.
@@ -112,18 +211,51 @@ pub enum TransactionKind {
     /// A debit to the client's asset account.
     Withdrawal(MonetaryValue),
     /// A client's claim that a transaction was erroneous and should be reversed.
-    Dispute,
+    /// Only the given amount is disputed when set, the whole transaction otherwise.
+    Dispute(Option<MonetaryValue>),
     /// A resolution to a dispute, releasing the associated held funds.
     Resolve,
     /// The final state of a dispute that represents the client reversing a transaction.
     Chargeback,
+    /// A debit to the client's asset account credited to another client's account.
+    /// Transfers happen between our own clients and cannot be disputed.
+    Transfer {
+        recipient: ClientId,
+        amount: MonetaryValue,
+    },
+    /// A merchant-initiated return of a deposit, debiting the client's asset account.
+    /// Only the given amount is refunded when set, the whole deposit otherwise.
+    Refund(Option<MonetaryValue>),
+    /// A reservation of funds for a card payment that will be captured later.
+    Authorize(MonetaryValue),
+    /// The settlement of an authorization. Only the given amount is debited when set,
+    /// the rest of the authorization being released.
+    Capture(Option<MonetaryValue>),
+    /// The cancellation of an authorization, releasing the reserved funds.
+    Void,
 }
 
-#[derive(PartialEq)]
+impl TransactionKind {
+    /// Returns the amount carried by the transaction, if any.
+    pub fn amount(self) -> Option<MonetaryValue> {
+        match self {
+            Self::Deposit(amount)
+            | Self::Withdrawal(amount)
+            | Self::Authorize(amount)
+            | Self::Transfer { amount, .. } => Some(amount),
+            Self::Dispute(amount) | Self::Refund(amount) | Self::Capture(amount) => amount,
+            Self::Resolve | Self::Chargeback | Self::Void => None,
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Clone, Copy, Serialize, Deserialize)]
 /// Represents an arbitrary transaction.
 pub struct Transaction {
     pub id: TransactionId,
     pub kind: TransactionKind,
+    /// When the transaction happened, if the source provided it.
+    pub timestamp: Option<Timestamp>,
 }
 
 impl Transaction {
@@ -131,8 +263,15 @@ impl Transaction {
         Self {
             id: id.into(),
             kind,
+            timestamp: None,
         }
     }
+
+    /// Sets the time at which the transaction happened.
+    pub fn with_timestamp(mut self, timestamp: impl Into<Timestamp>) -> Self {
+        self.timestamp = Some(timestamp.into());
+        self
+    }
 }
 
 impl TryFrom<TransactionRow> for Transaction {
@@ -140,32 +279,48 @@ impl TryFrom<TransactionRow> for Transaction {
 
     fn try_from(row: TransactionRow) -> Result<Self, Self::Error> {
         use crate::cli::TransactionKind::*;
+
+        let kind = row.r#type;
+        // Negative amounts are told apart from overdrafts, which share the error of
+        // `MonetaryValue`. Kinds that take no amount ignore it.
+        let amount = || {
+            row.amount
+                .map(|amount| {
+                    MonetaryValue::try_from(amount)
+                        .map_err(|_| TransactionError::NegativeAmount(kind))
+                })
+                .transpose()
+        };
+        let required_amount = || amount()?.ok_or(TransactionError::MissingAmount(kind));
+
         Ok(Self {
             id: row.tx.into(),
-            kind: match row.r#type {
-                Deposit => TransactionKind::Deposit(
-                    row.amount
-                        .context("a deposit transaction should contain an amount")?
-                        .try_into()
-                        .context("unable to convert deposit amount to a monetary value")?,
-                ),
-                Withdrawal => TransactionKind::Withdrawal(
-                    row.amount
-                        .context("a deposit transaction should contain an amount")?
-                        .try_into()
-                        .context("unable to convert withdrawal amount to a monetary value")?,
-                ),
-                Dispute => TransactionKind::Dispute,
+            kind: match kind {
+                Deposit => TransactionKind::Deposit(required_amount()?),
+                Withdrawal => TransactionKind::Withdrawal(required_amount()?),
+                Dispute => TransactionKind::Dispute(amount()?),
                 Resolve => TransactionKind::Resolve,
                 Chargeback => TransactionKind::Chargeback,
+                Refund => TransactionKind::Refund(amount()?),
+                Authorize => TransactionKind::Authorize(required_amount()?),
+                Capture => TransactionKind::Capture(amount()?),
+                Void => TransactionKind::Void,
+                Transfer => TransactionKind::Transfer {
+                    recipient: row
+                        .counterparty
+                        .ok_or(TransactionError::MissingCounterparty)?
+                        .into(),
+                    amount: required_amount()?,
+                },
             },
+            timestamp: row.timestamp.map(Timestamp::from),
         })
     }
 }
 
 // ----------------------------------------------------------------------------
 
-#[derive(Debug, Default)]
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
 /// Represents the state of an account at a given point in time.
 pub struct Account {
     pub client_id: ClientId,
@@ -173,6 +328,8 @@ pub struct Account {
     pub available: MonetaryValue,
     /// Total funds that are held for dispute.
     pub held: MonetaryValue,
+    /// Total funds that are reserved by pending card authorizations.
+    pub authorized: MonetaryValue,
     /// Whether the account is locked. No transactions can happen on a locked account.
     pub locked: bool,
 }
@@ -235,6 +392,51 @@ impl Account {
         Ok(())
     }
 
+    /// Reserves a given amount of money until the authorization is captured or voided.
+    pub fn authorize(&mut self, amount: MonetaryValue) -> AccountOperationResult {
+        self.check_lock()?;
+
+        self.available = self.available.overdrawing_sub(amount)?;
+        self.authorized = self.authorized.overdrawing_add(amount)?;
+
+        Ok(())
+    }
+
+    /// Removes funds from the authorized funds, typically when a payment is captured.
+    pub fn capture(&mut self, amount: MonetaryValue) -> AccountOperationResult {
+        self.check_lock()?;
+
+        self.authorized = self.authorized.overdrawing_sub(amount)?;
+        Ok(())
+    }
+
+    /// Returns authorized funds to the available funds. Voiding never moves money out of
+    /// the account, so it is allowed on locked accounts.
+    pub fn void(&mut self, amount: MonetaryValue) -> AccountOperationResult {
+        self.authorized = self.authorized.overdrawing_sub(amount)?;
+        self.available = self.available.overdrawing_add(amount)?;
+
+        Ok(())
+    }
+
+    /// Moves a given amount of money from the account's available funds to the
+    /// recipient's. Neither account is modified if the transfer cannot complete.
+    pub fn transfer(
+        &mut self,
+        recipient: &mut Account,
+        amount: MonetaryValue,
+    ) -> AccountOperationResult {
+        self.check_lock()?;
+        recipient.check_lock()?;
+
+        let available = self.available.overdrawing_sub(amount)?;
+        let recipient_available = recipient.available.overdrawing_add(amount)?;
+
+        self.available = available;
+        recipient.available = recipient_available;
+        Ok(())
+    }
+
     /// Returns an `AccountLocked` error if the account is locked.
     fn check_lock(&self) -> AccountOperationResult {
         if self.locked {
@@ -251,7 +453,11 @@ impl Account {
 mod tests {
     use rust_decimal::Decimal;
 
-    use super::{Account, MonetaryValue};
+    use super::{Account, MonetaryValue, Transaction, TransactionError};
+    use crate::{
+        cli::{TransactionKind, TransactionRow},
+        error::ErrorCode,
+    };
 
     macro_rules! money {
         ($dec:expr) => {
@@ -361,4 +567,125 @@ mod tests {
         assert_eq!(account.held, money!(0.0));
         assert!(!account.locked);
     }
+
+    #[test]
+    fn transfer_funds() {
+        let mut sender = Account::new(1);
+        let mut recipient = Account::new(2);
+
+        assert!(sender.deposit(money!(2.0)).is_ok());
+        assert!(sender.transfer(&mut recipient, money!(1.5)).is_ok());
+        assert_eq!(sender.available, money!(0.5));
+        assert_eq!(recipient.available, money!(1.5));
+    }
+
+    #[test]
+    fn transfer_more_than_available() {
+        let mut sender = Account::new(1);
+        let mut recipient = Account::new(2);
+
+        assert!(sender.deposit(money!(2.0)).is_ok());
+        assert!(sender.transfer(&mut recipient, money!(3.0)).is_err());
+        assert_eq!(sender.available, money!(2.0));
+        assert_eq!(recipient.available, money!(0.0));
+    }
+
+    #[test]
+    fn transfer_to_locked_account() {
+        let mut sender = Account::new(1);
+        let mut recipient = Account::new(2);
+        recipient.locked = true;
+
+        assert!(sender.deposit(money!(2.0)).is_ok());
+        assert!(sender.transfer(&mut recipient, money!(1.0)).is_err());
+        assert_eq!(sender.available, money!(2.0));
+        assert_eq!(recipient.available, money!(0.0));
+    }
+
+    #[test]
+    fn authorize_funds() {
+        let mut account = Account::new(1);
+
+        assert!(account.deposit(money!(2.0)).is_ok());
+        assert!(account.authorize(money!(1.5)).is_ok());
+        assert_eq!(account.available, money!(0.5));
+        assert_eq!(account.authorized, money!(1.5));
+        assert!(account.authorize(money!(1.0)).is_err());
+        assert_eq!(account.available, money!(0.5));
+    }
+
+    #[test]
+    fn capture_authorized_funds() {
+        let mut account = Account::new(1);
+
+        assert!(account.deposit(money!(2.0)).is_ok());
+        assert!(account.authorize(money!(1.5)).is_ok());
+        assert!(account.capture(money!(1.5)).is_ok());
+        assert_eq!(account.available, money!(0.5));
+        assert_eq!(account.authorized, money!(0.0));
+    }
+
+    #[test]
+    fn void_authorized_funds() {
+        let mut account = Account::new(1);
+
+        assert!(account.deposit(money!(2.0)).is_ok());
+        assert!(account.authorize(money!(1.5)).is_ok());
+        account.locked = true;
+        assert!(account.void(money!(1.5)).is_ok());
+        assert_eq!(account.available, money!(2.0));
+        assert_eq!(account.authorized, money!(0.0));
+    }
+
+    #[test]
+    fn invalid_rows() {
+        let row = |r#type, amount, counterparty| TransactionRow {
+            r#type,
+            amount,
+            client: 1,
+            tx: 1,
+            timestamp: None,
+            counterparty,
+        };
+        let error =
+            |row: TransactionRow| Transaction::try_from(row).err().map(|error| error.code());
+
+        assert_eq!(
+            error(row(TransactionKind::Withdrawal, None, None)),
+            Some(ErrorCode::MissingAmount)
+        );
+        assert_eq!(
+            error(row(
+                TransactionKind::Deposit,
+                Some(Decimal::NEGATIVE_ONE),
+                None
+            )),
+            Some(ErrorCode::NegativeAmount)
+        );
+        assert_eq!(
+            error(row(TransactionKind::Transfer, Some(Decimal::ONE), None)),
+            Some(ErrorCode::MissingCounterparty)
+        );
+        // Kinds that take no amount ignore it.
+        assert_eq!(
+            error(row(
+                TransactionKind::Void,
+                Some(Decimal::NEGATIVE_ONE),
+                None
+            )),
+            None
+        );
+
+        let Err(error) = Transaction::try_from(row(TransactionKind::Withdrawal, None, None)) else {
+            unreachable!("withdrawals need an amount");
+        };
+        assert!(matches!(
+            error,
+            TransactionError::MissingAmount(TransactionKind::Withdrawal)
+        ));
+        assert_eq!(
+            error.to_string(),
+            "a withdrawal transaction should contain an amount"
+        );
+    }
 }
@@ -0,0 +1,31 @@
+use anyhow::{Context, Result};
+use clap::Parser;
+use kithmonite::{
+    cli,
+    grpc::{self, GrpcService},
+};
+
+/// Runs the payment engine as a gRPC service, as described in `proto/kithmonite.proto`.
+#[derive(Parser)]
+#[clap(author, version, about, long_about = None)]
+struct Args {
+    /// Address to listen on.
+    #[clap(long, default_value = "127.0.0.1:50051")]
+    listen: String,
+    #[clap(flatten)]
+    processor: cli::ProcessorArgs,
+}
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    let args = Args::parse();
+
+    let (listener, _) = grpc::bind(&args.listen)
+        .await
+        .with_context(|| format!("unable to listen on {}", args.listen))?;
+
+    GrpcService::new(args.processor.processor()?)
+        .serve(listener)
+        .await
+        .context("gRPC server failed")
+}
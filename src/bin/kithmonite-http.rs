@@ -0,0 +1,61 @@
+use anyhow::Result;
+use clap::Parser;
+use kithmonite::{
+    cli,
+    http::{self, HttpService},
+    processor::PaymentProcessor,
+    storage::Storage,
+};
+#[cfg(any(feature = "sled", feature = "sqlite"))]
+use std::path::PathBuf;
+
+/// Runs the payment engine as an HTTP service. Transactions are submitted as JSON
+/// to `POST /transactions` and accounts are read from `GET /accounts/{client}`.
+#[derive(Parser)]
+#[clap(author, version, about, long_about = None)]
+struct Args {
+    /// Address to listen on.
+    #[clap(long, default_value = "127.0.0.1:8080")]
+    listen: String,
+    /// Size above which request bodies are refused with a 413 status, in bytes.
+    #[clap(long, default_value_t = http::DEFAULT_BODY_LIMIT)]
+    max_body_bytes: u64,
+    /// Path of a database where accounts and their history are kept instead of
+    /// memory. If it already exists, the service resumes from its content.
+    #[cfg(feature = "sled")]
+    #[clap(long)]
+    storage: Option<PathBuf>,
+    /// Path of a SQLite database where accounts, transactions and disputes are
+    /// recorded as they are processed. If it already exists, the service resumes
+    /// from its content.
+    #[cfg(feature = "sqlite")]
+    #[clap(long)]
+    ledger: Option<PathBuf>,
+    #[clap(flatten)]
+    processor: cli::ProcessorArgs,
+}
+
+fn main() -> Result<()> {
+    let args = Args::parse();
+
+    #[cfg(feature = "sled")]
+    if let Some(path) = &args.storage {
+        let storage = kithmonite::storage::SledStorage::open(path)?;
+        return run(&args, args.processor.processor_with_storage(storage)?);
+    }
+
+    #[cfg(feature = "sqlite")]
+    if let Some(path) = &args.ledger {
+        let storage = kithmonite::storage::SqliteStorage::open(path)?;
+        return run(&args, args.processor.processor_with_storage(storage)?);
+    }
+
+    run(&args, args.processor.processor()?)
+}
+
+fn run<S: Storage>(args: &Args, processor: PaymentProcessor<S>) -> Result<()> {
+    let service = HttpService::bind(&args.listen, processor)?.with_body_limit(args.max_body_bytes);
+    service.run();
+
+    Ok(())
+}
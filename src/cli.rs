@@ -1,9 +1,182 @@
+use std::{fmt, path::PathBuf, str::FromStr, time::Duration};
+
+use anyhow::{anyhow, Context, Error as AnyError};
 use rust_decimal::Decimal;
 use serde::{Deserialize, Serialize};
 
-use crate::account::Account;
+use crate::{
+    account::{Account, MonetaryValue, Transaction, TransactionError},
+    error::ErrorCode,
+    processor::{AccountLog, PaymentProcessingError, PaymentProcessor},
+    rules::RuleSet,
+    schema::{ColumnMapping, SchemaError},
+    storage::{MemoryStorage, Storage},
+};
+
+const SECONDS_PER_DAY: u64 = 24 * 60 * 60;
+
+/// Command line options shared by every binary that runs a `PaymentProcessor`.
+#[derive(clap::Args)]
+pub struct ProcessorArgs {
+    /// Number of days after a transaction during which it can be disputed. Only
+    /// enforced on timestamped transactions.
+    #[clap(long)]
+    pub dispute_window_days: Option<u64>,
+    /// Number of days after which pending authorizations are voided. Only enforced on
+    /// timestamped transactions.
+    #[clap(long)]
+    pub authorization_expiry_days: Option<u64>,
+    /// Number of most recent deposits of each account that can be disputed or
+    /// refunded.
+    #[clap(long)]
+    pub deposit_retention: Option<u64>,
+    /// Only keep what future disputes can reference instead of the full history.
+    #[clap(long)]
+    pub compact_history: bool,
+    /// Path of a TOML file declaring fraud rules, evaluated before transactions are
+    /// applied.
+    #[clap(long)]
+    pub rules: Option<PathBuf>,
+}
+
+impl ProcessorArgs {
+    /// Creates a payment processor configured from the command line options.
+    pub fn processor(&self) -> Result<PaymentProcessor, AnyError> {
+        self.processor_with_storage(MemoryStorage::default())
+    }
+
+    /// Same as `processor`, keeping accounts in the given storage.
+    pub fn processor_with_storage<S: Storage>(
+        &self,
+        storage: S,
+    ) -> Result<PaymentProcessor<S>, AnyError> {
+        let mut processor = PaymentProcessor::with_storage(storage);
+
+        if let Some(days) = self.dispute_window_days {
+            let window = duration_of_days(days).context("invalid dispute window")?;
+            processor = processor.with_dispute_window(window);
+        }
+
+        if let Some(days) = self.authorization_expiry_days {
+            let expiry = duration_of_days(days).context("invalid authorization expiry")?;
+            processor = processor.with_authorization_expiry(expiry);
+        }
 
-#[derive(Serialize, Deserialize)]
+        if let Some(count) = self.deposit_retention {
+            processor = processor.with_deposit_retention(count);
+        }
+
+        if self.compact_history {
+            processor = processor.with_compaction();
+        }
+
+        if let Some(path) = &self.rules {
+            let rules = RuleSet::load(path)
+                .with_context(|| format!("unable to load rules from {}", path.display()))?;
+            processor = processor.with_rules(rules);
+        }
+
+        Ok(processor)
+    }
+}
+
+/// Converts a number of days given on the command line, which must fit in a duration
+/// in seconds.
+fn duration_of_days(days: u64) -> Result<Duration, AnyError> {
+    days.checked_mul(SECONDS_PER_DAY)
+        .map(Duration::from_secs)
+        .ok_or_else(|| anyhow!("{days} days is too long a duration"))
+}
+
+// ----------------------------------------------------------------------------
+
+/// Command line options describing the columns of input files.
+#[derive(clap::Args)]
+pub struct InputArgs {
+    /// Another header name for a column, such as `reference=tx`. Can be repeated.
+    #[clap(long = "column-alias", value_name = "ALIAS=COLUMN")]
+    pub column_aliases: Vec<String>,
+    /// Read files without a header, with the given columns in order, such as
+    /// `type,client,tx,amount`. `_` skips a column.
+    #[clap(long)]
+    pub columns: Option<String>,
+}
+
+impl InputArgs {
+    /// Creates a column mapping configured from the command line options.
+    pub fn mapping(&self) -> Result<ColumnMapping, AnyError> {
+        let mut mapping = ColumnMapping::default();
+
+        for alias in &self.column_aliases {
+            let (alias, column) = alias
+                .split_once('=')
+                .ok_or_else(|| anyhow!("expected ALIAS=COLUMN, got {alias:?}"))?;
+            mapping = mapping.with_alias(alias.trim(), column.trim().parse()?);
+        }
+
+        if let Some(columns) = &self.columns {
+            let columns = columns
+                .split(',')
+                .map(|column| match column.trim() {
+                    "_" => Ok(None),
+                    column => column.parse().map(Some),
+                })
+                .collect::<Result<_, SchemaError>>()?;
+            mapping = mapping.headerless(columns);
+        }
+
+        Ok(mapping)
+    }
+}
+
+// ----------------------------------------------------------------------------
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+/// What to do with rows that cannot be parsed or that are rejected by the processor.
+pub enum ErrorPolicy {
+    /// Abort on the first invalid row.
+    Strict,
+    /// Skip every invalid row.
+    Lenient,
+    /// Skip invalid rows, aborting once there are more than the given number.
+    MaxErrors(u64),
+}
+
+impl ErrorPolicy {
+    /// Whether processing must stop once `errors` rows were invalid.
+    pub fn should_abort(self, errors: u64) -> bool {
+        match self {
+            Self::Strict => errors > 0,
+            Self::Lenient => false,
+            Self::MaxErrors(max) => errors > max,
+        }
+    }
+}
+
+/// Parses `strict`, `lenient` or `max-errors=N`.
+impl FromStr for ErrorPolicy {
+    type Err = AnyError;
+
+    fn from_str(policy: &str) -> Result<Self, Self::Err> {
+        match policy {
+            "strict" => Ok(Self::Strict),
+            "lenient" => Ok(Self::Lenient),
+            _ => {
+                let max = policy
+                    .strip_prefix("max-errors=")
+                    .ok_or_else(|| anyhow!("expected strict, lenient or max-errors=N"))?;
+
+                Ok(Self::MaxErrors(
+                    max.parse().context("invalid maximum number of errors")?,
+                ))
+            }
+        }
+    }
+}
+
+// ----------------------------------------------------------------------------
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
 #[serde(rename_all = "lowercase")]
 pub enum TransactionKind {
     Deposit,
@@ -11,38 +184,239 @@ pub enum TransactionKind {
     Dispute,
     Resolve,
     Chargeback,
+    Transfer,
+    Refund,
+    Authorize,
+    Capture,
+    Void,
 }
 
-#[derive(Serialize, Deserialize)]
+impl fmt::Display for TransactionKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Self::Deposit => "deposit",
+            Self::Withdrawal => "withdrawal",
+            Self::Dispute => "dispute",
+            Self::Resolve => "resolve",
+            Self::Chargeback => "chargeback",
+            Self::Transfer => "transfer",
+            Self::Refund => "refund",
+            Self::Authorize => "authorize",
+            Self::Capture => "capture",
+            Self::Void => "void",
+        };
+
+        f.write_str(name)
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone)]
 pub struct TransactionRow {
     pub r#type: TransactionKind,
     pub amount: Option<Decimal>,
     pub client: u16,
     pub tx: u32,
+    /// Seconds since the Unix epoch. The column is optional in the input file.
+    #[serde(default)]
+    pub timestamp: Option<u64>,
+    /// Client credited by a transfer. The column is optional in the input file.
+    #[serde(default)]
+    pub counterparty: Option<u16>,
+}
+
+impl TransactionRow {
+    /// Checks that the amount fits the kind of transaction. Such rows can still be
+    /// converted to transactions: ignored amounts are dropped and zero amounts are
+    /// applied as is.
+    pub fn validate(&self) -> Result<(), TransactionError> {
+        use TransactionKind::*;
+
+        match (self.r#type, self.amount) {
+            // Disputes take an amount since partial disputes are allowed, and
+            // `apply_with_policy` checks it against the deposit.
+            (Resolve | Chargeback | Void, Some(_)) => {
+                Err(TransactionError::UnexpectedAmount(self.r#type))
+            }
+            // Every other kind that carries an amount needs it to move something.
+            (_, Some(amount))
+                if MonetaryValue::try_from(amount)
+                    .is_ok_and(|amount| amount == MonetaryValue::default()) =>
+            {
+                Err(TransactionError::ZeroAmount(self.r#type))
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+#[derive(clap::ArgEnum, Debug, Clone, Copy, PartialEq, Eq)]
+/// What to do with rows whose amount does not fit their kind.
+pub enum AmountPolicy {
+    Reject,
+    Warn,
+}
+
+// ----------------------------------------------------------------------------
+
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+/// Outcome of a single submitted transaction.
+pub struct TransactionResult {
+    pub client: u16,
+    pub tx: u32,
+    pub accepted: bool,
+    /// Why the transaction was rejected.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    /// Identifies the error, or the warning if the transaction was accepted.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub code: Option<ErrorCode>,
+    /// What is wrong with the transaction, if it was accepted nonetheless.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub warning: Option<String>,
+}
+
+/// Applies a transaction row to the processor, reporting why it was rejected if it was.
+/// Rows whose amount does not fit their kind are rejected.
+pub fn apply<S: Storage>(
+    processor: &mut PaymentProcessor<S>,
+    row: TransactionRow,
+) -> TransactionResult {
+    apply_with_policy(processor, row, AmountPolicy::Reject)
+}
+
+/// Same as `apply`, handling rows whose amount does not fit their kind as the policy
+/// says.
+pub fn apply_with_policy<S: Storage>(
+    processor: &mut PaymentProcessor<S>,
+    row: TransactionRow,
+    policy: AmountPolicy,
+) -> TransactionResult {
+    let (client, tx) = (row.client, row.tx);
+    // Rows rejected before reaching the processor are counted on the account as well.
+    let amount = row
+        .amount
+        .and_then(|amount| MonetaryValue::try_from(amount).ok());
+
+    let validation = row.validate();
+    let warning = match (validation, policy) {
+        (Err(error), AmountPolicy::Reject) => {
+            let rejection = (error.code(), error.to_string());
+            return reject(processor, (client, tx), amount, rejection, None);
+        }
+        (validation, _) => validation.err(),
+    };
+
+    // Unlike the amounts checked by `validate`, a dispute asking for more than what
+    // remains of its deposit can never be applied, so it is rejected whatever the
+    // policy. Failures of the storage are left for the processor to report.
+    if let (TransactionKind::Dispute, Some(disputed)) = (row.r#type, amount) {
+        if let Ok(Some(remaining)) = processor.deposit_balance(client, tx) {
+            if disputed > remaining {
+                let error = TransactionError::DisputeExceedsDeposit(remaining.into());
+                let rejection = (error.code(), error.to_string());
+                return reject(processor, (client, tx), amount, rejection, warning);
+            }
+        }
+    }
+
+    let transaction = match Transaction::try_from(row) {
+        Ok(transaction) => transaction,
+        Err(error) => {
+            let error = PaymentProcessingError::from(error);
+            let rejection = (error.code(), format!("{:#}", AnyError::from(error)));
+            return reject(processor, (client, tx), amount, rejection, warning);
+        }
+    };
+
+    match processor.process(client, transaction) {
+        Ok(()) => TransactionResult {
+            client,
+            tx,
+            accepted: true,
+            error: None,
+            code: warning.as_ref().map(TransactionError::code),
+            warning: warning.map(|warning| warning.to_string()),
+        },
+        Err(error) => TransactionResult {
+            client,
+            tx,
+            accepted: false,
+            code: Some(error.code()),
+            error: Some(format!("{:#}", AnyError::from(error))),
+            warning: warning.map(|warning| warning.to_string()),
+        },
+    }
+}
+
+/// Counts a row that cannot be processed on the account of its client, reporting the
+/// given code and error, or the failure of the storage if the row cannot be counted.
+fn reject<S: Storage>(
+    processor: &mut PaymentProcessor<S>,
+    (client, tx): (u16, u32),
+    amount: Option<MonetaryValue>,
+    (code, error): (ErrorCode, String),
+    warning: Option<TransactionError>,
+) -> TransactionResult {
+    let (code, error) = match processor.reject(client, amount) {
+        Ok(()) => (code, error),
+        Err(failure) => {
+            let failure = PaymentProcessingError::from(failure);
+            (failure.code(), format!("{:#}", AnyError::from(failure)))
+        }
+    };
+
+    TransactionResult {
+        client,
+        tx,
+        accepted: false,
+        error: Some(error),
+        code: Some(code),
+        warning: warning.map(|warning| warning.to_string()),
+    }
 }
 
 // ----------------------------------------------------------------------------
 
-#[derive(Serialize)]
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
 pub struct Output {
-    client: u16,
-    available: Decimal,
-    held: Decimal,
-    total: Decimal,
-    locked: bool,
+    pub client: u16,
+    pub available: Decimal,
+    pub held: Decimal,
+    pub authorized: Decimal,
+    pub total: Decimal,
+    pub locked: bool,
+    /// Number of rejected transactions, only output on demand.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub rejected: Option<u64>,
+    /// Sum of the amounts of the rejected transactions, only output on demand.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub rejected_amount: Option<Decimal>,
+}
+
+impl Output {
+    /// Adds the rejection counters of the account to the output.
+    pub fn with_rejections(mut self, log: &AccountLog) -> Self {
+        self.rejected = Some(log.rejected());
+        self.rejected_amount = Some(log.rejected_amount().into());
+        self
+    }
 }
 
 impl From<Account> for Output {
     fn from(account: Account) -> Self {
         let available: Decimal = account.available.into();
         let held: Decimal = account.held.into();
+        let authorized: Decimal = account.authorized.into();
 
         Self {
             client: account.client_id.into(),
             available,
             held,
-            total: (available + held),
+            authorized,
+            total: (available + held + authorized),
             locked: account.locked,
+            rejected: None,
+            rejected_amount: None,
         }
     }
 }
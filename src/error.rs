@@ -0,0 +1,149 @@
+use std::{fmt, str::FromStr};
+
+use serde::{Deserialize, Serialize};
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[serde(rename_all = "snake_case")]
+/// Stable, machine-readable identifier of why a row or a transaction was rejected.
+/// Codes are never renamed or reused, so that downstream systems can match on them
+/// instead of on error messages.
+pub enum ErrorCode {
+    /// The row could not be parsed.
+    InvalidRow,
+    MissingAmount,
+    MissingCounterparty,
+    NegativeAmount,
+    UnexpectedAmount,
+    ZeroAmount,
+    AmountOutOfRange,
+    InsufficientFunds,
+    AccountLocked,
+    StorageFailure,
+    NonMonotonicTimestamp,
+    UnknownTransaction,
+    DuplicateDispute,
+    NoDispute,
+    DisputeWindowExpired,
+    DisputeAmountExceeded,
+    RefundAmountExceeded,
+    SelfTransfer,
+    DuplicateAuthorization,
+    NoAuthorization,
+    CaptureAmountExceeded,
+    WithdrawalVelocityExceeded,
+    WithdrawalAmountExceeded,
+    DailyVolumeExceeded,
+    DisputeRateExceeded,
+    /// A dispute row asked for more than what remains of the deposit.
+    DisputeExceedsDeposit,
+}
+
+impl ErrorCode {
+    pub const ALL: [Self; 26] = [
+        Self::InvalidRow,
+        Self::MissingAmount,
+        Self::MissingCounterparty,
+        Self::NegativeAmount,
+        Self::UnexpectedAmount,
+        Self::ZeroAmount,
+        Self::AmountOutOfRange,
+        Self::InsufficientFunds,
+        Self::AccountLocked,
+        Self::StorageFailure,
+        Self::NonMonotonicTimestamp,
+        Self::UnknownTransaction,
+        Self::DuplicateDispute,
+        Self::NoDispute,
+        Self::DisputeWindowExpired,
+        Self::DisputeAmountExceeded,
+        Self::RefundAmountExceeded,
+        Self::SelfTransfer,
+        Self::DuplicateAuthorization,
+        Self::NoAuthorization,
+        Self::CaptureAmountExceeded,
+        Self::WithdrawalVelocityExceeded,
+        Self::WithdrawalAmountExceeded,
+        Self::DailyVolumeExceeded,
+        Self::DisputeRateExceeded,
+        Self::DisputeExceedsDeposit,
+    ];
+
+    /// The code as it appears in reports, which is also its serialized form.
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::InvalidRow => "invalid_row",
+            Self::MissingAmount => "missing_amount",
+            Self::MissingCounterparty => "missing_counterparty",
+            Self::NegativeAmount => "negative_amount",
+            Self::UnexpectedAmount => "unexpected_amount",
+            Self::ZeroAmount => "zero_amount",
+            Self::AmountOutOfRange => "amount_out_of_range",
+            Self::InsufficientFunds => "insufficient_funds",
+            Self::AccountLocked => "account_locked",
+            Self::StorageFailure => "storage_failure",
+            Self::NonMonotonicTimestamp => "non_monotonic_timestamp",
+            Self::UnknownTransaction => "unknown_transaction",
+            Self::DuplicateDispute => "duplicate_dispute",
+            Self::NoDispute => "no_dispute",
+            Self::DisputeWindowExpired => "dispute_window_expired",
+            Self::DisputeAmountExceeded => "dispute_amount_exceeded",
+            Self::RefundAmountExceeded => "refund_amount_exceeded",
+            Self::SelfTransfer => "self_transfer",
+            Self::DuplicateAuthorization => "duplicate_authorization",
+            Self::NoAuthorization => "no_authorization",
+            Self::CaptureAmountExceeded => "capture_amount_exceeded",
+            Self::WithdrawalVelocityExceeded => "withdrawal_velocity_exceeded",
+            Self::WithdrawalAmountExceeded => "withdrawal_amount_exceeded",
+            Self::DailyVolumeExceeded => "daily_volume_exceeded",
+            Self::DisputeRateExceeded => "dispute_rate_exceeded",
+            Self::DisputeExceedsDeposit => "dispute_exceeds_deposit",
+        }
+    }
+}
+
+impl fmt::Display for ErrorCode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl FromStr for ErrorCode {
+    type Err = UnknownErrorCode;
+
+    fn from_str(code: &str) -> Result<Self, Self::Err> {
+        Self::ALL
+            .into_iter()
+            .find(|known| known.as_str() == code)
+            .ok_or_else(|| UnknownErrorCode(code.to_owned()))
+    }
+}
+
+#[derive(thiserror::Error, Debug, PartialEq, Eq)]
+#[error("unknown error code `{0}`")]
+pub struct UnknownErrorCode(String);
+
+// ----------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+
+    use super::{ErrorCode, UnknownErrorCode};
+
+    #[test]
+    fn codes_are_unique_and_serialized_as_displayed() {
+        let codes: HashSet<_> = ErrorCode::ALL.iter().map(|code| code.as_str()).collect();
+        assert_eq!(codes.len(), ErrorCode::ALL.len());
+
+        for code in ErrorCode::ALL {
+            let serialized = serde_json::to_string(&code).expect("codes are serializable");
+            assert_eq!(serialized, format!("\"{code}\""));
+            assert_eq!(code.as_str().parse(), Ok(code));
+        }
+
+        assert_eq!(
+            "overdraft".parse::<ErrorCode>(),
+            Err(UnknownErrorCode("overdraft".to_owned()))
+        );
+    }
+}
@@ -0,0 +1,137 @@
+use std::{io::Write, sync::mpsc::Sender};
+
+use anyhow::Context;
+use serde::Serialize;
+
+use crate::account::{ClientId, MonetaryValue, TransactionId};
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+/// Something that happened to an account as a result of processing transactions.
+pub enum AccountEvent {
+    /// The funds of the account changed. `tx` is unset when the change was not caused
+    /// by a transaction, e.g. when stale authorizations are voided.
+    BalanceChanged {
+        client: ClientId,
+        tx: Option<TransactionId>,
+        available: MonetaryValue,
+        held: MonetaryValue,
+        authorized: MonetaryValue,
+    },
+    /// Funds of a deposit were held for dispute.
+    DisputeOpened {
+        client: ClientId,
+        tx: TransactionId,
+        amount: MonetaryValue,
+    },
+    /// A dispute was solved and its funds released.
+    DisputeResolved {
+        client: ClientId,
+        tx: TransactionId,
+        amount: MonetaryValue,
+    },
+    /// A dispute ended with its funds being reversed.
+    ChargedBack {
+        client: ClientId,
+        tx: TransactionId,
+        amount: MonetaryValue,
+    },
+    /// The account got locked and will not accept transactions anymore.
+    AccountLocked { client: ClientId, tx: TransactionId },
+}
+
+// ----------------------------------------------------------------------------
+
+/// Receives the events of a `PaymentProcessor`, in the order they happened.
+pub trait EventSubscriber {
+    fn on_event(&mut self, event: &AccountEvent);
+
+    /// Makes sure every event received so far has been handled, returning the first
+    /// error encountered while doing so.
+    fn flush(&mut self) -> anyhow::Result<()> {
+        Ok(())
+    }
+}
+
+/// Forwards events to another thread. Events are dropped once the receiver is gone.
+impl EventSubscriber for Sender<AccountEvent> {
+    fn on_event(&mut self, event: &AccountEvent) {
+        let _ = self.send(event.clone());
+    }
+}
+
+/// Writes every event as a line of JSON.
+pub struct JsonLinesSink<W: Write> {
+    writer: W,
+    /// First error encountered, after which events are not written anymore.
+    error: Option<anyhow::Error>,
+}
+
+impl<W: Write> JsonLinesSink<W> {
+    pub fn new(writer: W) -> Self {
+        Self {
+            writer,
+            error: None,
+        }
+    }
+
+    fn write(&mut self, event: &AccountEvent) -> anyhow::Result<()> {
+        serde_json::to_writer(&mut self.writer, event).context("unable to serialize event")?;
+        self.writer
+            .write_all(b"\n")
+            .context("unable to write event")
+    }
+}
+
+impl<W: Write> EventSubscriber for JsonLinesSink<W> {
+    fn on_event(&mut self, event: &AccountEvent) {
+        if self.error.is_none() {
+            self.error = self.write(event).err();
+        }
+    }
+
+    fn flush(&mut self) -> anyhow::Result<()> {
+        if let Some(error) = self.error.take() {
+            return Err(error);
+        }
+
+        self.writer.flush().context("unable to flush events")
+    }
+}
+
+// ----------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use rust_decimal_macros::dec;
+
+    use super::{AccountEvent, EventSubscriber, JsonLinesSink};
+    use crate::account::MonetaryValue;
+
+    #[test]
+    fn write_json_lines() {
+        let amount: MonetaryValue = dec!(1.5).try_into().expect("1.5 is a decimal");
+        let mut sink = JsonLinesSink::new(Vec::new());
+
+        sink.on_event(&AccountEvent::DisputeOpened {
+            client: 1.into(),
+            tx: 2.into(),
+            amount,
+        });
+        sink.on_event(&AccountEvent::AccountLocked {
+            client: 1.into(),
+            tx: 3.into(),
+        });
+
+        assert!(sink.flush().is_ok());
+        assert_eq!(
+            String::from_utf8(sink.writer).expect("events are valid utf-8"),
+            concat!(
+                r#"{"event":"dispute_opened","client":1,"tx":2,"amount":"1.5"}"#,
+                "\n",
+                r#"{"event":"account_locked","client":1,"tx":3}"#,
+                "\n",
+            )
+        );
+    }
+}
@@ -0,0 +1,511 @@
+use std::{net::SocketAddr, pin::Pin, sync::Arc};
+
+use anyhow::{bail, Context, Error as AnyError};
+use tokio::{
+    net::TcpListener,
+    sync::{broadcast, Mutex, MutexGuard},
+};
+use tokio_stream::{
+    wrappers::{errors::BroadcastStreamRecvError, BroadcastStream, TcpListenerStream},
+    Stream, StreamExt,
+};
+use tonic::{transport::Server, Request, Response, Status, Streaming};
+
+use crate::{
+    cli::{self, Output, TransactionResult, TransactionRow},
+    error::ErrorCode,
+    processor::PaymentProcessor,
+};
+
+use proto::{
+    payment_engine_server::{PaymentEngine, PaymentEngineServer},
+    GetAccountRequest, ListAccountsRequest, ListAccountsResponse, SubmitSummary,
+    WatchAccountsRequest,
+};
+
+/// Types generated from `proto/kithmonite.proto`.
+pub mod proto {
+    tonic::include_proto!("kithmonite");
+}
+
+/// Number of account changes buffered for slow `WatchAccounts` subscribers.
+const CHANGE_FEED_CAPACITY: usize = 1024;
+
+// ----------------------------------------------------------------------------
+
+impl TryFrom<proto::Transaction> for TransactionRow {
+    type Error = AnyError;
+
+    fn try_from(transaction: proto::Transaction) -> Result<Self, Self::Error> {
+        use cli::TransactionKind::*;
+        use proto::TransactionKind as Kind;
+
+        Ok(Self {
+            r#type: match Kind::try_from(transaction.r#type).context("unknown transaction type")? {
+                // Messages without a type must not be mistaken for any kind.
+                Kind::Unspecified => bail!("transaction type is not set"),
+                Kind::Deposit => Deposit,
+                Kind::Withdrawal => Withdrawal,
+                Kind::Dispute => Dispute,
+                Kind::Resolve => Resolve,
+                Kind::Chargeback => Chargeback,
+                Kind::Transfer => Transfer,
+                Kind::Refund => Refund,
+                Kind::Authorize => Authorize,
+                Kind::Capture => Capture,
+                Kind::Void => Void,
+            },
+            amount: transaction
+                .amount
+                .map(|amount| amount.parse())
+                .transpose()
+                .context("amount is not a decimal")?,
+            client: transaction
+                .client
+                .try_into()
+                .context("client id does not fit in 16 bits")?,
+            tx: transaction.tx,
+            timestamp: transaction.timestamp,
+            counterparty: transaction
+                .counterparty
+                .map(u16::try_from)
+                .transpose()
+                .context("counterparty id does not fit in 16 bits")?,
+        })
+    }
+}
+
+impl From<Output> for proto::Account {
+    fn from(output: Output) -> Self {
+        Self {
+            client: output.client.into(),
+            available: output.available.to_string(),
+            held: output.held.to_string(),
+            authorized: output.authorized.to_string(),
+            total: output.total.to_string(),
+            locked: output.locked,
+        }
+    }
+}
+
+impl From<TransactionResult> for proto::TransactionResult {
+    fn from(result: TransactionResult) -> Self {
+        Self {
+            client: result.client.into(),
+            tx: result.tx,
+            accepted: result.accepted,
+            error: result.error.unwrap_or_default(),
+            code: result.code.map(|code| code.to_string()).unwrap_or_default(),
+        }
+    }
+}
+
+// ----------------------------------------------------------------------------
+
+/// Serves a payment processor over gRPC. Transactions are applied one at a time, in
+/// the order they are received.
+#[derive(Clone)]
+pub struct GrpcService {
+    processor: Arc<Mutex<PaymentProcessor>>,
+    changes: broadcast::Sender<proto::Account>,
+}
+
+impl GrpcService {
+    pub fn new(processor: PaymentProcessor) -> Self {
+        Self {
+            processor: Arc::new(Mutex::new(processor)),
+            changes: broadcast::channel(CHANGE_FEED_CAPACITY).0,
+        }
+    }
+
+    /// Accepts connections on the listener until the process is stopped.
+    pub async fn serve(self, listener: TcpListener) -> Result<(), tonic::transport::Error> {
+        Server::builder()
+            .add_service(PaymentEngineServer::new(self))
+            .serve_with_incoming(TcpListenerStream::new(listener))
+            .await
+    }
+
+    /// Waits for the processor without blocking the thread, as it is held while a
+    /// transaction is applied.
+    async fn processor(&self) -> MutexGuard<'_, PaymentProcessor> {
+        self.processor.lock().await
+    }
+
+    /// Applies a transaction of the given client and publishes the accounts it changed.
+    async fn apply(&self, client: u16, transaction: proto::Transaction) -> TransactionResult {
+        let tx = transaction.tx;
+
+        let row = match TransactionRow::try_from(transaction) {
+            Ok(row) => row,
+            Err(error) => {
+                return TransactionResult {
+                    client,
+                    tx,
+                    accepted: false,
+                    error: Some(format!("{error:#}")),
+                    code: Some(ErrorCode::InvalidRow),
+                    warning: None,
+                }
+            }
+        };
+
+        let clients: Vec<u16> = [Some(row.client), row.counterparty]
+            .into_iter()
+            .flatten()
+            .collect();
+        let mut processor = self.processor().await;
+        // A storage error surfaces on the next read of the account.
+        let read = |processor: &PaymentProcessor, client_id: u16| {
+            processor.account_log(client_id).ok().flatten()
+        };
+        let before: Vec<_> = clients
+            .iter()
+            .map(|client_id| read(&processor, *client_id))
+            .collect();
+
+        let result = cli::apply(&mut processor, row);
+
+        // Rejected transactions may change accounts as well, such as a lock set by a
+        // rule or their rejection counters.
+        for (client_id, before) in clients.into_iter().zip(before) {
+            match read(&processor, client_id) {
+                Some(log) if Some(&log) != before.as_ref() => {
+                    // Sending only fails when nobody is watching.
+                    let _ = self.changes.send(Output::from(log.state().clone()).into());
+                }
+                _ => {}
+            }
+        }
+
+        result
+    }
+}
+
+type AccountStream = Pin<Box<dyn Stream<Item = Result<proto::Account, Status>> + Send>>;
+
+#[tonic::async_trait]
+impl PaymentEngine for GrpcService {
+    async fn submit(
+        &self,
+        request: Request<Streaming<proto::Transaction>>,
+    ) -> Result<Response<SubmitSummary>, Status> {
+        let mut transactions = request.into_inner();
+        let mut summary = SubmitSummary::default();
+
+        while let Some(transaction) = transactions.message().await? {
+            // Results identify transactions by their client, which must be valid.
+            let client = u16::try_from(transaction.client)
+                .map_err(|_| Status::invalid_argument("client id does not fit in 16 bits"))?;
+            let result = self.apply(client, transaction).await;
+
+            if result.accepted {
+                summary.accepted += 1;
+            } else {
+                summary.rejected += 1;
+                summary.rejections.push(result.into());
+            }
+        }
+
+        Ok(Response::new(summary))
+    }
+
+    async fn get_account(
+        &self,
+        request: Request<GetAccountRequest>,
+    ) -> Result<Response<proto::Account>, Status> {
+        let client_id = u16::try_from(request.into_inner().client)
+            .map_err(|_| Status::invalid_argument("client id does not fit in 16 bits"))?;
+
+        self.processor()
+            .await
+            .account_log(client_id)
+            .map_err(|error| Status::internal(format!("unable to read account: {error}")))?
+            .map(|log| Response::new(Output::from(log.state().clone()).into()))
+            .ok_or_else(|| Status::not_found("account not found"))
+    }
+
+    async fn list_accounts(
+        &self,
+        _request: Request<ListAccountsRequest>,
+    ) -> Result<Response<ListAccountsResponse>, Status> {
+        let mut accounts: Vec<proto::Account> = self
+            .processor()
+            .await
+            .account_logs()
+            .map(|log| log.map(|log| Output::from(log.state().clone()).into()))
+            .collect::<Result<_, _>>()
+            .map_err(|error| Status::internal(format!("unable to read accounts: {error}")))?;
+        accounts.sort_by_key(|account| account.client);
+
+        Ok(Response::new(ListAccountsResponse { accounts }))
+    }
+
+    type WatchAccountsStream = AccountStream;
+
+    async fn watch_accounts(
+        &self,
+        request: Request<WatchAccountsRequest>,
+    ) -> Result<Response<Self::WatchAccountsStream>, Status> {
+        let client = request.into_inner().client;
+
+        let changes =
+            BroadcastStream::new(self.changes.subscribe()).filter_map(move |change| match change {
+                Ok(account) if client.is_none_or(|client| client == account.client) => {
+                    Some(Ok(account))
+                }
+                Ok(_) => None,
+                Err(BroadcastStreamRecvError::Lagged(skipped)) => Some(Err(Status::data_loss(
+                    format!("{skipped} account changes were dropped"),
+                ))),
+            });
+
+        Ok(Response::new(Box::pin(changes)))
+    }
+}
+
+/// Binds a listener to the given address, returning it with its actual address.
+pub async fn bind(address: &str) -> std::io::Result<(TcpListener, SocketAddr)> {
+    let listener = TcpListener::bind(address).await?;
+    let address = listener.local_addr()?;
+
+    Ok((listener, address))
+}
+
+// ----------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use tokio_stream::StreamExt;
+
+    use super::{
+        bind,
+        proto::{
+            payment_engine_client::PaymentEngineClient, Account, GetAccountRequest,
+            ListAccountsRequest, Transaction, TransactionKind, WatchAccountsRequest,
+        },
+        GrpcService,
+    };
+    use rust_decimal_macros::dec;
+
+    use crate::{
+        processor::PaymentProcessor,
+        rules::{Limit, Rule, RuleSet},
+    };
+
+    type Client = PaymentEngineClient<tonic::transport::Channel>;
+
+    /// Starts a service on a random local port and connects a client to it.
+    async fn start() -> Client {
+        start_with(PaymentProcessor::new()).await
+    }
+
+    async fn start_with(processor: PaymentProcessor) -> Client {
+        let (listener, address) = bind("127.0.0.1:0")
+            .await
+            .expect("the service should bind to a local port");
+
+        tokio::spawn(GrpcService::new(processor).serve(listener));
+
+        PaymentEngineClient::connect(format!("http://{address}"))
+            .await
+            .expect("the client should connect to the service")
+    }
+
+    fn transaction(kind: TransactionKind, client: u32, tx: u32, amount: &str) -> Transaction {
+        Transaction {
+            r#type: kind.into(),
+            client,
+            tx,
+            amount: Some(amount.to_string()),
+            ..Default::default()
+        }
+    }
+
+    fn account(client: u32, available: &str) -> Account {
+        Account {
+            client,
+            available: available.to_string(),
+            held: "0".to_string(),
+            authorized: "0".to_string(),
+            total: available.to_string(),
+            locked: false,
+        }
+    }
+
+    #[tokio::test]
+    async fn submit_stream() {
+        let mut client = start().await;
+
+        let summary = client
+            .submit(tokio_stream::iter(vec![
+                transaction(TransactionKind::Deposit, 1, 1, "2.5"),
+                transaction(TransactionKind::Withdrawal, 1, 2, "3.0"),
+                transaction(TransactionKind::Deposit, 2, 3, "1.0"),
+            ]))
+            .await
+            .expect("the stream should be accepted")
+            .into_inner();
+
+        assert_eq!(summary.accepted, 2);
+        assert_eq!(summary.rejected, 1);
+        assert_eq!(summary.rejections[0].tx, 2);
+        assert!(!summary.rejections[0].error.is_empty());
+        assert_eq!(summary.rejections[0].code, "insufficient_funds");
+
+        let account = client
+            .get_account(GetAccountRequest { client: 1 })
+            .await
+            .expect("the account should exist")
+            .into_inner();
+
+        assert_eq!(account, self::account(1, "2.5"));
+
+        let accounts = client
+            .list_accounts(ListAccountsRequest {})
+            .await
+            .expect("accounts should be listed")
+            .into_inner()
+            .accounts;
+
+        assert_eq!(
+            accounts,
+            vec![self::account(1, "2.5"), self::account(2, "1.0")]
+        );
+    }
+
+    #[tokio::test]
+    async fn submit_without_type() {
+        let mut client = start().await;
+
+        let summary = client
+            .submit(tokio_stream::iter(vec![Transaction {
+                client: 1,
+                tx: 1,
+                amount: Some("2.5".to_string()),
+                ..Default::default()
+            }]))
+            .await
+            .expect("the stream should be accepted")
+            .into_inner();
+
+        assert_eq!(summary.accepted, 0);
+        assert_eq!(summary.rejected, 1);
+        assert_eq!(summary.rejections[0].code, "invalid_row");
+
+        let status = client
+            .get_account(GetAccountRequest { client: 1 })
+            .await
+            .expect_err("the account should not exist");
+
+        assert_eq!(status.code(), tonic::Code::NotFound);
+    }
+
+    #[tokio::test]
+    async fn submit_out_of_range_client() {
+        let mut client = start().await;
+
+        let status = client
+            .submit(tokio_stream::iter(vec![transaction(
+                TransactionKind::Deposit,
+                u32::from(u16::MAX) + 1,
+                1,
+                "2.5",
+            )]))
+            .await
+            .expect_err("the stream should be refused");
+
+        assert_eq!(status.code(), tonic::Code::InvalidArgument);
+
+        let accounts = client
+            .list_accounts(ListAccountsRequest {})
+            .await
+            .expect("accounts should be listed")
+            .into_inner()
+            .accounts;
+        assert!(accounts.is_empty());
+    }
+
+    #[tokio::test]
+    async fn get_unknown_account() {
+        let mut client = start().await;
+
+        let status = client
+            .get_account(GetAccountRequest { client: 1 })
+            .await
+            .expect_err("the account should not exist");
+
+        assert_eq!(status.code(), tonic::Code::NotFound);
+    }
+
+    #[tokio::test]
+    async fn watch_accounts() {
+        let mut client = start().await;
+
+        let mut changes = client
+            .watch_accounts(WatchAccountsRequest { client: Some(2) })
+            .await
+            .expect("the feed should open")
+            .into_inner();
+
+        client
+            .submit(tokio_stream::iter(vec![
+                transaction(TransactionKind::Deposit, 1, 1, "2.5"),
+                transaction(TransactionKind::Deposit, 2, 2, "1.0"),
+                transaction(TransactionKind::Withdrawal, 2, 3, "0.5"),
+            ]))
+            .await
+            .expect("the stream should be accepted");
+
+        let first = changes.next().await.expect("a change should be streamed");
+        let second = changes.next().await.expect("a change should be streamed");
+
+        assert_eq!(first.expect("no change is dropped"), account(2, "1.0"));
+        assert_eq!(second.expect("no change is dropped"), account(2, "0.5"));
+    }
+
+    #[tokio::test]
+    async fn watch_rejected_changes() {
+        let rules = RuleSet::default().with_rule(Rule::new(
+            "disputes",
+            Limit::MaxDisputeRate {
+                rate: dec!(0),
+                min_deposits: 0,
+            },
+        ));
+        let mut client = start_with(PaymentProcessor::new().with_rules(rules)).await;
+
+        let mut changes = client
+            .watch_accounts(WatchAccountsRequest { client: Some(1) })
+            .await
+            .expect("the feed should open")
+            .into_inner();
+
+        let summary = client
+            .submit(tokio_stream::iter(vec![
+                transaction(TransactionKind::Deposit, 1, 1, "2.5"),
+                Transaction {
+                    r#type: TransactionKind::Dispute.into(),
+                    client: 1,
+                    tx: 1,
+                    ..Default::default()
+                },
+            ]))
+            .await
+            .expect("the stream should be accepted")
+            .into_inner();
+        assert_eq!(summary.rejected, 1);
+
+        let first = changes.next().await.expect("a change should be streamed");
+        let second = changes.next().await.expect("a change should be streamed");
+
+        assert_eq!(first.expect("no change is dropped"), account(1, "2.5"));
+        assert_eq!(
+            second.expect("no change is dropped"),
+            Account {
+                locked: true,
+                ..account(1, "2.5")
+            }
+        );
+    }
+}
@@ -0,0 +1,391 @@
+use std::{
+    io::{Cursor, Read},
+    net::SocketAddr,
+};
+
+use anyhow::{anyhow, Context, Result};
+use serde::{Deserialize, Serialize};
+use tiny_http::{Header, Method, Request, Response, Server};
+
+use crate::{
+    cli::{apply, Output, TransactionResult, TransactionRow},
+    processor::PaymentProcessor,
+    storage::{MemoryStorage, Storage},
+};
+
+/// Default size above which request bodies are refused, in bytes.
+pub const DEFAULT_BODY_LIMIT: u64 = 1024 * 1024;
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+/// Body of a `POST /transactions` request.
+pub enum Submission {
+    Single(TransactionRow),
+    Batch(Vec<TransactionRow>),
+}
+
+#[derive(Serialize)]
+#[serde(untagged)]
+/// Body of a `POST /transactions` response, shaped like the submission.
+enum SubmissionResult {
+    Single(TransactionResult),
+    Batch(Vec<TransactionResult>),
+}
+
+#[derive(Serialize)]
+struct ErrorBody {
+    error: String,
+}
+
+// ----------------------------------------------------------------------------
+
+/// Serves a payment processor over HTTP. Requests are handled one at a time, in the
+/// order they are received.
+///
+/// - `POST /transactions` applies a transaction row, or an array of rows, and returns
+///   whether each of them was accepted.
+/// - `GET /accounts/{client}` returns the current state of an account.
+///
+/// Request bodies larger than the body limit are refused with a 413 status, and
+/// changes are flushed to the storage before each `POST` is answered.
+pub struct HttpService<S: Storage = MemoryStorage> {
+    server: Server,
+    processor: PaymentProcessor<S>,
+    body_limit: u64,
+}
+
+impl<S: Storage> HttpService<S> {
+    /// Binds the service to the given address without accepting requests yet.
+    pub fn bind(address: &str, processor: PaymentProcessor<S>) -> Result<Self> {
+        let server = Server::http(address)
+            .map_err(|error| anyhow!(error))
+            .with_context(|| format!("unable to listen on {address}"))?;
+
+        Ok(Self {
+            server,
+            processor,
+            body_limit: DEFAULT_BODY_LIMIT,
+        })
+    }
+
+    /// Sets the size above which request bodies are refused, in bytes.
+    pub fn with_body_limit(mut self, body_limit: u64) -> Self {
+        self.body_limit = body_limit;
+        self
+    }
+
+    /// Returns the address the service is listening on.
+    pub fn local_addr(&self) -> Option<SocketAddr> {
+        self.server.server_addr().to_ip()
+    }
+
+    /// Handles incoming requests until the process is stopped.
+    pub fn run(self) {
+        let Self {
+            server,
+            mut processor,
+            body_limit,
+        } = self;
+
+        for request in server.incoming_requests() {
+            // A failed response only means that the client went away.
+            let _ = handle(&mut processor, body_limit, request);
+        }
+    }
+}
+
+fn handle<S: Storage>(
+    processor: &mut PaymentProcessor<S>,
+    body_limit: u64,
+    mut request: Request,
+) -> std::io::Result<()> {
+    let response = match (request.method(), request.url()) {
+        (Method::Post, "/transactions") => match read_body(&mut request, body_limit)? {
+            Some(body) => submit(processor, &body),
+            None => json_response(
+                413,
+                &ErrorBody {
+                    error: format!("request body exceeds {body_limit} bytes"),
+                },
+            ),
+        },
+        (Method::Get, url) if url.starts_with("/accounts/") => {
+            let account = match url["/accounts/".len()..].parse::<u16>() {
+                Ok(client_id) => processor.account_log(client_id),
+                Err(_) => Ok(None),
+            };
+
+            match account {
+                Ok(Some(log)) => json_response(200, &Output::from(log.state().clone())),
+                Ok(None) => json_response(
+                    404,
+                    &ErrorBody {
+                        error: "account not found".to_string(),
+                    },
+                ),
+                Err(error) => json_response(
+                    500,
+                    &ErrorBody {
+                        error: format!("unable to read account: {error}"),
+                    },
+                ),
+            }
+        }
+        _ => json_response(
+            404,
+            &ErrorBody {
+                error: "route not found".to_string(),
+            },
+        ),
+    };
+
+    request.respond(response)
+}
+
+/// Reads the body of a request, unless it is larger than the limit. Bodies announcing
+/// a larger size are refused without being read.
+fn read_body(request: &mut Request, body_limit: u64) -> std::io::Result<Option<Vec<u8>>> {
+    if request
+        .body_length()
+        .is_some_and(|length| length as u64 > body_limit)
+    {
+        return Ok(None);
+    }
+
+    let mut body = Vec::new();
+    request
+        .as_reader()
+        .take(body_limit + 1)
+        .read_to_end(&mut body)?;
+
+    Ok((body.len() as u64 <= body_limit).then_some(body))
+}
+
+fn submit<S: Storage>(
+    processor: &mut PaymentProcessor<S>,
+    body: &[u8],
+) -> Response<Cursor<Vec<u8>>> {
+    let result = match serde_json::from_slice::<Submission>(body) {
+        Ok(Submission::Single(row)) => SubmissionResult::Single(apply(processor, row)),
+        Ok(Submission::Batch(rows)) => {
+            let results = rows.into_iter().map(|row| apply(processor, row));
+            SubmissionResult::Batch(results.collect())
+        }
+        Err(error) => {
+            return json_response(
+                400,
+                &ErrorBody {
+                    error: format!("invalid transaction: {error}"),
+                },
+            )
+        }
+    };
+
+    match processor.flush_storage() {
+        Ok(()) => json_response(200, &result),
+        Err(error) => json_response(
+            500,
+            &ErrorBody {
+                error: format!("unable to persist accounts: {error}"),
+            },
+        ),
+    }
+}
+
+fn json_response<T: Serialize>(status: u16, body: &T) -> Response<Cursor<Vec<u8>>> {
+    let content_type = Header::from_bytes("Content-Type", "application/json")
+        .expect("content type header is valid");
+
+    Response::from_data(serde_json::to_vec(body).expect("response bodies are serializable"))
+        .with_status_code(status)
+        .with_header(content_type)
+}
+
+// ----------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use rust_decimal_macros::dec;
+    use serde_json::json;
+
+    use super::HttpService;
+    use crate::{
+        cli::{Output, TransactionResult},
+        error::ErrorCode,
+        processor::PaymentProcessor,
+        storage::Storage,
+    };
+
+    /// Starts a service on a random local port and returns its base URL.
+    fn start() -> String {
+        start_with(
+            HttpService::bind("127.0.0.1:0", PaymentProcessor::new())
+                .expect("the service should bind to a local port"),
+        )
+    }
+
+    fn start_with<S: Storage + Send + 'static>(service: HttpService<S>) -> String {
+        let address = service.local_addr().expect("the service listens on ip");
+
+        std::thread::spawn(move || service.run());
+
+        format!("http://{address}")
+    }
+
+    #[test]
+    fn submit_single_transaction() {
+        let url = start();
+
+        let result: TransactionResult = ureq::post(&format!("{url}/transactions"))
+            .send_json(json!({ "type": "deposit", "client": 1, "tx": 1, "amount": "2.5" }))
+            .expect("the request should succeed")
+            .into_json()
+            .expect("the response should be a transaction result");
+
+        assert_eq!(
+            result,
+            TransactionResult {
+                client: 1,
+                tx: 1,
+                accepted: true,
+                error: None,
+                code: None,
+                warning: None,
+            }
+        );
+    }
+
+    #[test]
+    fn submit_batch() {
+        let url = start();
+
+        let results: Vec<TransactionResult> = ureq::post(&format!("{url}/transactions"))
+            .send_json(json!([
+                { "type": "deposit", "client": 1, "tx": 1, "amount": "2.5" },
+                { "type": "withdrawal", "client": 1, "tx": 2, "amount": "3.0" },
+                { "type": "withdrawal", "client": 1, "tx": 3 },
+            ]))
+            .expect("the request should succeed")
+            .into_json()
+            .expect("the response should be a list of transaction results");
+
+        assert_eq!(results.len(), 3);
+        assert!(results[0].accepted);
+        assert!(!results[1].accepted);
+        assert!(results[1]
+            .error
+            .as_deref()
+            .is_some_and(|error| error.contains("negative")));
+        assert_eq!(results[1].code, Some(ErrorCode::InsufficientFunds));
+        assert!(!results[2].accepted);
+        assert!(results[2]
+            .error
+            .as_deref()
+            .is_some_and(|error| error.contains("should contain an amount")));
+        assert_eq!(results[2].code, Some(ErrorCode::MissingAmount));
+    }
+
+    #[test]
+    fn get_account() {
+        let url = start();
+
+        ureq::post(&format!("{url}/transactions"))
+            .send_json(json!({ "type": "deposit", "client": 7, "tx": 1, "amount": "2.5" }))
+            .expect("the request should succeed");
+
+        let account: Output = ureq::get(&format!("{url}/accounts/7"))
+            .call()
+            .expect("the account should exist")
+            .into_json()
+            .expect("the response should be an account");
+
+        assert_eq!(
+            account,
+            Output {
+                client: 7,
+                available: dec!(2.5),
+                held: dec!(0),
+                authorized: dec!(0),
+                total: dec!(2.5),
+                locked: false,
+                rejected: None,
+                rejected_amount: None,
+            }
+        );
+    }
+
+    #[test]
+    fn get_unknown_account() {
+        let url = start();
+
+        let response = ureq::get(&format!("{url}/accounts/7")).call();
+
+        assert!(matches!(response, Err(ureq::Error::Status(404, _))));
+    }
+
+    #[test]
+    fn submit_malformed_transaction() {
+        let url = start();
+
+        let response = ureq::post(&format!("{url}/transactions"))
+            .send_json(json!({ "type": "teleport", "client": 1, "tx": 1 }));
+
+        assert!(matches!(response, Err(ureq::Error::Status(400, _))));
+    }
+
+    #[test]
+    fn refuse_oversized_body() {
+        let url = start_with(
+            HttpService::bind("127.0.0.1:0", PaymentProcessor::new())
+                .expect("the service should bind to a local port")
+                .with_body_limit(64),
+        );
+        let deposit = json!({ "type": "deposit", "client": 1, "tx": 1, "amount": "2.5" });
+
+        let response =
+            ureq::post(&format!("{url}/transactions")).send_json(json!([deposit, deposit]));
+
+        assert!(matches!(response, Err(ureq::Error::Status(413, _))));
+
+        let result: TransactionResult = ureq::post(&format!("{url}/transactions"))
+            .send_json(deposit)
+            .expect("bodies within the limit should be accepted")
+            .into_json()
+            .expect("the response should be a transaction result");
+
+        assert!(result.accepted);
+    }
+
+    #[cfg(feature = "sqlite")]
+    #[test]
+    fn persist_submissions() {
+        use crate::storage::SqliteStorage;
+
+        let path =
+            std::env::temp_dir().join(format!("kithmonite-http-{}.sqlite", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+
+        let storage = SqliteStorage::open(&path).expect("the database should open");
+        let url = start_with(
+            HttpService::bind("127.0.0.1:0", PaymentProcessor::with_storage(storage))
+                .expect("the service should bind to a local port"),
+        );
+
+        ureq::post(&format!("{url}/transactions"))
+            .send_json(json!({ "type": "deposit", "client": 7, "tx": 1, "amount": "2.5" }))
+            .expect("the request should succeed");
+
+        // Once answered, the deposit is visible to another connection.
+        let processor = PaymentProcessor::with_storage(
+            SqliteStorage::open(&path).expect("the database should reopen"),
+        );
+        let log = processor
+            .account_log(7)
+            .expect("the account should be readable")
+            .expect("the account should exist");
+
+        assert_eq!(Output::from(log.state().clone()).available, dec!(2.5));
+
+        std::fs::remove_file(&path).expect("the database should be removable");
+    }
+}
@@ -1,3 +1,12 @@
 pub mod account;
 pub mod cli;
+pub mod error;
+pub mod events;
+#[cfg(feature = "grpc")]
+pub mod grpc;
+pub mod http;
+pub mod packed;
 pub mod processor;
+pub mod rules;
+pub mod schema;
+pub mod storage;
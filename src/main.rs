@@ -1,35 +1,211 @@
-use anyhow::{Context, Result};
-use csv::Trim;
-use kithmonite::{account::Transaction, cli, processor::PaymentProcessor};
-use std::env;
+use anyhow::{bail, Context, Result};
+use clap::Parser;
+use csv::{StringRecord, Trim};
+use kithmonite::{
+    cli::{self, AmountPolicy, ErrorPolicy},
+    error::ErrorCode,
+    events::JsonLinesSink,
+    processor::PaymentProcessor,
+    storage::Storage,
+};
+use std::{fs::File, io::BufWriter, path::PathBuf};
+
+/// Proof of concept payment engine. Reads transactions from a CSV file and writes
+/// the resulting accounts to the standard output.
+#[derive(Parser)]
+#[clap(author, version, about, long_about = None)]
+struct Args {
+    /// Path to the transactions CSV file.
+    input: PathBuf,
+    /// Path of a file where account events are written as JSON lines.
+    #[clap(long)]
+    events: Option<PathBuf>,
+    /// Report the number of records kept and the peak memory usage on the standard
+    /// error once every transaction has been processed.
+    #[clap(long)]
+    memory_report: bool,
+    /// What to do with rows that cannot be parsed or are rejected: `strict` aborts on
+    /// the first one, `lenient` skips them all and `max-errors=N` aborts once more than
+    /// N rows were skipped. Skipped rows are reported on the standard error.
+    #[clap(long, default_value = "lenient")]
+    error_policy: ErrorPolicy,
+    /// What to do with rows whose amount does not fit their kind, such as a resolve
+    /// with an amount or a zero deposit: `reject` counts them as invalid rows and `warn`
+    /// reports them on the standard error before applying them.
+    #[clap(long, arg_enum, default_value = "reject")]
+    amount_policy: AmountPolicy,
+    /// Add the number of rejected transactions of each account and the sum of their
+    /// amounts to the output, as the `rejected` and `rejected_amount` columns.
+    #[clap(long)]
+    rejection_columns: bool,
+    /// Path of a database where accounts and their history are kept instead of
+    /// memory. If it already exists, processing resumes from its content and skips
+    /// the rows of the input file it already processed.
+    #[cfg(feature = "sled")]
+    #[clap(long)]
+    storage: Option<PathBuf>,
+    /// Path of a SQLite database where accounts, transactions and disputes are
+    /// recorded as they are processed. If it already exists, processing resumes from
+    /// its content and skips the rows of the input file it already processed.
+    #[cfg(feature = "sqlite")]
+    #[clap(long)]
+    ledger: Option<PathBuf>,
+    #[clap(flatten)]
+    input_columns: cli::InputArgs,
+    #[clap(flatten)]
+    processor: cli::ProcessorArgs,
+}
 
 fn main() -> Result<()> {
-    let transactions_file_path = env::args().nth(1).context("an input file is required")?;
+    let args = Args::parse();
+
+    #[cfg(feature = "sled")]
+    if let Some(path) = &args.storage {
+        let storage = kithmonite::storage::SledStorage::open(path)?;
+        return run(&args, args.processor.processor_with_storage(storage)?);
+    }
+
+    #[cfg(feature = "sqlite")]
+    if let Some(path) = &args.ledger {
+        let storage = kithmonite::storage::SqliteStorage::open(path)?;
+        return run(&args, args.processor.processor_with_storage(storage)?);
+    }
+
+    run(&args, args.processor.processor()?)
+}
 
+fn run<S: Storage>(args: &Args, mut payment_processor: PaymentProcessor<S>) -> Result<()> {
+    let mapping = args
+        .input_columns
+        .mapping()
+        .context("invalid column options")?;
     let mut reader = csv::ReaderBuilder::new()
         .trim(Trim::All)
-        .from_path(transactions_file_path)
+        .has_headers(mapping.has_headers())
+        .from_path(&args.input)
         .context("unable to create csv reader for given file")?;
 
-    let mut payment_processor = PaymentProcessor::new();
+    let header = match mapping.has_headers() {
+        true => reader.headers().context("unable to read header")?.clone(),
+        false => StringRecord::new(),
+    };
+    let mapper = mapping
+        .resolve(&header)
+        .context("unable to map the columns of the input file")?;
+
+    if let Some(path) = &args.events {
+        let file = File::create(path).context("unable to create events file")?;
+        payment_processor.subscribe(JsonLinesSink::new(BufWriter::new(file)));
+    }
+
+    // Rows whose changes were persisted by an earlier run must not be applied twice.
+    let processed_rows = payment_processor
+        .processed_rows()
+        .context("unable to read the number of processed rows")?;
+    if processed_rows > 0 {
+        eprintln!("resuming after row {processed_rows}");
+    }
+
+    let mut errors = 0;
+
+    for (index, record) in reader.records().enumerate() {
+        // Rows are numbered from 1, after the header if there is one.
+        let row_number = index + 1;
+        if row_number as u64 <= processed_rows {
+            continue;
+        }
+        payment_processor
+            .save_processed_rows(row_number as u64)
+            .context("unable to record the number of processed rows")?;
+        let error = match record.and_then(|record| mapper.deserialize(&record)) {
+            Ok(row) => {
+                let result =
+                    cli::apply_with_policy(&mut payment_processor, row, args.amount_policy);
+                let (client, tx) = (result.client, result.tx);
+
+                match (result.code, result.error, result.warning) {
+                    (Some(code), Some(error), _) => {
+                        Some(format!("{code}: client {client}, tx {tx}: {error}"))
+                    }
+                    (Some(code), None, Some(warning)) => {
+                        eprintln!(
+                            "row {row_number}: warning: {code}: client {client}, tx {tx}: {warning}"
+                        );
+                        None
+                    }
+                    _ => None,
+                }
+            }
+            Err(error) => Some(format!(
+                "{}: unable to deserialize transaction: {error}",
+                ErrorCode::InvalidRow
+            )),
+        };
 
-    for row in reader.deserialize::<cli::TransactionRow>() {
-        let transaction = row.context("unable to deserialize transaction")?;
-        let client_id = transaction.client;
-        if let Ok(tx) = Transaction::try_from(transaction) {
-            let _ = payment_processor.process(client_id, tx);
+        let Some(error) = error else {
+            continue;
+        };
+
+        errors += 1;
+        if !args.error_policy.should_abort(errors) {
+            eprintln!("row {row_number}: {error}");
+            continue;
         }
+
+        if let ErrorPolicy::MaxErrors(max) = args.error_policy {
+            bail!("more than {max} invalid rows, the last one being row {row_number}: {error}");
+        }
+        bail!("row {row_number}: {error}");
     }
 
-    let accounts = payment_processor.accounts().map(cli::Output::from);
+    payment_processor.flush_events()?;
+    payment_processor
+        .flush_storage()
+        .context("unable to persist accounts")?;
+
+    if args.memory_report {
+        let stats = payment_processor
+            .storage_stats()
+            .context("unable to count records")?;
+
+        eprintln!("accounts: {}", stats.accounts);
+        eprintln!("retained deposits: {}", stats.deposits);
+        eprintln!("pending authorizations: {}", stats.authorizations);
+        eprintln!("history entries: {}", stats.transactions);
+        eprintln!("rejection entries: {}", stats.rejections);
+        match peak_rss_kib() {
+            Some(kib) => eprintln!("peak rss: {} MiB", kib / 1024),
+            None => eprintln!("peak rss: unavailable"),
+        }
+    }
 
     let mut writer = csv::Writer::from_writer(std::io::stdout());
 
-    for account in accounts {
+    for log in payment_processor.account_logs() {
+        let log = log.context("unable to read account")?;
+        let mut output = cli::Output::from(log.state().clone());
+        if args.rejection_columns {
+            output = output.with_rejections(&log);
+        }
+
         writer
-            .serialize(&account)
+            .serialize(output)
             .context("unable to serialize record")?
     }
 
     Ok(())
 }
+
+/// Reads the peak resident set size of the process, which only Linux exposes.
+fn peak_rss_kib() -> Option<u64> {
+    let status = std::fs::read_to_string("/proc/self/status").ok()?;
+
+    status
+        .lines()
+        .find_map(|line| line.strip_prefix("VmHWM:"))?
+        .trim()
+        .trim_end_matches("kB")
+        .trim()
+        .parse()
+        .ok()
+}
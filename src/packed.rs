@@ -0,0 +1,243 @@
+use std::num::NonZeroU64;
+
+use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize};
+
+use crate::account::{MonetaryValue, Timestamp, Transaction, TransactionKind};
+
+/// Number of bits used to store the scale of a `CompactMoney`.
+const SCALE_BITS: u32 = 3;
+/// Largest scale a `MonetaryValue` can have.
+const MAX_SCALE: u32 = 4;
+/// Exclusive bound of the mantissa of a `CompactMoney`, once rescaled to `MAX_SCALE`.
+const MANTISSA_BOUND: u64 = 1 << (u64::BITS - SCALE_BITS);
+
+#[derive(thiserror::Error, Debug)]
+#[error("amount does not fit in a compact monetary value")]
+pub struct OutOfRange;
+
+// ----------------------------------------------------------------------------
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+/// A `MonetaryValue` packed in 8 bytes, keeping its scale so that it is displayed the
+/// same way once converted back. `Option<CompactMoney>` takes 8 bytes as well.
+///
+/// Equal amounts with different scales have different representations, so amounts
+/// are compared once converted back to `MonetaryValue`.
+///
+/// Only amounts below 2^61 / 10^4, about 230 trillion, can be represented. Since the
+/// bound is checked at the highest scale, every amount below a representable one is
+/// representable too.
+pub struct CompactMoney(NonZeroU64);
+
+impl CompactMoney {
+    pub const ZERO: Self = match NonZeroU64::new(1) {
+        Some(encoded) => Self(encoded),
+        None => unreachable!(),
+    };
+
+    fn mantissa(self) -> u64 {
+        (self.0.get() - 1) >> SCALE_BITS
+    }
+
+    fn scale(self) -> u32 {
+        ((self.0.get() - 1) & ((1 << SCALE_BITS) - 1)) as u32
+    }
+}
+
+impl Default for CompactMoney {
+    fn default() -> Self {
+        Self::ZERO
+    }
+}
+
+impl TryFrom<MonetaryValue> for CompactMoney {
+    type Error = OutOfRange;
+
+    fn try_from(value: MonetaryValue) -> Result<Self, Self::Error> {
+        let decimal = Decimal::from(value);
+        let scale = decimal.scale();
+
+        let mut rescaled = decimal;
+        rescaled.rescale(MAX_SCALE);
+        if scale > MAX_SCALE || rescaled.mantissa() >= i128::from(MANTISSA_BOUND) {
+            return Err(OutOfRange);
+        }
+
+        let mantissa = u64::try_from(decimal.mantissa()).map_err(|_| OutOfRange)?;
+        let encoded = (mantissa << SCALE_BITS | u64::from(scale)) + 1;
+
+        Ok(Self(
+            NonZeroU64::new(encoded).expect("encoded values are offset by one"),
+        ))
+    }
+}
+
+impl From<CompactMoney> for MonetaryValue {
+    fn from(value: CompactMoney) -> Self {
+        let mantissa = i64::try_from(value.mantissa()).expect("mantissas fit in 61 bits");
+
+        Decimal::new(mantissa, value.scale())
+            .try_into()
+            .expect("compact values are positive with at most 4 decimal places")
+    }
+}
+
+// ----------------------------------------------------------------------------
+
+const DEPOSIT: u8 = 0;
+const WITHDRAWAL: u8 = 1;
+const DISPUTE: u8 = 2;
+const RESOLVE: u8 = 3;
+const CHARGEBACK: u8 = 4;
+const TRANSFER: u8 = 5;
+const REFUND: u8 = 6;
+const AUTHORIZE: u8 = 7;
+const CAPTURE: u8 = 8;
+const VOID: u8 = 9;
+
+#[derive(Debug, Clone, Copy)]
+/// A `Transaction` packed in 24 bytes instead of 48, for transactions that are kept
+/// around in large numbers.
+pub struct PackedTransaction {
+    /// Meaningless unless `has_timestamp` is set.
+    timestamp: u64,
+    amount: Option<CompactMoney>,
+    id: u32,
+    /// Recipient of a transfer, meaningless for other kinds.
+    counterparty: u16,
+    kind: u8,
+    has_timestamp: bool,
+}
+
+impl TryFrom<Transaction> for PackedTransaction {
+    type Error = OutOfRange;
+
+    fn try_from(transaction: Transaction) -> Result<Self, Self::Error> {
+        use TransactionKind::*;
+
+        let (kind, amount, counterparty) = match transaction.kind {
+            Deposit(amount) => (DEPOSIT, Some(amount), 0),
+            Withdrawal(amount) => (WITHDRAWAL, Some(amount), 0),
+            Dispute(amount) => (DISPUTE, amount, 0),
+            Resolve => (RESOLVE, None, 0),
+            Chargeback => (CHARGEBACK, None, 0),
+            Transfer { recipient, amount } => (TRANSFER, Some(amount), recipient.into()),
+            Refund(amount) => (REFUND, amount, 0),
+            Authorize(amount) => (AUTHORIZE, Some(amount), 0),
+            Capture(amount) => (CAPTURE, amount, 0),
+            Void => (VOID, None, 0),
+        };
+
+        Ok(Self {
+            timestamp: transaction.timestamp.map(u64::from).unwrap_or_default(),
+            amount: amount.map(CompactMoney::try_from).transpose()?,
+            id: transaction.id.into(),
+            counterparty,
+            kind,
+            has_timestamp: transaction.timestamp.is_some(),
+        })
+    }
+}
+
+impl From<PackedTransaction> for Transaction {
+    fn from(packed: PackedTransaction) -> Self {
+        use TransactionKind::*;
+
+        let optional_amount = packed.amount.map(MonetaryValue::from);
+        let amount = || optional_amount.expect("packed transactions of this kind have an amount");
+
+        let kind = match packed.kind {
+            DEPOSIT => Deposit(amount()),
+            WITHDRAWAL => Withdrawal(amount()),
+            DISPUTE => Dispute(optional_amount),
+            RESOLVE => Resolve,
+            CHARGEBACK => Chargeback,
+            TRANSFER => Transfer {
+                recipient: packed.counterparty.into(),
+                amount: amount(),
+            },
+            REFUND => Refund(optional_amount),
+            AUTHORIZE => Authorize(amount()),
+            CAPTURE => Capture(optional_amount),
+            VOID => Void,
+            other => unreachable!("unknown packed transaction kind {other}"),
+        };
+
+        Self {
+            id: packed.id.into(),
+            kind,
+            timestamp: packed
+                .has_timestamp
+                .then(|| Timestamp::from(packed.timestamp)),
+        }
+    }
+}
+
+// ----------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use std::mem::size_of;
+
+    use rust_decimal::Decimal;
+    use rust_decimal_macros::dec;
+
+    use super::{CompactMoney, PackedTransaction};
+    use crate::account::{MonetaryValue, Transaction, TransactionKind};
+
+    fn money(value: Decimal) -> MonetaryValue {
+        value.try_into().expect("value is a valid amount")
+    }
+
+    #[test]
+    fn compact_money_round_trip() {
+        for value in [
+            dec!(0),
+            dec!(0.0000),
+            dec!(1.5),
+            dec!(1.5000),
+            dec!(42),
+            dec!(0.0001),
+            dec!(230584300921369.3951),
+        ] {
+            let compact = CompactMoney::try_from(money(value)).expect("value fits");
+            let restored = Decimal::from(MonetaryValue::from(compact));
+
+            assert_eq!(restored, value);
+            assert_eq!(restored.to_string(), value.to_string());
+        }
+    }
+
+    #[test]
+    fn compact_money_out_of_range() {
+        assert!(CompactMoney::try_from(money(dec!(230584300921369.3952))).is_err());
+        assert!(CompactMoney::try_from(money(dec!(1000000000000000))).is_err());
+    }
+
+    #[test]
+    fn packed_transaction_round_trip() {
+        let amount = money(dec!(12.25));
+        let transactions = [
+            Transaction::new(1, TransactionKind::Deposit(amount)).with_timestamp(0),
+            Transaction::new(2, TransactionKind::Dispute(None)),
+            Transaction::new(3, TransactionKind::Dispute(Some(amount))).with_timestamp(7),
+            Transaction::new(
+                u32::MAX,
+                TransactionKind::Transfer {
+                    recipient: u16::MAX.into(),
+                    amount,
+                },
+            ),
+            Transaction::new(5, TransactionKind::Void).with_timestamp(u64::MAX),
+        ];
+
+        for transaction in transactions {
+            let packed = PackedTransaction::try_from(transaction).expect("amounts fit");
+            assert_eq!(Transaction::from(packed), transaction);
+        }
+
+        assert_eq!(size_of::<Option<CompactMoney>>(), 8);
+        assert_eq!(size_of::<PackedTransaction>(), 24);
+    }
+}
@@ -1,31 +1,206 @@
-use std::collections::HashMap;
+use std::time::Duration;
 
-use crate::account::{Account, ClientId, Transaction, TransactionError};
+use serde::{Deserialize, Serialize};
 
+use crate::{
+    account::{
+        Account, ClientId, MonetaryValue, Timestamp, Transaction, TransactionError, TransactionId,
+        TransactionKind,
+    },
+    error::ErrorCode,
+    events::{AccountEvent, EventSubscriber},
+    packed::{CompactMoney, OutOfRange, PackedTransaction},
+    rules::{self, RuleSet, RuleViolation},
+    storage::{MemoryStorage, Storage, StorageError, StorageStats},
+};
+
+/// Number of retained deposits below which an account is never compacted.
+const MIN_COMPACTION_THRESHOLD: u64 = 64;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct AccountLog {
-    state: Account,
-    history: Vec<Transaction>,
+    pub(crate) state: Account,
+    /// Timestamp of the most recent accepted transaction that carried one.
+    pub(crate) last_timestamp: Option<Timestamp>,
+    /// Number of deposits recorded so far, used to order them.
+    pub(crate) deposits: u64,
+    /// Number of deposit records currently kept in the storage.
+    pub(crate) retained_deposits: u64,
+    /// Number of retained deposits at which the next compaction happens.
+    pub(crate) next_compaction: u64,
+    /// Number of transactions of the account rejected by the processor.
+    pub(crate) rejected: u64,
+    /// Sum of the amounts carried by the rejected transactions.
+    pub(crate) rejected_amount: MonetaryValue,
+    /// Whether each of the last 32 accepted transactions was a debit, the most recent
+    /// in the lowest bit.
+    pub(crate) recent_debits: u32,
+    /// Number of disputes accepted so far.
+    pub(crate) disputes: u64,
+    /// Day of `daily_volume`, in days since the Unix epoch.
+    pub(crate) volume_day: u64,
+    /// Sum of the timestamped debits of `volume_day`.
+    pub(crate) daily_volume: MonetaryValue,
 }
 
 impl AccountLog {
     pub fn new(client_id: impl Into<ClientId>) -> Self {
         Self {
             state: Account::new(client_id),
-            history: Vec::new(),
+            last_timestamp: None,
+            deposits: 0,
+            retained_deposits: 0,
+            next_compaction: MIN_COMPACTION_THRESHOLD,
+            rejected: 0,
+            rejected_amount: MonetaryValue::default(),
+            recent_debits: 0,
+            disputes: 0,
+            volume_day: 0,
+            daily_volume: MonetaryValue::default(),
+        }
+    }
+
+    /// Returns the current state of the account.
+    pub fn state(&self) -> &Account {
+        &self.state
+    }
+
+    /// Returns the number of transactions of the account that were rejected.
+    pub fn rejected(&self) -> u64 {
+        self.rejected
+    }
+
+    /// Returns the sum of the amounts carried by the rejected transactions.
+    pub fn rejected_amount(&self) -> MonetaryValue {
+        self.rejected_amount
+    }
+
+    /// Counts the debits among the last `count` accepted transactions.
+    pub(crate) fn recent_debits(&self, count: u32) -> u32 {
+        let mask = u32::MAX
+            .checked_shr(u32::BITS.saturating_sub(count))
+            .unwrap_or(0);
+        (self.recent_debits & mask).count_ones()
+    }
+
+    /// Returns the sum of the timestamped debits of the given day.
+    pub(crate) fn daily_volume(&self, day: u64) -> MonetaryValue {
+        match self.volume_day == day {
+            true => self.daily_volume,
+            false => MonetaryValue::default(),
+        }
+    }
+
+    /// Updates what the rules are evaluated against with an accepted transaction.
+    /// `debit` is what the transaction took out of the account at the request of the
+    /// client.
+    fn record_activity(&mut self, transaction: &Transaction, debit: Option<MonetaryValue>) {
+        self.recent_debits = self.recent_debits << 1 | u32::from(debit.is_some());
+
+        if let TransactionKind::Dispute(_) = transaction.kind {
+            self.disputes += 1;
+        }
+
+        if let (Some(debit), Some(timestamp)) = (debit, transaction.timestamp) {
+            self.daily_volume = self.daily_volume(timestamp.day()).saturating_add(debit);
+            self.volume_day = timestamp.day();
+        }
+    }
+
+    /// Returns a `NonMonotonicTimestamp` error if the timestamp is older than the
+    /// last one seen on this account.
+    fn check_timestamp(&self, timestamp: Option<Timestamp>) -> PaymentProcessingResult {
+        match (self.last_timestamp, timestamp) {
+            (Some(last), Some(current)) if current < last => {
+                Err(PaymentProcessingError::NonMonotonicTimestamp)
+            }
+            _ => Ok(()),
         }
     }
 }
 
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+/// Tracks which part of a deposit can still be disputed or refunded.
+pub struct DepositRecord {
+    /// Portion of the deposit that is neither under dispute, charged back nor refunded.
+    pub(crate) disputable: CompactMoney,
+    /// Amount held by the dispute currently open on the deposit, if any.
+    pub(crate) disputed: Option<CompactMoney>,
+    pub(crate) timestamp: Option<Timestamp>,
+    /// Position of the deposit among the deposits of the account.
+    pub(crate) sequence: u64,
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+/// Funds reserved by a pending authorization.
+pub struct AuthorizationRecord {
+    pub(crate) amount: CompactMoney,
+    pub(crate) timestamp: Option<Timestamp>,
+}
+
+#[derive(Debug, PartialEq, Clone, Copy, Serialize, Deserialize)]
+/// A transaction rejected by the processor, and why.
+pub struct RejectedTransaction {
+    pub transaction: Transaction,
+    pub code: ErrorCode,
+}
+
 // ----------------------------------------------------------------------------
 
 #[derive(thiserror::Error, Debug)]
 pub enum PaymentProcessingError {
     #[error("an error occured during the underlying transaction")]
     TransactionError(#[from] TransactionError),
+    #[error("an error occured in the storage backend")]
+    StorageError(#[from] StorageError),
     #[error("multiple disputes on the same transaction")]
     DisputeAlreadyExists,
     #[error("relevant transaction is not in dispute")]
     NoDispute,
+    #[error("transaction is older than the last transaction of the account")]
+    NonMonotonicTimestamp,
+    #[error("the dispute window of the relevant transaction has expired")]
+    DisputeWindowExpired,
+    #[error("relevant transaction does not exist or cannot be disputed")]
+    UnknownTransaction,
+    #[error("disputed amount exceeds the undisputed part of the transaction")]
+    DisputeAmountExceeded,
+    #[error("a transfer cannot be made to the sending account")]
+    SelfTransfer,
+    #[error("refunded amount exceeds the unrefunded part of the transaction")]
+    RefundAmountExceeded,
+    #[error("multiple authorizations with the same transaction id")]
+    AuthorizationAlreadyExists,
+    #[error("relevant authorization does not exist or has expired")]
+    NoAuthorization,
+    #[error("captured amount exceeds the authorized amount")]
+    CaptureAmountExceeded,
+    #[error("amount is too large to be processed")]
+    AmountOutOfRange(#[from] OutOfRange),
+    #[error(transparent)]
+    RuleViolation(#[from] RuleViolation),
+}
+
+impl PaymentProcessingError {
+    pub fn code(&self) -> ErrorCode {
+        match self {
+            Self::TransactionError(error) => error.code(),
+            Self::StorageError(_) => ErrorCode::StorageFailure,
+            Self::DisputeAlreadyExists => ErrorCode::DuplicateDispute,
+            Self::NoDispute => ErrorCode::NoDispute,
+            Self::NonMonotonicTimestamp => ErrorCode::NonMonotonicTimestamp,
+            Self::DisputeWindowExpired => ErrorCode::DisputeWindowExpired,
+            Self::UnknownTransaction => ErrorCode::UnknownTransaction,
+            Self::DisputeAmountExceeded => ErrorCode::DisputeAmountExceeded,
+            Self::SelfTransfer => ErrorCode::SelfTransfer,
+            Self::RefundAmountExceeded => ErrorCode::RefundAmountExceeded,
+            Self::AuthorizationAlreadyExists => ErrorCode::DuplicateAuthorization,
+            Self::NoAuthorization => ErrorCode::NoAuthorization,
+            Self::CaptureAmountExceeded => ErrorCode::CaptureAmountExceeded,
+            Self::AmountOutOfRange(_) => ErrorCode::AmountOutOfRange,
+            Self::RuleViolation(violation) => violation.code(),
+        }
+    }
 }
 
 type PaymentProcessingResult = Result<(), PaymentProcessingError>;
@@ -34,105 +209,795 @@ type PaymentProcessingResult = Result<(), PaymentProcessingError>;
 
 /// A state machine that takes client transactions and incrementally builds accounts
 /// from the transaction history.
-pub struct PaymentProcessor {
-    accounts: HashMap<ClientId, AccountLog>,
+pub struct PaymentProcessor<S: Storage = MemoryStorage> {
+    storage: S,
+    /// Maximum delay between a transaction and its dispute. Disputes are not
+    /// time-bound when unset.
+    dispute_window: Option<Duration>,
+    /// Delay after which pending authorizations are voided. Authorizations never
+    /// expire when unset.
+    authorization_expiry: Option<Duration>,
+    /// Number of most recent deposits of an account that can be disputed or
+    /// refunded. Every deposit can be when unset.
+    deposit_retention: Option<u64>,
+    /// Whether the history and the deposits that cannot be referenced anymore are
+    /// dropped instead of being kept around.
+    compaction: bool,
+    /// Rules evaluated before transactions are applied.
+    rules: RuleSet,
+    subscribers: Vec<Box<dyn EventSubscriber + Send>>,
+}
+
+impl Default for PaymentProcessor {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl PaymentProcessor {
+    /// Creates a processor keeping every account and transaction in memory.
     pub fn new() -> Self {
+        Self::with_storage(MemoryStorage::default())
+    }
+}
+
+impl<S: Storage> PaymentProcessor<S> {
+    /// Creates a processor keeping accounts and transactions in the given storage.
+    /// Accounts already present in the storage are picked up where they were left.
+    pub fn with_storage(storage: S) -> Self {
         Self {
-            accounts: HashMap::new(),
+            storage,
+            dispute_window: None,
+            authorization_expiry: None,
+            deposit_retention: None,
+            compaction: false,
+            rules: RuleSet::default(),
+            subscribers: Vec::new(),
         }
     }
 
+    /// Rejects disputes filed more than `window` after the disputed transaction.
+    /// The window is only enforced when both transactions carry a timestamp.
+    pub fn with_dispute_window(mut self, window: Duration) -> Self {
+        self.dispute_window = Some(window);
+        self
+    }
+
+    /// Voids authorizations that have been pending for longer than `expiry`. Expired
+    /// authorizations are swept whenever a timestamped transaction reaches the account.
+    pub fn with_authorization_expiry(mut self, expiry: Duration) -> Self {
+        self.authorization_expiry = Some(expiry);
+        self
+    }
+
+    /// Only lets the `count` most recent deposits of each account be disputed or
+    /// refunded. Older deposits are treated as unknown transactions.
+    pub fn with_deposit_retention(mut self, count: u64) -> Self {
+        self.deposit_retention = Some(count);
+        self
+    }
+
+    /// Keeps only what future transactions can reference: accepted transactions are
+    /// not recorded in the history anymore, and deposits are forgotten once they are
+    /// fully disputed or refunded, or fall out of the deposit retention or the
+    /// dispute window.
+    ///
+    /// A deposit reusing the id of a forgotten deposit is disputable on its own.
+    pub fn with_compaction(mut self) -> Self {
+        self.compaction = true;
+        self
+    }
+
+    /// Evaluates the given rules before applying the transactions submitted by the
+    /// owner of an account, rejecting those that violate one of them.
+    pub fn with_rules(mut self, rules: RuleSet) -> Self {
+        self.rules = rules;
+        self
+    }
+
+    /// Sends account events to the given subscriber as transactions are processed.
+    pub fn subscribe(&mut self, subscriber: impl EventSubscriber + Send + 'static) {
+        self.subscribers.push(Box::new(subscriber));
+    }
+
     /// Takes a transaction and applies it to the relevant customer account. The
-    /// transaction will be persisted until the processor is dropped.
+    /// transaction will be persisted in the storage.
     pub fn process<I>(&mut self, client_id: I, transaction: Transaction) -> PaymentProcessingResult
     where
         I: Into<ClientId> + Copy,
     {
-        let account = self
-            .accounts
-            .entry(client_id.into())
-            .or_insert_with(|| AccountLog::new(client_id));
+        let outcome = self.process_and_notify(client_id.into(), transaction);
+        if let Err(error) = &outcome {
+            self.record_rejection(client_id.into(), transaction, error)?;
+        }
+        self.storage.checkpoint()?;
+
+        outcome
+    }
+
+    /// Counts a transaction rejected before it could be processed, such as a row with
+    /// a missing or zero amount, on the account of its client. Its amount is added to
+    /// the rejected amount unless it is negative. Unlike the transactions rejected by
+    /// `process`, it is not recorded with the rejections of the account.
+    pub fn reject(
+        &mut self,
+        client_id: impl Into<ClientId>,
+        amount: Option<MonetaryValue>,
+    ) -> Result<(), StorageError> {
+        self.count_rejection(client_id.into(), amount)?;
+        self.storage.checkpoint()
+    }
+
+    /// Counts a rejected transaction on the account of its client, and records it
+    /// unless the history is compacted. Failures of the storage itself are not
+    /// recorded.
+    fn record_rejection(
+        &mut self,
+        client_id: ClientId,
+        transaction: Transaction,
+        error: &PaymentProcessingError,
+    ) -> Result<(), StorageError> {
+        if let PaymentProcessingError::StorageError(_) = error {
+            return Ok(());
+        }
+
+        self.count_rejection(client_id, transaction.kind.amount())?;
+
+        if !self.compaction {
+            self.storage.append_rejection(
+                client_id,
+                &RejectedTransaction {
+                    transaction,
+                    code: error.code(),
+                },
+            )?;
+        }
+
+        Ok(())
+    }
 
+    /// Adds a rejected transaction to the counters of an account. Like any other
+    /// transaction submitted by a client, it opens the account if it does not exist
+    /// yet.
+    fn count_rejection(
+        &mut self,
+        client_id: ClientId,
+        amount: Option<MonetaryValue>,
+    ) -> Result<(), StorageError> {
+        let mut account = self
+            .storage
+            .account(client_id)?
+            .unwrap_or_else(|| AccountLog::new(client_id));
+
+        account.rejected += 1;
+        if let Some(amount) = amount {
+            account.rejected_amount = account.rejected_amount.saturating_add(amount);
+        }
+        self.storage.save_account(&account)
+    }
+
+    /// Applies a transaction and emits the events describing what it changed.
+    fn process_and_notify(
+        &mut self,
+        client_id: ClientId,
+        transaction: Transaction,
+    ) -> PaymentProcessingResult {
+        if self.subscribers.is_empty() {
+            return self.apply(client_id, transaction).map(|_| ());
+        }
+
+        let tx = transaction.id;
+        let affected_clients = match transaction.kind {
+            TransactionKind::Transfer { recipient, .. } => vec![client_id, recipient],
+            _ => vec![client_id],
+        };
+        let snapshots = affected_clients
+            .into_iter()
+            .map(|client_id| Ok((client_id, self.snapshot(client_id)?)))
+            .collect::<Result<Vec<_>, StorageError>>()?;
+
+        let outcome = self.apply(client_id, transaction);
+
+        if let Ok(Some(event)) = &outcome {
+            self.emit(event);
+        }
+
+        // Balances may change even if the transaction is rejected, e.g. when stale
+        // authorizations are voided before it is applied.
+        for (client_id, before) in snapshots {
+            self.emit_changes(client_id, Some(tx), before)?;
+        }
+
+        outcome.map(|_| ())
+    }
+
+    /// Applies a transaction, returning the dispute event it caused if any. The
+    /// account is only saved once the transaction has been fully applied.
+    fn apply(
+        &mut self,
+        client_id: ClientId,
+        transaction: Transaction,
+    ) -> Result<Option<AccountEvent>, PaymentProcessingError> {
         use crate::account::TransactionKind::*;
-        match transaction.kind {
+
+        // Records keep amounts in compact form, which bounds the amounts that can be
+        // processed.
+        PackedTransaction::try_from(transaction)?;
+
+        // Transfers involve two accounts and are applied separately.
+        if let Transfer { recipient, amount } = transaction.kind {
+            return self
+                .transfer(client_id, recipient, amount, transaction)
+                .map(|_| None);
+        }
+
+        let mut account = self.load_or_create(client_id)?;
+
+        account.check_timestamp(transaction.timestamp)?;
+        let timestamp = transaction.timestamp;
+
+        if let (Some(expiry), Some(now)) = (self.authorization_expiry, timestamp) {
+            self.expire_account_authorizations(&mut account, now, expiry)?;
+        }
+
+        let event = match transaction.kind {
             Deposit(amount) => {
                 account.state.deposit(amount)?;
-                account.history.push(transaction);
+                // Only the first deposit with a given id can be disputed.
+                if self.storage.deposit(client_id, transaction.id)?.is_none() {
+                    self.storage.save_deposit(
+                        client_id,
+                        transaction.id,
+                        &DepositRecord {
+                            disputable: amount.try_into()?,
+                            disputed: None,
+                            timestamp,
+                            sequence: account.deposits,
+                        },
+                    )?;
+                    account.deposits += 1;
+                    account.retained_deposits += 1;
+                }
+                None
             }
             Withdrawal(amount) => {
-                account.state.withdraw(amount)?;
-                account.history.push(transaction);
+                let mut applied = account.state.clone();
+                applied.withdraw(amount)?;
+                self.check_rules(&mut account, &transaction, applied)?;
+                None
             }
-            Dispute => {
-                let dispute_exists = account
-                    .history
-                    .iter()
-                    .any(|tx| tx.id == transaction.id && matches!(tx.kind, Dispute));
+            Dispute(amount) => {
+                let mut record = self
+                    .retained_deposit(&account, transaction.id)?
+                    .ok_or(PaymentProcessingError::UnknownTransaction)?;
 
-                if dispute_exists {
+                if record.disputed.is_some() {
                     return Err(PaymentProcessingError::DisputeAlreadyExists);
                 }
 
-                let disputed_transaction =
-                    account.history.iter().find(|tx| tx.id == transaction.id);
+                let disputable = MonetaryValue::from(record.disputable);
 
-                if let Some(Deposit(disputed_amount)) = disputed_transaction.map(|tx| tx.kind) {
-                    account.state.hold(disputed_amount)?;
-                    account.history.push(transaction);
+                if let (Some(window), Some(disputed_at), Some(filed_at)) =
+                    (self.dispute_window, record.timestamp, timestamp)
+                {
+                    if filed_at
+                        .duration_since(disputed_at)
+                        .is_some_and(|elapsed| elapsed > window)
+                    {
+                        return Err(PaymentProcessingError::DisputeWindowExpired);
+                    }
+                }
+
+                // Without an explicit amount, whatever is left of the deposit is disputed.
+                let disputed_amount = match amount {
+                    Some(amount) if amount > disputable => {
+                        return Err(PaymentProcessingError::DisputeAmountExceeded)
+                    }
+                    Some(amount) => amount,
+                    None if disputable == MonetaryValue::default() => {
+                        return Err(PaymentProcessingError::DisputeAlreadyExists)
+                    }
+                    None => disputable,
                 };
+
+                let mut applied = account.state.clone();
+                applied.hold(disputed_amount)?;
+                self.check_rules(&mut account, &transaction, applied)?;
+
+                record.disputable = disputable.overdrawing_sub(disputed_amount)?.try_into()?;
+                record.disputed = Some(disputed_amount.try_into()?);
+                self.save_deposit(&mut account, transaction.id, &record)?;
+
+                Some(AccountEvent::DisputeOpened {
+                    client: client_id,
+                    tx: transaction.id,
+                    amount: disputed_amount,
+                })
             }
             Resolve => {
-                let dispute_exists = account
-                    .history
-                    .iter()
-                    .any(|tx| tx.id == transaction.id && matches!(tx.kind, Dispute));
-
-                if !dispute_exists {
-                    return Err(PaymentProcessingError::NoDispute);
-                }
+                let mut record = self
+                    .storage
+                    .deposit(client_id, transaction.id)?
+                    .ok_or(PaymentProcessingError::NoDispute)?;
+                let disputed_amount = record
+                    .disputed
+                    .map(MonetaryValue::from)
+                    .ok_or(PaymentProcessingError::NoDispute)?;
 
-                let disputed_transaction =
-                    account.history.iter().find(|tx| tx.id == transaction.id);
+                // The released funds are the client's again, so they can be disputed
+                // or refunded again.
+                account.state.release(disputed_amount)?;
+                record.disputable = MonetaryValue::from(record.disputable)
+                    .saturating_add(disputed_amount)
+                    .try_into()?;
+                record.disputed = None;
+                self.save_deposit(&mut account, transaction.id, &record)?;
 
-                if let Some(Deposit(disputed_amount)) = disputed_transaction.map(|tx| tx.kind) {
-                    account.state.release(disputed_amount)?;
-                    account.history.push(transaction);
-                };
+                Some(AccountEvent::DisputeResolved {
+                    client: client_id,
+                    tx: transaction.id,
+                    amount: disputed_amount,
+                })
             }
             Chargeback => {
-                let dispute_exists = account
-                    .history
-                    .iter()
-                    .any(|tx| tx.id == transaction.id && matches!(tx.kind, Dispute));
+                let mut record = self
+                    .storage
+                    .deposit(client_id, transaction.id)?
+                    .ok_or(PaymentProcessingError::NoDispute)?;
+                let disputed_amount = record
+                    .disputed
+                    .map(MonetaryValue::from)
+                    .ok_or(PaymentProcessingError::NoDispute)?;
 
-                if !dispute_exists {
-                    return Err(PaymentProcessingError::NoDispute);
+                account.state.chargeback(disputed_amount)?;
+                record.disputed = None;
+                self.save_deposit(&mut account, transaction.id, &record)?;
+
+                Some(AccountEvent::ChargedBack {
+                    client: client_id,
+                    tx: transaction.id,
+                    amount: disputed_amount,
+                })
+            }
+            Refund(amount) => {
+                let mut record = self
+                    .retained_deposit(&account, transaction.id)?
+                    .ok_or(PaymentProcessingError::UnknownTransaction)?;
+
+                // Funds that are under dispute or were charged back cannot be refunded as well.
+                let disputable = MonetaryValue::from(record.disputable);
+                let refunded_amount = match amount {
+                    Some(amount) if amount > disputable => {
+                        return Err(PaymentProcessingError::RefundAmountExceeded)
+                    }
+                    Some(amount) => amount,
+                    None if disputable == MonetaryValue::default() => {
+                        return Err(PaymentProcessingError::RefundAmountExceeded)
+                    }
+                    None => disputable,
+                };
+
+                account.state.withdraw(refunded_amount)?;
+                record.disputable = disputable.overdrawing_sub(refunded_amount)?.try_into()?;
+                self.save_deposit(&mut account, transaction.id, &record)?;
+                None
+            }
+            Authorize(amount) => {
+                if self
+                    .storage
+                    .authorization(client_id, transaction.id)?
+                    .is_some()
+                {
+                    return Err(PaymentProcessingError::AuthorizationAlreadyExists);
                 }
 
-                let disputed_transaction =
-                    account.history.iter().find(|tx| tx.id == transaction.id);
+                account.state.authorize(amount)?;
+                self.storage.save_authorization(
+                    client_id,
+                    transaction.id,
+                    &AuthorizationRecord {
+                        amount: amount.try_into()?,
+                        timestamp,
+                    },
+                )?;
+                None
+            }
+            Capture(amount) => {
+                let authorization = self
+                    .storage
+                    .authorization(client_id, transaction.id)?
+                    .ok_or(PaymentProcessingError::NoAuthorization)?;
 
-                if let Some(Deposit(disputed_amount)) = disputed_transaction.map(|tx| tx.kind) {
-                    account.state.chargeback(disputed_amount)?;
-                    account.history.push(transaction);
+                let authorized_amount = MonetaryValue::from(authorization.amount);
+                let captured_amount = match amount {
+                    Some(amount) if amount > authorized_amount => {
+                        return Err(PaymentProcessingError::CaptureAmountExceeded)
+                    }
+                    Some(amount) => amount,
+                    None => authorized_amount,
                 };
+                let released_amount = authorized_amount.overdrawing_sub(captured_amount)?;
+
+                account.state.capture(captured_amount)?;
+                account.state.void(released_amount)?;
+                self.storage
+                    .remove_authorization(client_id, transaction.id)?;
+                None
+            }
+            Void => {
+                let authorization = self
+                    .storage
+                    .authorization(client_id, transaction.id)?
+                    .ok_or(PaymentProcessingError::NoAuthorization)?;
+
+                account.state.void(authorization.amount.into())?;
+                self.storage
+                    .remove_authorization(client_id, transaction.id)?;
+                None
+            }
+            Transfer { .. } => {
+                unreachable!("transfers are applied by `PaymentProcessor::transfer`")
+            }
+        };
+
+        if timestamp.is_some() {
+            account.last_timestamp = timestamp;
+        }
+        account.record_activity(&transaction, rules::debit(transaction.kind));
+
+        if self.compaction {
+            self.compact(&mut account)?;
+        } else {
+            self.storage.append_history(client_id, &transaction)?;
+        }
+        self.storage.save_account(&account)?;
+
+        Ok(event)
+    }
+
+    /// Debits the sender and credits the recipient. Both accounts must be unlocked and
+    /// the transfer is either fully applied or not at all.
+    fn transfer(
+        &mut self,
+        sender: ClientId,
+        recipient: ClientId,
+        amount: MonetaryValue,
+        transaction: Transaction,
+    ) -> PaymentProcessingResult {
+        if sender == recipient {
+            return Err(PaymentProcessingError::SelfTransfer);
+        }
+
+        let mut sender_log = self.load_or_create(sender)?;
+        // The recipient did not submit the transfer, so its account is only created
+        // once it is credited.
+        let mut recipient_log = self
+            .storage
+            .account(recipient)?
+            .unwrap_or_else(|| AccountLog::new(recipient));
+
+        sender_log.check_timestamp(transaction.timestamp)?;
+        recipient_log.check_timestamp(transaction.timestamp)?;
+
+        if let (Some(expiry), Some(now)) = (self.authorization_expiry, transaction.timestamp) {
+            self.expire_account_authorizations(&mut sender_log, now, expiry)?;
+            self.expire_account_authorizations(&mut recipient_log, now, expiry)?;
+        }
+
+        let mut applied = sender_log.state.clone();
+        let mut credited = recipient_log.state.clone();
+        applied.transfer(&mut credited, amount)?;
+        self.check_rules(&mut sender_log, &transaction, applied)?;
+        recipient_log.state = credited;
+
+        for (log, debit) in [(&mut sender_log, Some(amount)), (&mut recipient_log, None)] {
+            if transaction.timestamp.is_some() {
+                log.last_timestamp = transaction.timestamp;
+            }
+            log.record_activity(&transaction, debit);
+
+            if !self.compaction {
+                self.storage
+                    .append_history(log.state.client_id, &transaction)?;
+            }
+            self.storage.save_account(log)?;
+        }
+
+        Ok(())
+    }
+
+    /// Evaluates the rules against a transaction submitted by the owner of the account
+    /// once it passed validation, `applied` being the balances it leads to. These
+    /// balances replace those of the account only if no rule is violated, and the
+    /// account is locked right away if the violated rule says so. Rules only limit
+    /// debits and disputes, so other transactions are not evaluated.
+    fn check_rules(
+        &mut self,
+        account: &mut AccountLog,
+        transaction: &Transaction,
+        applied: Account,
+    ) -> PaymentProcessingResult {
+        let Err(violation) = self.rules.check(account, transaction) else {
+            account.state = applied;
+            return Ok(());
+        };
+
+        if violation.locks_account() && !account.state.locked {
+            account.state.locked = true;
+            self.storage.save_account(account)?;
+        }
+
+        Err(violation.into())
+    }
+
+    /// Returns the record of a deposit unless it is too old to be referenced.
+    fn retained_deposit(
+        &self,
+        account: &AccountLog,
+        tx: TransactionId,
+    ) -> Result<Option<DepositRecord>, StorageError> {
+        Ok(self
+            .storage
+            .deposit(account.state.client_id, tx)?
+            .filter(|record| self.within_retention(account, record)))
+    }
+
+    fn within_retention(&self, account: &AccountLog, record: &DepositRecord) -> bool {
+        self.deposit_retention
+            .is_none_or(|count| record.sequence + count >= account.deposits)
+    }
+
+    /// Saves the record of a deposit, forgetting it instead when compacting and
+    /// nothing is left to dispute or refund.
+    fn save_deposit(
+        &mut self,
+        account: &mut AccountLog,
+        tx: TransactionId,
+        record: &DepositRecord,
+    ) -> Result<(), StorageError> {
+        let client_id = account.state.client_id;
+
+        if self.compaction
+            && record.disputed.is_none()
+            && MonetaryValue::from(record.disputable) == MonetaryValue::default()
+        {
+            account.retained_deposits -= 1;
+            return self.storage.remove_deposit(client_id, tx);
+        }
+
+        self.storage.save_deposit(client_id, tx, record)
+    }
+
+    /// Forgets the deposits of an account that cannot be referenced anymore. Deposits
+    /// are only swept once their number doubled since the last sweep, which keeps the
+    /// cost of compaction proportional to the number of deposits.
+    fn compact(&mut self, account: &mut AccountLog) -> Result<(), StorageError> {
+        if account.retained_deposits < account.next_compaction {
+            return Ok(());
+        }
+
+        let client_id = account.state.client_id;
+        for (tx, record) in self.storage.deposits(client_id)? {
+            // Open disputes must stay resolvable whatever their age.
+            if record.disputed.is_some() {
+                continue;
+            }
+
+            let within_window = match (
+                self.dispute_window,
+                record.timestamp,
+                account.last_timestamp,
+            ) {
+                (Some(window), Some(deposited_at), Some(now)) => now
+                    .duration_since(deposited_at)
+                    .is_none_or(|elapsed| elapsed <= window),
+                _ => true,
+            };
+
+            if !within_window || !self.within_retention(account, &record) {
+                self.storage.remove_deposit(client_id, tx)?;
+                account.retained_deposits -= 1;
+            }
+        }
+
+        account.next_compaction = (account.retained_deposits * 2).max(MIN_COMPACTION_THRESHOLD);
+        Ok(())
+    }
+
+    /// Returns the log of an account, creating and saving it if it does not exist yet.
+    fn load_or_create(&mut self, client_id: ClientId) -> Result<AccountLog, StorageError> {
+        if let Some(log) = self.storage.account(client_id)? {
+            return Ok(log);
+        }
+
+        let log = AccountLog::new(client_id);
+        self.storage.save_account(&log)?;
+        Ok(log)
+    }
+
+    /// Voids every authorization of the account that is more than `expiry` older than
+    /// `now`. The account is saved right away since the authorizations are removed.
+    fn expire_account_authorizations(
+        &mut self,
+        account: &mut AccountLog,
+        now: Timestamp,
+        expiry: Duration,
+    ) -> PaymentProcessingResult {
+        let client_id = account.state.client_id;
+        let expired: Vec<(TransactionId, AuthorizationRecord)> = self
+            .storage
+            .authorizations(client_id)?
+            .into_iter()
+            .filter(|(_, authorization)| {
+                authorization
+                    .timestamp
+                    .and_then(|authorized_at| now.duration_since(authorized_at))
+                    .is_some_and(|elapsed| elapsed > expiry)
+            })
+            .collect();
+
+        if expired.is_empty() {
+            return Ok(());
+        }
+
+        for (id, authorization) in expired {
+            account.state.void(authorization.amount.into())?;
+            self.storage.remove_authorization(client_id, id)?;
+        }
+
+        self.storage.save_account(account)?;
+        Ok(())
+    }
+
+    /// Voids the authorizations of every account that have expired at `now`.
+    pub fn expire_authorizations(&mut self, now: impl Into<Timestamp>) -> PaymentProcessingResult {
+        let now = now.into();
+
+        if let Some(expiry) = self.authorization_expiry {
+            let accounts = self
+                .storage
+                .accounts()
+                .collect::<Result<Vec<AccountLog>, StorageError>>()?;
+
+            for mut account in accounts {
+                let before = account.state.clone();
+
+                self.expire_account_authorizations(&mut account, now, expiry)?;
+                self.storage.checkpoint()?;
+                self.emit_changes(before.client_id, None, before)?;
             }
+        }
+
+        Ok(())
+    }
+
+    /// Flushes the subscribers, returning the first error any of them ran into.
+    pub fn flush_events(&mut self) -> anyhow::Result<()> {
+        self.subscribers
+            .iter_mut()
+            .try_for_each(|subscriber| subscriber.flush())
+    }
+
+    /// Returns the number of input rows already processed according to the storage,
+    /// which are to be skipped when resuming.
+    pub fn processed_rows(&self) -> Result<u64, StorageError> {
+        self.storage.processed_rows()
+    }
+
+    /// Marks the first `rows` input rows as processed. Must be called before the last
+    /// of these rows is processed, so that its changes and the position are persisted
+    /// together.
+    pub fn save_processed_rows(&mut self, rows: u64) -> Result<(), StorageError> {
+        self.storage.save_processed_rows(rows)
+    }
+
+    /// Makes sure every change made so far is persisted by the storage.
+    pub fn flush_storage(&mut self) -> Result<(), StorageError> {
+        self.storage.flush()
+    }
+
+    /// Copies the state of an account so that changes can be detected afterwards.
+    fn snapshot(&self, client_id: ClientId) -> Result<Account, StorageError> {
+        Ok(self
+            .storage
+            .account(client_id)?
+            .map(|log| log.state)
+            .unwrap_or_else(|| Account::new(client_id)))
+    }
+
+    /// Emits the events describing how an account changed since the snapshot.
+    fn emit_changes(
+        &mut self,
+        client_id: ClientId,
+        tx: Option<TransactionId>,
+        before: Account,
+    ) -> Result<(), StorageError> {
+        let Some(after) = self.storage.account(client_id)?.map(|log| log.state) else {
+            return Ok(());
         };
 
+        if (before.available, before.held, before.authorized)
+            != (after.available, after.held, after.authorized)
+        {
+            self.emit(&AccountEvent::BalanceChanged {
+                client: client_id,
+                tx,
+                available: after.available,
+                held: after.held,
+                authorized: after.authorized,
+            });
+        }
+
+        if let (false, true, Some(tx)) = (before.locked, after.locked, tx) {
+            self.emit(&AccountEvent::AccountLocked {
+                client: client_id,
+                tx,
+            });
+        }
+
         Ok(())
     }
 
+    fn emit(&mut self, event: &AccountEvent) {
+        for subscriber in &mut self.subscribers {
+            subscriber.on_event(event);
+        }
+    }
+
     /// Retrieves the account log of the specified client
-    pub fn account_log(&self, client_id: impl Into<ClientId>) -> Option<&AccountLog> {
-        self.accounts.get(&client_id.into())
+    pub fn account_log(
+        &self,
+        client_id: impl Into<ClientId>,
+    ) -> Result<Option<AccountLog>, StorageError> {
+        self.storage.account(client_id.into())
+    }
+
+    /// Retrieves what remains of a deposit of the specified client, including the
+    /// amount under dispute, unless the deposit is unknown or no longer retained
+    pub fn deposit_balance(
+        &self,
+        client_id: impl Into<ClientId>,
+        tx: impl Into<TransactionId>,
+    ) -> Result<Option<MonetaryValue>, StorageError> {
+        let Some(account) = self.storage.account(client_id.into())? else {
+            return Ok(None);
+        };
+
+        Ok(self.retained_deposit(&account, tx.into())?.map(|record| {
+            let disputed = record.disputed.map(MonetaryValue::from).unwrap_or_default();
+            MonetaryValue::from(record.disputable).saturating_add(disputed)
+        }))
+    }
+
+    /// Retrieves the accepted transactions of the specified client, oldest first
+    pub fn history(
+        &self,
+        client_id: impl Into<ClientId>,
+    ) -> Result<Vec<Transaction>, StorageError> {
+        self.storage.history(client_id.into())
+    }
+
+    /// Retrieves the rejected transactions of the specified client, oldest first
+    pub fn rejections(
+        &self,
+        client_id: impl Into<ClientId>,
+    ) -> Result<Vec<RejectedTransaction>, StorageError> {
+        self.storage.rejections(client_id.into())
+    }
+
+    /// Counts the records kept by the storage
+    pub fn storage_stats(&self) -> Result<StorageStats, StorageError> {
+        self.storage.stats()
+    }
+
+    /// Returns an iterator over the account logs of all existing accounts
+    pub fn account_logs(&self) -> impl Iterator<Item = Result<AccountLog, StorageError>> + '_ {
+        self.storage.accounts()
     }
 
     /// Returns an iterator over all existing accounts
-    pub fn accounts(self) -> impl Iterator<Item = Account> {
-        self.accounts.into_iter().map(|(_, log)| log.state)
+    pub fn accounts(&self) -> impl Iterator<Item = Result<Account, StorageError>> + '_ {
+        self.account_logs().map(|log| log.map(|log| log.state))
     }
 }
 
@@ -140,10 +1005,19 @@ impl PaymentProcessor {
 
 #[cfg(test)]
 mod tests {
+    use std::time::Duration;
+
     use rust_decimal_macros::dec;
 
-    use super::PaymentProcessor;
-    use crate::account::{Transaction, TransactionKind};
+    use std::sync::mpsc;
+
+    use super::{PaymentProcessingError, PaymentProcessor};
+    use crate::{
+        account::{Transaction, TransactionKind},
+        error::ErrorCode,
+        events::AccountEvent,
+        rules::{Limit, Rule, RuleSet},
+    };
 
     macro_rules! tx {
         ($id:expr, deposit, $amount:expr) => {
@@ -153,7 +1027,10 @@ mod tests {
             Transaction::new($id, TransactionKind::Withdrawal($amount))
         };
         ($id:expr, dispute) => {
-            Transaction::new($id, TransactionKind::Dispute)
+            Transaction::new($id, TransactionKind::Dispute(None))
+        };
+        ($id:expr, dispute, $amount:expr) => {
+            Transaction::new($id, TransactionKind::Dispute(Some($amount)))
         };
         ($id:expr, resolve) => {
             Transaction::new($id, TransactionKind::Resolve)
@@ -161,6 +1038,33 @@ mod tests {
         ($id:expr, chargeback) => {
             Transaction::new($id, TransactionKind::Chargeback)
         };
+        ($id:expr, refund) => {
+            Transaction::new($id, TransactionKind::Refund(None))
+        };
+        ($id:expr, refund, $amount:expr) => {
+            Transaction::new($id, TransactionKind::Refund(Some($amount)))
+        };
+        ($id:expr, authorize, $amount:expr) => {
+            Transaction::new($id, TransactionKind::Authorize($amount))
+        };
+        ($id:expr, capture) => {
+            Transaction::new($id, TransactionKind::Capture(None))
+        };
+        ($id:expr, capture, $amount:expr) => {
+            Transaction::new($id, TransactionKind::Capture(Some($amount)))
+        };
+        ($id:expr, void) => {
+            Transaction::new($id, TransactionKind::Void)
+        };
+        ($id:expr, transfer, $recipient:expr, $amount:expr) => {
+            Transaction::new(
+                $id,
+                TransactionKind::Transfer {
+                    recipient: $recipient.into(),
+                    amount: $amount,
+                },
+            )
+        };
     }
 
     #[test]
@@ -172,6 +1076,7 @@ mod tests {
 
         let account = processor
             .account_log(1)
+            .expect("storage should be readable")
             .expect("an account should have been created");
 
         assert_eq!(account.state.available, deposit_value);
@@ -190,6 +1095,7 @@ mod tests {
 
         let account = processor
             .account_log(1)
+            .expect("storage should be readable")
             .expect("an account should have been created");
 
         assert_eq!(account.state.available, zero);
@@ -206,6 +1112,7 @@ mod tests {
 
         let account = processor
             .account_log(1)
+            .expect("storage should be readable")
             .expect("an account should have been created");
 
         assert_eq!(account.state.available, zero);
@@ -224,6 +1131,7 @@ mod tests {
 
         let account = processor
             .account_log(1)
+            .expect("storage should be readable")
             .expect("an account should have been created");
 
         assert_eq!(account.state.available, zero);
@@ -242,6 +1150,7 @@ mod tests {
 
         let account = processor
             .account_log(1)
+            .expect("storage should be readable")
             .expect("an account should have been created");
 
         assert_eq!(account.state.available, zero);
@@ -259,6 +1168,7 @@ mod tests {
 
         let account = processor
             .account_log(1)
+            .expect("storage should be readable")
             .expect("an account should have been created");
 
         assert_eq!(account.state.available, deposit_value);
@@ -277,6 +1187,7 @@ mod tests {
 
         let account = processor
             .account_log(1)
+            .expect("storage should be readable")
             .expect("an account should have been created");
 
         assert_eq!(account.state.available, zero);
@@ -294,6 +1205,7 @@ mod tests {
 
         let account = processor
             .account_log(1)
+            .expect("storage should be readable")
             .expect("an account should have been created");
 
         assert_eq!(account.state.available, deposit_value);
@@ -311,9 +1223,11 @@ mod tests {
 
         let account_1 = processor
             .account_log(1)
+            .expect("storage should be readable")
             .expect("an account should have been created");
         let account_2 = processor
             .account_log(2)
+            .expect("storage should be readable")
             .expect("an account should have been created");
 
         assert_eq!(account_1.state.available, deposit_value);
@@ -321,4 +1235,940 @@ mod tests {
         assert_eq!(account_2.state.available, deposit_value);
         assert_eq!(account_2.state.held, zero);
     }
-}
+
+    #[test]
+    fn non_monotonic_timestamp() {
+        let mut processor = PaymentProcessor::new();
+        let deposit_value = dec!(1.0).try_into().expect("1.0 is a decimal");
+
+        assert!(processor
+            .process(1, tx!(1, deposit, deposit_value).with_timestamp(20))
+            .is_ok());
+        assert!(matches!(
+            processor.process(1, tx!(2, deposit, deposit_value).with_timestamp(10)),
+            Err(PaymentProcessingError::NonMonotonicTimestamp)
+        ));
+        assert!(processor
+            .process(2, tx!(3, deposit, deposit_value).with_timestamp(10))
+            .is_ok());
+
+        let account = processor
+            .account_log(1)
+            .expect("storage should be readable")
+            .expect("an account should have been created");
+
+        assert_eq!(account.state.available, deposit_value);
+    }
+
+    #[test]
+    fn dispute_within_window() {
+        let mut processor = PaymentProcessor::new().with_dispute_window(Duration::from_secs(100));
+        let deposit_value = dec!(1.0).try_into().expect("1.0 is a decimal");
+
+        assert!(processor
+            .process(1, tx!(1, deposit, deposit_value).with_timestamp(0))
+            .is_ok());
+        assert!(processor
+            .process(1, tx!(1, dispute).with_timestamp(100))
+            .is_ok());
+
+        let account = processor
+            .account_log(1)
+            .expect("storage should be readable")
+            .expect("an account should have been created");
+
+        assert_eq!(account.state.held, deposit_value);
+    }
+
+    #[test]
+    fn dispute_after_window() {
+        let mut processor = PaymentProcessor::new().with_dispute_window(Duration::from_secs(100));
+        let deposit_value = dec!(1.0).try_into().expect("1.0 is a decimal");
+        let zero = dec!(0.0).try_into().expect("0.0 is a decimal");
+
+        assert!(processor
+            .process(1, tx!(1, deposit, deposit_value).with_timestamp(0))
+            .is_ok());
+        assert!(matches!(
+            processor.process(1, tx!(1, dispute).with_timestamp(101)),
+            Err(PaymentProcessingError::DisputeWindowExpired)
+        ));
+
+        let account = processor
+            .account_log(1)
+            .expect("storage should be readable")
+            .expect("an account should have been created");
+
+        assert_eq!(account.state.available, deposit_value);
+        assert_eq!(account.state.held, zero);
+    }
+
+    #[test]
+    fn partial_dispute() {
+        let mut processor = PaymentProcessor::new();
+        let deposit_value = dec!(10.0).try_into().expect("10.0 is a decimal");
+        let disputed_value = dec!(4.0).try_into().expect("4.0 is a decimal");
+        let remaining_value = dec!(6.0).try_into().expect("6.0 is a decimal");
+
+        assert!(processor.process(1, tx!(1, deposit, deposit_value)).is_ok());
+        assert!(processor
+            .process(1, tx!(1, dispute, disputed_value))
+            .is_ok());
+
+        let account = processor
+            .account_log(1)
+            .expect("storage should be readable")
+            .expect("an account should have been created");
+
+        assert_eq!(account.state.available, remaining_value);
+        assert_eq!(account.state.held, disputed_value);
+    }
+
+    #[test]
+    fn partial_dispute_exceeding_deposit() {
+        let mut processor = PaymentProcessor::new();
+        let deposit_value = dec!(1.0).try_into().expect("1.0 is a decimal");
+        let disputed_value = dec!(2.0).try_into().expect("2.0 is a decimal");
+        let zero = dec!(0.0).try_into().expect("0.0 is a decimal");
+
+        assert!(processor.process(1, tx!(1, deposit, deposit_value)).is_ok());
+        assert!(matches!(
+            processor.process(1, tx!(1, dispute, disputed_value)),
+            Err(PaymentProcessingError::DisputeAmountExceeded)
+        ));
+
+        let account = processor
+            .account_log(1)
+            .expect("storage should be readable")
+            .expect("an account should have been created");
+
+        assert_eq!(account.state.available, deposit_value);
+        assert_eq!(account.state.held, zero);
+    }
+
+    #[test]
+    fn dispute_remaining_amount_after_resolve() {
+        let mut processor = PaymentProcessor::new();
+        let deposit_value = dec!(10.0).try_into().expect("10.0 is a decimal");
+        let disputed_value = dec!(4.0).try_into().expect("4.0 is a decimal");
+        let remaining_value = dec!(6.0).try_into().expect("6.0 is a decimal");
+        let zero = dec!(0.0).try_into().expect("0.0 is a decimal");
+
+        assert!(processor.process(1, tx!(1, deposit, deposit_value)).is_ok());
+        assert!(processor
+            .process(1, tx!(1, dispute, disputed_value))
+            .is_ok());
+        assert!(processor.process(1, tx!(1, resolve)).is_ok());
+        assert!(processor
+            .process(1, tx!(1, dispute, remaining_value))
+            .is_ok());
+        assert!(processor.process(1, tx!(1, resolve)).is_ok());
+
+        // Resolved portions can be disputed again, so the whole deposit is.
+        assert!(processor.process(1, tx!(1, dispute)).is_ok());
+
+        let account = processor
+            .account_log(1)
+            .expect("storage should be readable")
+            .expect("an account should have been created");
+
+        assert_eq!(account.state.available, zero);
+        assert_eq!(account.state.held, deposit_value);
+
+        assert!(processor.process(1, tx!(1, chargeback)).is_ok());
+        assert!(matches!(
+            processor.process(1, tx!(1, dispute)),
+            Err(PaymentProcessingError::DisputeAlreadyExists)
+        ));
+    }
+
+    #[test]
+    fn partial_chargeback() {
+        let mut processor = PaymentProcessor::new();
+        let deposit_value = dec!(10.0).try_into().expect("10.0 is a decimal");
+        let disputed_value = dec!(4.0).try_into().expect("4.0 is a decimal");
+        let remaining_value = dec!(6.0).try_into().expect("6.0 is a decimal");
+        let zero = dec!(0.0).try_into().expect("0.0 is a decimal");
+
+        assert!(processor.process(1, tx!(1, deposit, deposit_value)).is_ok());
+        assert!(processor
+            .process(1, tx!(1, dispute, disputed_value))
+            .is_ok());
+        assert!(processor.process(1, tx!(1, chargeback)).is_ok());
+
+        let account = processor
+            .account_log(1)
+            .expect("storage should be readable")
+            .expect("an account should have been created");
+
+        assert_eq!(account.state.available, remaining_value);
+        assert_eq!(account.state.held, zero);
+        assert!(account.state.locked);
+    }
+
+    #[test]
+    fn dispute_unknown_transaction() {
+        let mut processor = PaymentProcessor::new();
+        let value = dec!(1.0).try_into().expect("1.0 is a decimal");
+
+        assert!(processor.process(1, tx!(1, deposit, value)).is_ok());
+        assert!(processor.process(1, tx!(2, withdrawal, value)).is_ok());
+        assert!(matches!(
+            processor.process(1, tx!(2, dispute)),
+            Err(PaymentProcessingError::UnknownTransaction)
+        ));
+        assert!(matches!(
+            processor.process(1, tx!(3, dispute)),
+            Err(PaymentProcessingError::UnknownTransaction)
+        ));
+    }
+
+    #[test]
+    fn transfer() {
+        let mut processor = PaymentProcessor::new();
+        let deposit_value = dec!(3.0).try_into().expect("3.0 is a decimal");
+        let transfer_value = dec!(2.0).try_into().expect("2.0 is a decimal");
+        let remaining_value = dec!(1.0).try_into().expect("1.0 is a decimal");
+
+        assert!(processor.process(1, tx!(1, deposit, deposit_value)).is_ok());
+        assert!(processor
+            .process(1, tx!(2, transfer, 2, transfer_value))
+            .is_ok());
+
+        let sender = processor
+            .account_log(1)
+            .expect("storage should be readable")
+            .expect("an account should have been created");
+        let recipient = processor
+            .account_log(2)
+            .expect("storage should be readable")
+            .expect("an account should have been created");
+
+        assert_eq!(sender.state.available, remaining_value);
+        assert_eq!(recipient.state.available, transfer_value);
+    }
+
+    #[test]
+    fn transfer_insufficient_funds() {
+        let mut processor = PaymentProcessor::new();
+        let deposit_value = dec!(1.0).try_into().expect("1.0 is a decimal");
+        let transfer_value = dec!(2.0).try_into().expect("2.0 is a decimal");
+
+        assert!(processor.process(1, tx!(1, deposit, deposit_value)).is_ok());
+        assert!(processor
+            .process(1, tx!(2, transfer, 2, transfer_value))
+            .is_err());
+
+        let sender = processor
+            .account_log(1)
+            .expect("storage should be readable")
+            .expect("an account should have been created");
+
+        assert_eq!(sender.state.available, deposit_value);
+        assert!(processor
+            .account_log(2)
+            .expect("storage should be readable")
+            .is_none());
+    }
+
+    #[test]
+    fn transfer_to_locked_account() {
+        let mut processor = PaymentProcessor::new();
+        let deposit_value = dec!(1.0).try_into().expect("1.0 is a decimal");
+
+        assert!(processor.process(1, tx!(1, deposit, deposit_value)).is_ok());
+        assert!(processor.process(2, tx!(2, deposit, deposit_value)).is_ok());
+        assert!(processor.process(2, tx!(2, dispute)).is_ok());
+        assert!(processor.process(2, tx!(2, chargeback)).is_ok());
+        assert!(processor
+            .process(1, tx!(3, transfer, 2, deposit_value))
+            .is_err());
+
+        let sender = processor
+            .account_log(1)
+            .expect("storage should be readable")
+            .expect("an account should have been created");
+
+        assert_eq!(sender.state.available, deposit_value);
+    }
+
+    #[test]
+    fn transfer_to_self() {
+        let mut processor = PaymentProcessor::new();
+        let deposit_value = dec!(1.0).try_into().expect("1.0 is a decimal");
+
+        assert!(processor.process(1, tx!(1, deposit, deposit_value)).is_ok());
+        assert!(matches!(
+            processor.process(1, tx!(2, transfer, 1, deposit_value)),
+            Err(PaymentProcessingError::SelfTransfer)
+        ));
+    }
+
+    #[test]
+    fn dispute_transfer() {
+        let mut processor = PaymentProcessor::new();
+        let deposit_value = dec!(1.0).try_into().expect("1.0 is a decimal");
+
+        assert!(processor.process(1, tx!(1, deposit, deposit_value)).is_ok());
+        assert!(processor
+            .process(1, tx!(2, transfer, 2, deposit_value))
+            .is_ok());
+        assert!(matches!(
+            processor.process(1, tx!(2, dispute)),
+            Err(PaymentProcessingError::UnknownTransaction)
+        ));
+        assert!(matches!(
+            processor.process(2, tx!(2, dispute)),
+            Err(PaymentProcessingError::UnknownTransaction)
+        ));
+    }
+
+    #[test]
+    fn refund() {
+        let mut processor = PaymentProcessor::new();
+        let deposit_value = dec!(3.0).try_into().expect("3.0 is a decimal");
+        let refund_value = dec!(1.0).try_into().expect("1.0 is a decimal");
+        let remaining_value = dec!(2.0).try_into().expect("2.0 is a decimal");
+        let zero = dec!(0.0).try_into().expect("0.0 is a decimal");
+
+        assert!(processor.process(1, tx!(1, deposit, deposit_value)).is_ok());
+        assert!(processor.process(1, tx!(1, refund, refund_value)).is_ok());
+
+        let account = processor
+            .account_log(1)
+            .expect("storage should be readable")
+            .expect("an account should have been created");
+
+        assert_eq!(account.state.available, remaining_value);
+
+        assert!(processor.process(1, tx!(1, refund)).is_ok());
+        assert!(matches!(
+            processor.process(1, tx!(1, refund)),
+            Err(PaymentProcessingError::RefundAmountExceeded)
+        ));
+
+        let account = processor
+            .account_log(1)
+            .expect("storage should be readable")
+            .expect("an account should have been created");
+
+        assert_eq!(account.state.available, zero);
+    }
+
+    #[test]
+    fn refund_exceeding_deposit() {
+        let mut processor = PaymentProcessor::new();
+        let deposit_value = dec!(1.0).try_into().expect("1.0 is a decimal");
+        let refund_value = dec!(2.0).try_into().expect("2.0 is a decimal");
+
+        assert!(processor.process(1, tx!(1, deposit, deposit_value)).is_ok());
+        assert!(processor.process(1, tx!(2, deposit, deposit_value)).is_ok());
+        assert!(matches!(
+            processor.process(1, tx!(1, refund, refund_value)),
+            Err(PaymentProcessingError::RefundAmountExceeded)
+        ));
+        assert!(matches!(
+            processor.process(1, tx!(3, refund, refund_value)),
+            Err(PaymentProcessingError::UnknownTransaction)
+        ));
+    }
+
+    #[test]
+    fn chargeback_refunded_deposit() {
+        let mut processor = PaymentProcessor::new();
+        let deposit_value = dec!(3.0).try_into().expect("3.0 is a decimal");
+        let refund_value = dec!(1.0).try_into().expect("1.0 is a decimal");
+        let remaining_value = dec!(2.0).try_into().expect("2.0 is a decimal");
+        let zero = dec!(0.0).try_into().expect("0.0 is a decimal");
+
+        assert!(processor.process(1, tx!(1, deposit, deposit_value)).is_ok());
+        assert!(processor.process(1, tx!(1, refund, refund_value)).is_ok());
+        assert!(matches!(
+            processor.process(1, tx!(1, dispute, deposit_value)),
+            Err(PaymentProcessingError::DisputeAmountExceeded)
+        ));
+        assert!(processor.process(1, tx!(1, dispute)).is_ok());
+
+        let account = processor
+            .account_log(1)
+            .expect("storage should be readable")
+            .expect("an account should have been created");
+
+        assert_eq!(account.state.available, zero);
+        assert_eq!(account.state.held, remaining_value);
+
+        assert!(processor.process(1, tx!(1, chargeback)).is_ok());
+
+        let account = processor
+            .account_log(1)
+            .expect("storage should be readable")
+            .expect("an account should have been created");
+
+        assert_eq!(account.state.held, zero);
+        assert!(account.state.locked);
+    }
+
+    #[test]
+    fn refund_disputed_deposit() {
+        let mut processor = PaymentProcessor::new();
+        let deposit_value = dec!(3.0).try_into().expect("3.0 is a decimal");
+        let disputed_value = dec!(1.0).try_into().expect("1.0 is a decimal");
+        let zero = dec!(0.0).try_into().expect("0.0 is a decimal");
+
+        assert!(processor.process(1, tx!(1, deposit, deposit_value)).is_ok());
+        assert!(processor
+            .process(1, tx!(1, dispute, disputed_value))
+            .is_ok());
+        assert!(processor.process(1, tx!(1, refund)).is_ok());
+
+        let account = processor
+            .account_log(1)
+            .expect("storage should be readable")
+            .expect("an account should have been created");
+
+        assert_eq!(account.state.available, zero);
+        assert_eq!(account.state.held, disputed_value);
+    }
+
+    #[test]
+    fn authorize_and_capture() {
+        let mut processor = PaymentProcessor::new();
+        let deposit_value = dec!(3.0).try_into().expect("3.0 is a decimal");
+        let authorized_value = dec!(2.0).try_into().expect("2.0 is a decimal");
+        let remaining_value = dec!(1.0).try_into().expect("1.0 is a decimal");
+        let zero = dec!(0.0).try_into().expect("0.0 is a decimal");
+
+        assert!(processor.process(1, tx!(1, deposit, deposit_value)).is_ok());
+        assert!(processor
+            .process(1, tx!(2, authorize, authorized_value))
+            .is_ok());
+
+        let account = processor
+            .account_log(1)
+            .expect("storage should be readable")
+            .expect("an account should have been created");
+
+        assert_eq!(account.state.available, remaining_value);
+        assert_eq!(account.state.authorized, authorized_value);
+        assert_eq!(account.state.held, zero);
+
+        assert!(processor.process(1, tx!(2, capture)).is_ok());
+        assert!(matches!(
+            processor.process(1, tx!(2, capture)),
+            Err(PaymentProcessingError::NoAuthorization)
+        ));
+
+        let account = processor
+            .account_log(1)
+            .expect("storage should be readable")
+            .expect("an account should have been created");
+
+        assert_eq!(account.state.available, remaining_value);
+        assert_eq!(account.state.authorized, zero);
+    }
+
+    #[test]
+    fn partial_capture() {
+        let mut processor = PaymentProcessor::new();
+        let deposit_value = dec!(3.0).try_into().expect("3.0 is a decimal");
+        let captured_value = dec!(1.0).try_into().expect("1.0 is a decimal");
+        let remaining_value = dec!(2.0).try_into().expect("2.0 is a decimal");
+        let zero = dec!(0.0).try_into().expect("0.0 is a decimal");
+
+        assert!(processor.process(1, tx!(1, deposit, deposit_value)).is_ok());
+        assert!(processor
+            .process(1, tx!(2, authorize, deposit_value))
+            .is_ok());
+        assert!(matches!(
+            processor.process(
+                1,
+                tx!(2, capture, dec!(4.0).try_into().expect("4.0 is a decimal"))
+            ),
+            Err(PaymentProcessingError::CaptureAmountExceeded)
+        ));
+        assert!(processor
+            .process(1, tx!(2, capture, captured_value))
+            .is_ok());
+
+        let account = processor
+            .account_log(1)
+            .expect("storage should be readable")
+            .expect("an account should have been created");
+
+        assert_eq!(account.state.available, remaining_value);
+        assert_eq!(account.state.authorized, zero);
+    }
+
+    #[test]
+    fn authorize_and_void() {
+        let mut processor = PaymentProcessor::new();
+        let deposit_value = dec!(3.0).try_into().expect("3.0 is a decimal");
+        let zero = dec!(0.0).try_into().expect("0.0 is a decimal");
+
+        assert!(processor.process(1, tx!(1, deposit, deposit_value)).is_ok());
+        assert!(processor
+            .process(1, tx!(2, authorize, deposit_value))
+            .is_ok());
+        assert!(matches!(
+            processor.process(1, tx!(2, authorize, deposit_value)),
+            Err(PaymentProcessingError::AuthorizationAlreadyExists)
+        ));
+        assert!(processor.process(1, tx!(2, void)).is_ok());
+
+        let account = processor
+            .account_log(1)
+            .expect("storage should be readable")
+            .expect("an account should have been created");
+
+        assert_eq!(account.state.available, deposit_value);
+        assert_eq!(account.state.authorized, zero);
+    }
+
+    #[test]
+    fn authorization_expiry() {
+        let mut processor =
+            PaymentProcessor::new().with_authorization_expiry(Duration::from_secs(100));
+        let deposit_value = dec!(3.0).try_into().expect("3.0 is a decimal");
+        let zero = dec!(0.0).try_into().expect("0.0 is a decimal");
+
+        assert!(processor
+            .process(1, tx!(1, deposit, deposit_value).with_timestamp(0))
+            .is_ok());
+        assert!(processor
+            .process(1, tx!(2, authorize, deposit_value).with_timestamp(0))
+            .is_ok());
+        assert!(matches!(
+            processor.process(1, tx!(2, capture).with_timestamp(101)),
+            Err(PaymentProcessingError::NoAuthorization)
+        ));
+
+        let account = processor
+            .account_log(1)
+            .expect("storage should be readable")
+            .expect("an account should have been created");
+
+        assert_eq!(account.state.available, deposit_value);
+        assert_eq!(account.state.authorized, zero);
+    }
+
+    #[test]
+    fn expire_all_authorizations() {
+        let mut processor =
+            PaymentProcessor::new().with_authorization_expiry(Duration::from_secs(100));
+        let deposit_value = dec!(3.0).try_into().expect("3.0 is a decimal");
+        let zero = dec!(0.0).try_into().expect("0.0 is a decimal");
+
+        assert!(processor
+            .process(1, tx!(1, deposit, deposit_value).with_timestamp(0))
+            .is_ok());
+        assert!(processor
+            .process(1, tx!(2, authorize, deposit_value).with_timestamp(50))
+            .is_ok());
+        assert!(processor.expire_authorizations(150).is_ok());
+
+        let account = processor
+            .account_log(1)
+            .expect("storage should be readable")
+            .expect("an account should have been created");
+
+        assert_eq!(account.state.authorized, deposit_value);
+
+        assert!(processor.expire_authorizations(151).is_ok());
+
+        let account = processor
+            .account_log(1)
+            .expect("storage should be readable")
+            .expect("an account should have been created");
+
+        assert_eq!(account.state.available, deposit_value);
+        assert_eq!(account.state.authorized, zero);
+    }
+
+    #[test]
+    fn deposit_retention() {
+        let mut processor = PaymentProcessor::new().with_deposit_retention(2);
+        let deposit_value = dec!(1.0).try_into().expect("1.0 is a decimal");
+
+        for id in 1..=3 {
+            assert!(processor
+                .process(1, tx!(id, deposit, deposit_value))
+                .is_ok());
+        }
+
+        assert!(matches!(
+            processor.process(1, tx!(1, dispute)),
+            Err(PaymentProcessingError::UnknownTransaction)
+        ));
+        assert!(matches!(
+            processor.process(1, tx!(1, refund)),
+            Err(PaymentProcessingError::UnknownTransaction)
+        ));
+        assert!(processor.process(1, tx!(2, dispute)).is_ok());
+
+        // Open disputes can still be settled once their deposit is out of retention.
+        assert!(processor.process(1, tx!(4, deposit, deposit_value)).is_ok());
+        assert!(processor.process(1, tx!(2, resolve)).is_ok());
+    }
+
+    #[test]
+    fn compaction() {
+        let mut processor = PaymentProcessor::new().with_compaction();
+        let deposit_value = dec!(2.0).try_into().expect("2.0 is a decimal");
+        let withdrawal_value = dec!(0.5).try_into().expect("0.5 is a decimal");
+
+        assert!(processor.process(1, tx!(1, deposit, deposit_value)).is_ok());
+        assert!(processor.process(1, tx!(2, deposit, deposit_value)).is_ok());
+        assert!(processor
+            .process(1, tx!(3, withdrawal, withdrawal_value))
+            .is_ok());
+        assert!(processor.process(1, tx!(1, refund)).is_ok());
+
+        let stats = processor
+            .storage_stats()
+            .expect("storage should be readable");
+
+        // The refunded deposit cannot be referenced anymore.
+        assert_eq!(stats.deposits, 1);
+        assert_eq!(stats.transactions, 0);
+        assert!(matches!(
+            processor.process(1, tx!(1, dispute)),
+            Err(PaymentProcessingError::UnknownTransaction)
+        ));
+        assert!(processor
+            .process(1, tx!(2, dispute, withdrawal_value))
+            .is_ok());
+    }
+
+    #[test]
+    fn compaction_with_retention() {
+        let mut processor = PaymentProcessor::new()
+            .with_deposit_retention(10)
+            .with_compaction();
+        let deposit_value = dec!(1.0).try_into().expect("1.0 is a decimal");
+
+        assert!(processor.process(1, tx!(0, deposit, deposit_value)).is_ok());
+        assert!(processor.process(1, tx!(0, dispute)).is_ok());
+
+        for id in 1..1000 {
+            assert!(processor
+                .process(1, tx!(id, deposit, deposit_value))
+                .is_ok());
+        }
+
+        let stats = processor
+            .storage_stats()
+            .expect("storage should be readable");
+
+        // Deposits are swept in batches, and the disputed one is kept until settled.
+        assert!(stats.deposits <= 2 * super::MIN_COMPACTION_THRESHOLD);
+        assert!(processor.process(1, tx!(990, dispute)).is_ok());
+        assert!(processor.process(1, tx!(0, chargeback)).is_ok());
+    }
+
+    #[test]
+    fn amount_out_of_range() {
+        let mut processor = PaymentProcessor::new();
+        let huge_value = dec!(1000000000000000)
+            .try_into()
+            .expect("1e15 is a decimal");
+
+        assert!(matches!(
+            processor.process(1, tx!(1, deposit, huge_value)),
+            Err(PaymentProcessingError::AmountOutOfRange(_))
+        ));
+
+        let account = processor
+            .account_log(1)
+            .expect("storage should be readable")
+            .expect("an account should have been created");
+        assert_eq!(account.rejected(), 1);
+        assert_eq!(
+            account.state.available,
+            dec!(0).try_into().expect("0 is a decimal")
+        );
+    }
+
+    #[test]
+    fn dispute_events() {
+        let (sender, receiver) = mpsc::channel();
+        let mut processor = PaymentProcessor::new();
+        processor.subscribe(sender);
+        let deposit_value = dec!(1.0).try_into().expect("1.0 is a decimal");
+        let zero = dec!(0.0).try_into().expect("0.0 is a decimal");
+
+        assert!(processor.process(1, tx!(1, deposit, deposit_value)).is_ok());
+        assert!(processor.process(1, tx!(1, dispute)).is_ok());
+        assert!(processor.process(1, tx!(1, chargeback)).is_ok());
+        assert!(processor
+            .process(1, tx!(2, deposit, deposit_value))
+            .is_err());
+
+        let events: Vec<AccountEvent> = receiver.try_iter().collect();
+
+        assert_eq!(
+            events,
+            vec![
+                AccountEvent::BalanceChanged {
+                    client: 1.into(),
+                    tx: Some(1.into()),
+                    available: deposit_value,
+                    held: zero,
+                    authorized: zero,
+                },
+                AccountEvent::DisputeOpened {
+                    client: 1.into(),
+                    tx: 1.into(),
+                    amount: deposit_value,
+                },
+                AccountEvent::BalanceChanged {
+                    client: 1.into(),
+                    tx: Some(1.into()),
+                    available: zero,
+                    held: deposit_value,
+                    authorized: zero,
+                },
+                AccountEvent::ChargedBack {
+                    client: 1.into(),
+                    tx: 1.into(),
+                    amount: deposit_value,
+                },
+                AccountEvent::BalanceChanged {
+                    client: 1.into(),
+                    tx: Some(1.into()),
+                    available: zero,
+                    held: zero,
+                    authorized: zero,
+                },
+                AccountEvent::AccountLocked {
+                    client: 1.into(),
+                    tx: 1.into(),
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn transfer_events() {
+        let (sender, receiver) = mpsc::channel();
+        let mut processor = PaymentProcessor::new();
+        let deposit_value = dec!(1.0).try_into().expect("1.0 is a decimal");
+        let zero = dec!(0.0).try_into().expect("0.0 is a decimal");
+
+        assert!(processor.process(1, tx!(1, deposit, deposit_value)).is_ok());
+        processor.subscribe(sender);
+        assert!(processor
+            .process(1, tx!(2, transfer, 2, deposit_value))
+            .is_ok());
+
+        let events: Vec<AccountEvent> = receiver.try_iter().collect();
+
+        assert_eq!(
+            events,
+            vec![
+                AccountEvent::BalanceChanged {
+                    client: 1.into(),
+                    tx: Some(2.into()),
+                    available: zero,
+                    held: zero,
+                    authorized: zero,
+                },
+                AccountEvent::BalanceChanged {
+                    client: 2.into(),
+                    tx: Some(2.into()),
+                    available: deposit_value,
+                    held: zero,
+                    authorized: zero,
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn transfer_after_authorization_expiry() {
+        let mut processor =
+            PaymentProcessor::new().with_authorization_expiry(Duration::from_secs(100));
+        let deposit_value = dec!(3.0).try_into().expect("3.0 is a decimal");
+        let zero = dec!(0.0).try_into().expect("0.0 is a decimal");
+
+        assert!(processor
+            .process(1, tx!(1, deposit, deposit_value).with_timestamp(0))
+            .is_ok());
+        assert!(processor
+            .process(1, tx!(2, authorize, deposit_value).with_timestamp(0))
+            .is_ok());
+        assert!(processor
+            .process(1, tx!(3, transfer, 2, deposit_value).with_timestamp(101))
+            .is_ok());
+
+        let sender = processor
+            .account_log(1)
+            .expect("storage should be readable")
+            .expect("an account should have been created");
+        let recipient = processor
+            .account_log(2)
+            .expect("storage should be readable")
+            .expect("an account should have been created");
+
+        assert_eq!(sender.state.available, zero);
+        assert_eq!(sender.state.authorized, zero);
+        assert_eq!(recipient.state.available, deposit_value);
+    }
+
+    #[test]
+    fn record_rejections() {
+        let mut processor = PaymentProcessor::new();
+        let deposit_value = dec!(1.0).try_into().expect("1.0 is a decimal");
+        let withdrawal_value = dec!(2.5).try_into().expect("2.5 is a decimal");
+
+        // A rejected transaction opens the account of its client like any other.
+        assert!(processor
+            .process(1, tx!(1, transfer, 1, deposit_value))
+            .is_err());
+        assert!(processor
+            .account_log(1)
+            .expect("storage should be readable")
+            .is_some());
+
+        assert!(processor.process(1, tx!(2, deposit, deposit_value)).is_ok());
+        assert!(processor
+            .process(1, tx!(3, withdrawal, withdrawal_value))
+            .is_err());
+        assert!(processor.process(1, tx!(2, resolve)).is_err());
+
+        let account = processor
+            .account_log(1)
+            .expect("storage should be readable")
+            .expect("an account should have been created");
+        assert_eq!(account.rejected(), 3);
+        assert_eq!(
+            account.rejected_amount(),
+            dec!(3.5).try_into().expect("3.5 is a decimal")
+        );
+
+        let codes: Vec<_> = processor
+            .rejections(1)
+            .expect("storage should be readable")
+            .into_iter()
+            .map(|rejection| (rejection.transaction, rejection.code))
+            .collect();
+        assert_eq!(
+            codes,
+            [
+                (tx!(1, transfer, 1, deposit_value), ErrorCode::SelfTransfer),
+                (
+                    tx!(3, withdrawal, withdrawal_value),
+                    ErrorCode::InsufficientFunds
+                ),
+                (tx!(2, resolve), ErrorCode::NoDispute),
+            ]
+        );
+        assert_eq!(
+            processor.history(1).expect("storage should be readable"),
+            [tx!(2, deposit, deposit_value)]
+        );
+
+        // Transactions rejected before being processed are only counted.
+        assert!(processor.reject(2, Some(deposit_value)).is_ok());
+        assert!(processor.reject(2, None).is_ok());
+        let account = processor
+            .account_log(2)
+            .expect("storage should be readable")
+            .expect("an account should have been created");
+        assert_eq!(account.rejected(), 2);
+        assert_eq!(account.rejected_amount(), deposit_value);
+        assert!(processor
+            .rejections(2)
+            .expect("storage should be readable")
+            .is_empty());
+    }
+
+    #[test]
+    fn enforce_rules() {
+        let rules = RuleSet::default()
+            .with_rule(Rule::new(
+                "velocity",
+                Limit::MaxWithdrawals {
+                    withdrawals: 1,
+                    transactions: 2,
+                },
+            ))
+            .with_rule(Rule::new(
+                "disputes",
+                Limit::MaxDisputeRate {
+                    rate: dec!(0.5),
+                    min_deposits: 0,
+                },
+            ));
+        let mut processor = PaymentProcessor::new().with_rules(rules);
+        let value = dec!(1.0).try_into().expect("1.0 is a decimal");
+
+        assert!(processor.process(1, tx!(1, deposit, value)).is_ok());
+        assert!(processor.process(1, tx!(2, deposit, value)).is_ok());
+        assert!(processor.process(1, tx!(3, withdrawal, value)).is_ok());
+        assert!(matches!(
+            processor.process(1, tx!(4, withdrawal, value)),
+            Err(PaymentProcessingError::RuleViolation(violation)) if violation.rule == "velocity"
+        ));
+
+        // One dispute for two deposits is the limit, the next one locks the account.
+        assert!(processor.process(1, tx!(1, dispute)).is_ok());
+        assert!(processor.process(1, tx!(1, resolve)).is_ok());
+        let error = processor
+            .process(1, tx!(2, dispute))
+            .expect_err("a second dispute exceeds the dispute rate");
+        assert_eq!(error.code(), ErrorCode::DisputeRateExceeded);
+
+        let account = processor
+            .account_log(1)
+            .expect("storage should be readable")
+            .expect("an account should have been created");
+        assert!(account.state.locked);
+        assert_eq!(
+            account.state.held,
+            dec!(0).try_into().expect("0 is a decimal")
+        );
+    }
+
+    #[test]
+    fn invalid_disputes_ignore_rules() {
+        let rules = RuleSet::default().with_rule(Rule::new(
+            "disputes",
+            Limit::MaxDisputeRate {
+                rate: dec!(0.5),
+                min_deposits: 0,
+            },
+        ));
+        let mut processor = PaymentProcessor::new().with_rules(rules);
+        let value = dec!(1.0).try_into().expect("1.0 is a decimal");
+
+        assert!(processor.process(1, tx!(1, deposit, value)).is_ok());
+        assert!(processor.process(1, tx!(2, deposit, value)).is_ok());
+        assert!(processor.process(1, tx!(1, dispute)).is_ok());
+
+        // The dispute rate is reached, but rejected disputes are not evaluated.
+        assert!(matches!(
+            processor.process(1, tx!(9, dispute)),
+            Err(PaymentProcessingError::UnknownTransaction)
+        ));
+        assert!(matches!(
+            processor.process(1, tx!(1, dispute)),
+            Err(PaymentProcessingError::DisputeAlreadyExists)
+        ));
+
+        let account = processor
+            .account_log(1)
+            .expect("storage should be readable")
+            .expect("an account should have been created");
+        assert!(!account.state.locked);
+        assert_eq!(account.disputes, 1);
+
+        // A valid dispute still exceeds the rate.
+        let error = processor
+            .process(1, tx!(2, dispute))
+            .expect_err("a second dispute exceeds the dispute rate");
+        assert_eq!(error.code(), ErrorCode::DisputeRateExceeded);
+    }
+}
+
+#[cfg(test)]
+mod model_tests;
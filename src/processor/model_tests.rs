@@ -0,0 +1,286 @@
+//! Model-based tests: arbitrary transaction sequences are applied both to a
+//! `PaymentProcessor` and to a minimal reference model of the account rules, which
+//! must agree on every outcome. Few clients and transaction ids are used so that
+//! sequences often hit duplicate ids, nested disputes, overdrafts and locked accounts.
+
+use std::collections::{BTreeMap, HashMap};
+
+use proptest::prelude::*;
+use rust_decimal::Decimal;
+
+use super::PaymentProcessor;
+use crate::account::{MonetaryValue, Transaction, TransactionKind};
+
+const CLIENTS: u16 = 3;
+const TRANSACTION_IDS: u32 = 6;
+
+#[derive(Default, Debug, PartialEq)]
+struct ModelAccount {
+    available: Decimal,
+    held: Decimal,
+    authorized: Decimal,
+    locked: bool,
+}
+
+struct ModelDeposit {
+    /// Part of the deposit that is neither under dispute, charged back nor refunded.
+    remaining: Decimal,
+    disputed: Option<Decimal>,
+}
+
+#[derive(Default)]
+/// Reference model of the account rules. Any transaction opens the account of its
+/// client, even if it is rejected. Recipients of transfers are only opened once
+/// credited.
+struct Model {
+    accounts: BTreeMap<u16, ModelAccount>,
+    deposits: HashMap<(u16, u32), ModelDeposit>,
+    authorizations: HashMap<(u16, u32), Decimal>,
+}
+
+impl Model {
+    /// Applies a transaction, returning whether it is accepted.
+    fn apply(&mut self, client: u16, transaction: Transaction) -> bool {
+        use TransactionKind::*;
+
+        let key = (client, u32::from(transaction.id));
+
+        if let Transfer { recipient, amount } = transaction.kind {
+            let recipient = u16::from(recipient);
+            self.accounts.entry(client).or_default();
+            if recipient == client {
+                return false;
+            }
+
+            let amount = Decimal::from(amount);
+            let recipient_locked = self
+                .accounts
+                .get(&recipient)
+                .is_some_and(|account| account.locked);
+            let sender = self.accounts.entry(client).or_default();
+            if sender.locked || recipient_locked || sender.available < amount {
+                return false;
+            }
+
+            self.accounts.entry(client).or_default().available -= amount;
+            self.accounts.entry(recipient).or_default().available += amount;
+            return true;
+        }
+
+        let account = self.accounts.entry(client).or_default();
+        let amount = |value: Option<MonetaryValue>| value.map(Decimal::from);
+
+        match transaction.kind {
+            Deposit(value) if !account.locked => {
+                let value = Decimal::from(value);
+                account.available += value;
+                self.deposits.entry(key).or_insert(ModelDeposit {
+                    remaining: value,
+                    disputed: None,
+                });
+            }
+            Withdrawal(value) if !account.locked && account.available >= value.into() => {
+                account.available -= Decimal::from(value);
+            }
+            Dispute(value) => {
+                let Some(deposit) = self.deposits.get_mut(&key) else {
+                    return false;
+                };
+                let disputed = amount(value).unwrap_or(deposit.remaining);
+
+                if deposit.disputed.is_some()
+                    || disputed > deposit.remaining
+                    || (value.is_none() && disputed.is_zero())
+                    || account.locked
+                    || account.available < disputed
+                {
+                    return false;
+                }
+
+                account.available -= disputed;
+                account.held += disputed;
+                deposit.remaining -= disputed;
+                deposit.disputed = Some(disputed);
+            }
+            Resolve => {
+                let Some(disputed) = self.deposits.get(&key).and_then(|deposit| deposit.disputed)
+                else {
+                    return false;
+                };
+                if account.locked {
+                    return false;
+                }
+
+                account.available += disputed;
+                account.held -= disputed;
+                let deposit = self.deposits.get_mut(&key).expect("deposit exists");
+                deposit.remaining += disputed;
+                deposit.disputed = None;
+            }
+            Chargeback => {
+                let Some(disputed) = self
+                    .deposits
+                    .get_mut(&key)
+                    .and_then(|deposit| deposit.disputed.take())
+                else {
+                    return false;
+                };
+
+                account.held -= disputed;
+                account.locked = true;
+            }
+            Refund(value) => {
+                let Some(deposit) = self.deposits.get_mut(&key) else {
+                    return false;
+                };
+                let refunded = amount(value).unwrap_or(deposit.remaining);
+
+                if refunded > deposit.remaining
+                    || (value.is_none() && refunded.is_zero())
+                    || account.locked
+                    || account.available < refunded
+                {
+                    return false;
+                }
+
+                account.available -= refunded;
+                deposit.remaining -= refunded;
+            }
+            Authorize(value)
+                if !self.authorizations.contains_key(&key)
+                    && !account.locked
+                    && account.available >= value.into() =>
+            {
+                account.available -= Decimal::from(value);
+                account.authorized += Decimal::from(value);
+                self.authorizations.insert(key, value.into());
+            }
+            Capture(value) => {
+                let Some(&authorized) = self.authorizations.get(&key) else {
+                    return false;
+                };
+                let captured = amount(value).unwrap_or(authorized);
+                if captured > authorized || account.locked {
+                    return false;
+                }
+
+                account.authorized -= authorized;
+                account.available += authorized - captured;
+                self.authorizations.remove(&key);
+            }
+            Void => {
+                let Some(authorized) = self.authorizations.remove(&key) else {
+                    return false;
+                };
+
+                account.authorized -= authorized;
+                account.available += authorized;
+            }
+            _ => return false,
+        }
+
+        true
+    }
+}
+
+// ----------------------------------------------------------------------------
+
+fn amount() -> impl Strategy<Value = MonetaryValue> {
+    (0..=5_000i64).prop_map(|cents| {
+        Decimal::new(cents, 2)
+            .try_into()
+            .expect("positive amounts are valid")
+    })
+}
+
+fn kind() -> impl Strategy<Value = TransactionKind> {
+    use TransactionKind::*;
+
+    prop_oneof![
+        4 => amount().prop_map(Deposit),
+        2 => amount().prop_map(Withdrawal),
+        3 => proptest::option::of(amount()).prop_map(Dispute),
+        2 => Just(Resolve),
+        1 => Just(Chargeback),
+        1 => proptest::option::of(amount()).prop_map(Refund),
+        1 => amount().prop_map(Authorize),
+        1 => proptest::option::of(amount()).prop_map(Capture),
+        1 => Just(Void),
+        1 => (0..CLIENTS, amount()).prop_map(|(recipient, amount)| Transfer {
+            recipient: recipient.into(),
+            amount,
+        }),
+    ]
+}
+
+fn operation() -> impl Strategy<Value = (u16, Transaction)> {
+    (0..CLIENTS, 0..TRANSACTION_IDS, kind())
+        .prop_map(|(client, tx, kind)| (client, Transaction::new(tx, kind)))
+}
+
+proptest! {
+    #[test]
+    fn processor_matches_model(operations in proptest::collection::vec(operation(), 1..60)) {
+        let mut processor = PaymentProcessor::new();
+        let mut model = Model::default();
+
+        for (step, (client, transaction)) in operations.into_iter().enumerate() {
+            let accepted = processor.process(client, transaction).is_ok();
+            prop_assert_eq!(
+                accepted,
+                model.apply(client, transaction),
+                "outcome of step {} differs: {:?}",
+                step,
+                transaction
+            );
+        }
+
+        let accounts: BTreeMap<u16, ModelAccount> = processor
+            .accounts()
+            .map(|account| {
+                let account = account.expect("memory storage cannot fail");
+                let model_account = ModelAccount {
+                    available: account.available.into(),
+                    held: account.held.into(),
+                    authorized: account.authorized.into(),
+                    locked: account.locked,
+                };
+                (u16::from(account.client_id), model_account)
+            })
+            .collect();
+        prop_assert_eq!(accounts, model.accounts);
+    }
+
+    #[test]
+    fn funds_are_conserved(operations in proptest::collection::vec(operation(), 1..60)) {
+        use TransactionKind::*;
+
+        let mut processor = PaymentProcessor::new();
+
+        for (client, transaction) in operations {
+            let before = total_funds(&processor);
+            let accepted = processor.process(client, transaction).is_ok();
+            let after = total_funds(&processor);
+
+            match (accepted, transaction.kind) {
+                (true, Deposit(amount)) => prop_assert_eq!(after - before, amount.into()),
+                (true, Withdrawal(amount)) => prop_assert_eq!(before - after, amount.into()),
+                // Money only leaves the accounts through these transactions.
+                (true, Refund(_) | Capture(_) | Chargeback) => prop_assert!(after <= before),
+                _ => prop_assert_eq!(before, after),
+            }
+        }
+    }
+}
+
+fn total_funds(processor: &PaymentProcessor) -> Decimal {
+    processor
+        .accounts()
+        .map(|account| {
+            let account = account.expect("memory storage cannot fail");
+            Decimal::from(account.available)
+                + Decimal::from(account.held)
+                + Decimal::from(account.authorized)
+        })
+        .sum()
+}
@@ -0,0 +1,438 @@
+use std::{fmt, fs, path::Path};
+
+use rust_decimal::Decimal;
+use serde::Deserialize;
+
+use crate::{
+    account::{MonetaryValue, Transaction, TransactionKind},
+    error::ErrorCode,
+    processor::AccountLog,
+};
+
+/// Number of most recent transactions of an account over which debits are counted.
+pub const VELOCITY_WINDOW: u32 = u32::BITS;
+
+#[derive(thiserror::Error, Debug)]
+pub enum RuleError {
+    #[error("unable to read rules file")]
+    Io(#[from] std::io::Error),
+    #[error("invalid rules file")]
+    Parse(#[from] toml::de::Error),
+    #[error("rule `{0}` {1}")]
+    Invalid(String, &'static str),
+}
+
+// ----------------------------------------------------------------------------
+
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+#[serde(tag = "type", rename_all = "snake_case")]
+/// A limit on what a client can do with an account. Debits are the withdrawals and
+/// outgoing transfers requested by the client.
+pub enum Limit {
+    /// At most `withdrawals` debits among `transactions` consecutive accepted
+    /// transactions of the account.
+    MaxWithdrawals { withdrawals: u32, transactions: u32 },
+    /// No debit larger than `amount`.
+    MaxWithdrawalAmount { amount: MonetaryValue },
+    /// At most `amount` debited per day, in UTC. Only timestamped debits count.
+    MaxDailyVolume { amount: MonetaryValue },
+    /// At most `rate` disputes per deposit once the account made `min_deposits`
+    /// deposits. The account is locked by the dispute that would exceed it.
+    MaxDisputeRate {
+        /// Number of accepted disputes over the number of deposits the account made,
+        /// between 0 and 1. Amounts are not taken into account: a partial dispute
+        /// counts as a whole one, and a deposit disputed again after being resolved
+        /// counts once per dispute.
+        rate: Decimal,
+        #[serde(default)]
+        min_deposits: u64,
+    },
+}
+
+impl fmt::Display for Limit {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::MaxWithdrawals {
+                withdrawals,
+                transactions,
+            } => write!(
+                f,
+                "more than {withdrawals} withdrawals in {transactions} transactions"
+            ),
+            Self::MaxWithdrawalAmount { amount } => {
+                write!(f, "withdrawal larger than {}", Decimal::from(*amount))
+            }
+            Self::MaxDailyVolume { amount } => {
+                write!(
+                    f,
+                    "daily withdrawals larger than {}",
+                    Decimal::from(*amount)
+                )
+            }
+            Self::MaxDisputeRate { rate, .. } => write!(f, "dispute rate higher than {rate}"),
+        }
+    }
+}
+
+/// Returns the amount a transaction submitted by the owner of an account debits.
+pub(crate) fn debit(kind: TransactionKind) -> Option<MonetaryValue> {
+    match kind {
+        TransactionKind::Withdrawal(amount) | TransactionKind::Transfer { amount, .. } => {
+            Some(amount)
+        }
+        _ => None,
+    }
+}
+
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+/// A named limit, declared in a rules file as:
+///
+/// ```toml
+/// [[rule]]
+/// name = "velocity"
+/// type = "max_withdrawals"
+/// withdrawals = 3
+/// transactions = 10
+/// ```
+pub struct Rule {
+    /// Name reported when the rule rejects a transaction.
+    pub name: String,
+    #[serde(flatten)]
+    pub limit: Limit,
+}
+
+impl Rule {
+    pub fn new(name: impl Into<String>, limit: Limit) -> Self {
+        Self {
+            name: name.into(),
+            limit,
+        }
+    }
+
+    /// Checks that the limit can be enforced.
+    pub fn validate(&self) -> Result<(), RuleError> {
+        let invalid = |reason| Err(RuleError::Invalid(self.name.clone(), reason));
+
+        match self.limit {
+            Limit::MaxWithdrawals {
+                transactions: 0, ..
+            } => invalid("should span at least one transaction"),
+            Limit::MaxWithdrawals { transactions, .. } if transactions > VELOCITY_WINDOW => {
+                invalid("cannot span more than 32 transactions")
+            }
+            Limit::MaxDisputeRate { rate, .. } if rate < Decimal::ZERO || rate > Decimal::ONE => {
+                invalid("should have a rate between 0 and 1")
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Checks a transaction submitted by the owner of the account before it is
+    /// applied.
+    pub fn check(&self, log: &AccountLog, transaction: &Transaction) -> Result<(), RuleViolation> {
+        let debit = debit(transaction.kind);
+
+        let violated = match self.limit {
+            Limit::MaxWithdrawals {
+                withdrawals,
+                transactions,
+            } => {
+                debit.is_some() && log.recent_debits(transactions.saturating_sub(1)) >= withdrawals
+            }
+            Limit::MaxWithdrawalAmount { amount } => debit.is_some_and(|debit| debit > amount),
+            Limit::MaxDailyVolume { amount } => match (debit, transaction.timestamp) {
+                (Some(debit), Some(timestamp)) => {
+                    log.daily_volume(timestamp.day()).saturating_add(debit) > amount
+                }
+                _ => false,
+            },
+            Limit::MaxDisputeRate { rate, min_deposits } => {
+                matches!(transaction.kind, TransactionKind::Dispute(_))
+                    && log.deposits >= min_deposits
+                    && rate
+                        .checked_mul(Decimal::from(log.deposits))
+                        .is_some_and(|allowed| Decimal::from(log.disputes + 1) > allowed)
+            }
+        };
+
+        match violated {
+            true => Err(RuleViolation {
+                rule: self.name.clone(),
+                limit: self.limit.clone(),
+            }),
+            false => Ok(()),
+        }
+    }
+}
+
+#[derive(thiserror::Error, Debug, Clone, PartialEq)]
+#[error("rule `{rule}` rejected the transaction: {limit}")]
+pub struct RuleViolation {
+    /// Name of the violated rule.
+    pub rule: String,
+    pub limit: Limit,
+}
+
+impl RuleViolation {
+    pub fn code(&self) -> ErrorCode {
+        match self.limit {
+            Limit::MaxWithdrawals { .. } => ErrorCode::WithdrawalVelocityExceeded,
+            Limit::MaxWithdrawalAmount { .. } => ErrorCode::WithdrawalAmountExceeded,
+            Limit::MaxDailyVolume { .. } => ErrorCode::DailyVolumeExceeded,
+            Limit::MaxDisputeRate { .. } => ErrorCode::DisputeRateExceeded,
+        }
+    }
+
+    /// Whether the account is locked along with the rejection.
+    pub fn locks_account(&self) -> bool {
+        matches!(self.limit, Limit::MaxDisputeRate { .. })
+    }
+}
+
+// ----------------------------------------------------------------------------
+
+#[derive(Deserialize, Debug, Clone, Default, PartialEq)]
+/// Rules a `PaymentProcessor` evaluates before applying a transaction, declared as
+/// `[[rule]]` tables in a TOML file.
+pub struct RuleSet {
+    #[serde(default, rename = "rule")]
+    rules: Vec<Rule>,
+}
+
+impl RuleSet {
+    /// Parses and validates the rules declared in a TOML document.
+    pub fn from_toml(document: &str) -> Result<Self, RuleError> {
+        let rules: Self = toml::from_str(document)?;
+        for rule in &rules.rules {
+            rule.validate()?;
+        }
+
+        Ok(rules)
+    }
+
+    /// Reads the rules declared in a TOML file.
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, RuleError> {
+        Self::from_toml(&fs::read_to_string(path)?)
+    }
+
+    /// Adds a rule, evaluated after the previous ones.
+    pub fn with_rule(mut self, rule: Rule) -> Self {
+        self.rules.push(rule);
+        self
+    }
+
+    pub fn rules(&self) -> &[Rule] {
+        &self.rules
+    }
+
+    /// Checks a transaction against every rule, reporting the first one it violates.
+    pub fn check(&self, log: &AccountLog, transaction: &Transaction) -> Result<(), RuleViolation> {
+        self.rules
+            .iter()
+            .try_for_each(|rule| rule.check(log, transaction))
+    }
+}
+
+// ----------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use rust_decimal_macros::dec;
+
+    use super::{Limit, Rule, RuleError, RuleSet};
+    use crate::{
+        account::{MonetaryValue, Transaction, TransactionKind},
+        error::ErrorCode,
+        processor::AccountLog,
+    };
+
+    const DAY: u64 = 24 * 60 * 60;
+
+    fn amount(value: rust_decimal::Decimal) -> MonetaryValue {
+        value.try_into().expect("amount is valid")
+    }
+
+    fn withdrawal(value: rust_decimal::Decimal) -> Transaction {
+        Transaction::new(1, TransactionKind::Withdrawal(amount(value)))
+    }
+
+    #[test]
+    fn parse_rules_file() {
+        let rules = RuleSet::from_toml(
+            r#"
+            [[rule]]
+            name = "velocity"
+            type = "max_withdrawals"
+            withdrawals = 2
+            transactions = 5
+
+            [[rule]]
+            name = "large"
+            type = "max_withdrawal_amount"
+            amount = "100.5"
+
+            [[rule]]
+            name = "daily"
+            type = "max_daily_volume"
+            amount = 1000
+
+            [[rule]]
+            name = "disputes"
+            type = "max_dispute_rate"
+            rate = "0.25"
+            min_deposits = 4
+            "#,
+        )
+        .expect("rules are valid");
+
+        assert_eq!(
+            rules.rules(),
+            [
+                Rule::new(
+                    "velocity",
+                    Limit::MaxWithdrawals {
+                        withdrawals: 2,
+                        transactions: 5
+                    }
+                ),
+                Rule::new(
+                    "large",
+                    Limit::MaxWithdrawalAmount {
+                        amount: amount(dec!(100.5))
+                    }
+                ),
+                Rule::new(
+                    "daily",
+                    Limit::MaxDailyVolume {
+                        amount: amount(dec!(1000))
+                    }
+                ),
+                Rule::new(
+                    "disputes",
+                    Limit::MaxDisputeRate {
+                        rate: dec!(0.25),
+                        min_deposits: 4
+                    }
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn invalid_rules_file() {
+        let error = RuleSet::from_toml(
+            r#"
+            [[rule]]
+            name = "velocity"
+            type = "max_withdrawals"
+            withdrawals = 2
+            transactions = 40
+            "#,
+        );
+        assert!(matches!(error, Err(RuleError::Invalid(name, _)) if name == "velocity"));
+
+        let error = RuleSet::from_toml("[[rule]]\nname = \"unknown\"\ntype = \"max_deposits\"");
+        assert!(matches!(error, Err(RuleError::Parse(_))));
+    }
+
+    #[test]
+    fn max_withdrawals() {
+        let rule = Rule::new(
+            "velocity",
+            Limit::MaxWithdrawals {
+                withdrawals: 2,
+                transactions: 3,
+            },
+        );
+        let mut log = AccountLog::new(1);
+
+        // One debit among the last two transactions leaves room for another one.
+        log.recent_debits = 0b101;
+        assert!(rule.check(&log, &withdrawal(dec!(1))).is_ok());
+
+        log.recent_debits = 0b011;
+        let violation = rule
+            .check(&log, &withdrawal(dec!(1)))
+            .expect_err("a third withdrawal exceeds the limit");
+        assert_eq!(violation.code(), ErrorCode::WithdrawalVelocityExceeded);
+        assert_eq!(
+            violation.to_string(),
+            "rule `velocity` rejected the transaction: more than 2 withdrawals in 3 transactions"
+        );
+
+        // Deposits are not limited.
+        let deposit = Transaction::new(2, TransactionKind::Deposit(amount(dec!(1))));
+        assert!(rule.check(&log, &deposit).is_ok());
+    }
+
+    #[test]
+    fn max_withdrawal_amount() {
+        let rule = Rule::new(
+            "large",
+            Limit::MaxWithdrawalAmount {
+                amount: amount(dec!(100)),
+            },
+        );
+        let log = AccountLog::new(1);
+        let transfer = Transaction::new(
+            1,
+            TransactionKind::Transfer {
+                recipient: 2.into(),
+                amount: amount(dec!(100.01)),
+            },
+        );
+
+        assert!(rule.check(&log, &withdrawal(dec!(100))).is_ok());
+        assert!(rule.check(&log, &transfer).is_err());
+    }
+
+    #[test]
+    fn max_daily_volume() {
+        let rule = Rule::new(
+            "daily",
+            Limit::MaxDailyVolume {
+                amount: amount(dec!(100)),
+            },
+        );
+        let mut log = AccountLog::new(1);
+        log.volume_day = 3;
+        log.daily_volume = amount(dec!(80));
+
+        let today = withdrawal(dec!(30)).with_timestamp(3 * DAY + 10);
+        let tomorrow = withdrawal(dec!(30)).with_timestamp(4 * DAY);
+        assert_eq!(
+            rule.check(&log, &today)
+                .map_err(|violation| violation.code()),
+            Err(ErrorCode::DailyVolumeExceeded)
+        );
+        assert!(rule.check(&log, &tomorrow).is_ok());
+        // Debits without a timestamp cannot be assigned to a day.
+        assert!(rule.check(&log, &withdrawal(dec!(30))).is_ok());
+    }
+
+    #[test]
+    fn max_dispute_rate() {
+        let rule = Rule::new(
+            "disputes",
+            Limit::MaxDisputeRate {
+                rate: dec!(0.5),
+                min_deposits: 2,
+            },
+        );
+        let dispute = Transaction::new(1, TransactionKind::Dispute(None));
+        let mut log = AccountLog::new(1);
+
+        log.deposits = 1;
+        assert!(rule.check(&log, &dispute).is_ok());
+
+        log.deposits = 4;
+        log.disputes = 1;
+        assert!(rule.check(&log, &dispute).is_ok());
+
+        log.disputes = 2;
+        let violation = rule
+            .check(&log, &dispute)
+            .expect_err("a third dispute exceeds half of the deposits");
+        assert!(violation.locks_account());
+    }
+}
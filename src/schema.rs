@@ -0,0 +1,250 @@
+use std::str::FromStr;
+
+use csv::StringRecord;
+
+use crate::cli::TransactionRow;
+
+#[derive(thiserror::Error, Debug, PartialEq, Eq)]
+pub enum SchemaError {
+    #[error("missing required column `{}`, accepted names are: {}", .0.name(), .0.aliases().join(", "))]
+    MissingColumn(Column),
+    #[error("several columns map to `{}`", .0.name())]
+    DuplicateColumn(Column),
+    #[error("unknown column `{0}`")]
+    UnknownColumn(String),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+/// A field of `TransactionRow`.
+pub enum Column {
+    Type,
+    Client,
+    Tx,
+    Amount,
+    Timestamp,
+    Counterparty,
+}
+
+impl Column {
+    pub const ALL: [Self; 6] = [
+        Self::Type,
+        Self::Client,
+        Self::Tx,
+        Self::Amount,
+        Self::Timestamp,
+        Self::Counterparty,
+    ];
+
+    /// Name of the field in `TransactionRow`.
+    pub fn name(self) -> &'static str {
+        self.aliases()[0]
+    }
+
+    /// Header names recognized without configuration, the field name first.
+    pub fn aliases(self) -> &'static [&'static str] {
+        match self {
+            Self::Type => &["type", "transaction_type", "kind"],
+            Self::Client => &["client", "client_id"],
+            Self::Tx => &["tx", "tx_id", "transaction_id"],
+            Self::Amount => &["amount"],
+            Self::Timestamp => &["timestamp", "time"],
+            Self::Counterparty => &["counterparty", "recipient"],
+        }
+    }
+
+    fn is_required(self) -> bool {
+        matches!(self, Self::Type | Self::Client | Self::Tx)
+    }
+}
+
+impl FromStr for Column {
+    type Err = SchemaError;
+
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        Self::ALL
+            .into_iter()
+            .find(|column| column.name() == name)
+            .ok_or_else(|| SchemaError::UnknownColumn(name.to_owned()))
+    }
+}
+
+// ----------------------------------------------------------------------------
+
+#[derive(Debug, Clone, Default)]
+/// How the columns of an input file map to the fields of `TransactionRow`. Header names
+/// are matched case-insensitively against the aliases of each column, and columns that
+/// match none of them are ignored.
+pub struct ColumnMapping {
+    aliases: Vec<(String, Column)>,
+    /// Columns of headerless files, in order. `None` skips a column.
+    positions: Option<Vec<Option<Column>>>,
+}
+
+impl ColumnMapping {
+    /// Recognizes another header name for a column.
+    pub fn with_alias(mut self, alias: impl Into<String>, column: Column) -> Self {
+        self.aliases.push((alias.into().to_lowercase(), column));
+        self
+    }
+
+    /// Reads files without a header, with the given columns in order.
+    pub fn headerless(mut self, columns: Vec<Option<Column>>) -> Self {
+        self.positions = Some(columns);
+        self
+    }
+
+    /// Whether the first record of the file is a header.
+    pub fn has_headers(&self) -> bool {
+        self.positions.is_none()
+    }
+
+    fn column(&self, name: &str) -> Option<Column> {
+        let name = name.trim().to_lowercase();
+
+        self.aliases
+            .iter()
+            .find(|(alias, _)| *alias == name)
+            .map(|(_, column)| *column)
+            .or_else(|| {
+                Column::ALL
+                    .into_iter()
+                    .find(|column| column.aliases().contains(&name.as_str()))
+            })
+    }
+
+    /// Maps the header of a file, which is ignored for headerless files, to the columns
+    /// it contains.
+    pub fn resolve(&self, header: &StringRecord) -> Result<RowMapper, SchemaError> {
+        let columns = match &self.positions {
+            Some(positions) => positions.clone(),
+            None => header.iter().map(|name| self.column(name)).collect(),
+        };
+
+        for column in Column::ALL {
+            match columns
+                .iter()
+                .filter(|mapped| **mapped == Some(column))
+                .count()
+            {
+                0 if column.is_required() => return Err(SchemaError::MissingColumn(column)),
+                0 | 1 => {}
+                _ => return Err(SchemaError::DuplicateColumn(column)),
+            }
+        }
+
+        Ok(RowMapper {
+            // Ignored columns get a name that matches no field.
+            header: columns
+                .iter()
+                .map(|column| column.map_or("", Column::name))
+                .collect(),
+            type_index: columns
+                .iter()
+                .position(|column| *column == Some(Column::Type))
+                .expect("the type column is required"),
+        })
+    }
+}
+
+/// Deserializes the records of a file whose columns were resolved.
+pub struct RowMapper {
+    header: StringRecord,
+    type_index: usize,
+}
+
+impl RowMapper {
+    /// Deserializes a record, reading its kind case-insensitively.
+    pub fn deserialize(&self, record: &StringRecord) -> Result<TransactionRow, csv::Error> {
+        let has_uppercase_kind = record
+            .get(self.type_index)
+            .is_some_and(|kind| kind.bytes().any(|byte| byte.is_ascii_uppercase()));
+
+        if !has_uppercase_kind {
+            return record.deserialize(Some(&self.header));
+        }
+
+        let mut lowercase = StringRecord::with_capacity(record.as_slice().len(), record.len());
+        for (index, field) in record.iter().enumerate() {
+            match index == self.type_index {
+                true => lowercase.push_field(&field.to_ascii_lowercase()),
+                false => lowercase.push_field(field),
+            }
+        }
+        lowercase.set_position(record.position().cloned());
+
+        lowercase.deserialize(Some(&self.header))
+    }
+}
+
+// ----------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use csv::StringRecord;
+    use rust_decimal_macros::dec;
+
+    use super::{Column, ColumnMapping, SchemaError};
+
+    fn record(fields: &[&str]) -> StringRecord {
+        StringRecord::from(fields.to_vec())
+    }
+
+    #[test]
+    fn aliases_and_extra_columns() {
+        let mapping = ColumnMapping::default().with_alias("Reference", Column::Tx);
+        let mapper = mapping
+            .resolve(&record(&[
+                "Transaction_Type",
+                "client_id",
+                "reference",
+                "amount",
+                "channel",
+            ]))
+            .expect("required columns are present");
+
+        let row = mapper
+            .deserialize(&record(&["DEPOSIT", "1", "7", "2.5", "web"]))
+            .expect("row is valid");
+        assert_eq!((row.client, row.tx, row.amount), (1, 7, Some(dec!(2.5))));
+        assert!(matches!(row.r#type, crate::cli::TransactionKind::Deposit));
+    }
+
+    #[test]
+    fn headerless() {
+        let mapping = ColumnMapping::default().headerless(vec![
+            Some(Column::Client),
+            None,
+            Some(Column::Tx),
+            Some(Column::Type),
+        ]);
+        assert!(!mapping.has_headers());
+
+        let mapper = mapping
+            .resolve(&record(&["3", "ignored", "4", "dispute"]))
+            .expect("required columns are mapped");
+        let row = mapper
+            .deserialize(&record(&["3", "ignored", "4", "Dispute"]))
+            .expect("row is valid");
+        assert_eq!((row.client, row.tx, row.amount), (3, 4, None));
+    }
+
+    #[test]
+    fn invalid_columns() {
+        let mapping = ColumnMapping::default();
+
+        assert_eq!(
+            mapping.resolve(&record(&["type", "tx", "amount"])).err(),
+            Some(SchemaError::MissingColumn(Column::Client))
+        );
+        assert_eq!(
+            mapping
+                .resolve(&record(&["type", "client", "client_id", "tx"]))
+                .err(),
+            Some(SchemaError::DuplicateColumn(Column::Client))
+        );
+        assert_eq!(
+            "account".parse::<Column>().err(),
+            Some(SchemaError::UnknownColumn("account".to_owned()))
+        );
+    }
+}
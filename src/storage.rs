@@ -0,0 +1,133 @@
+use crate::{
+    account::{ClientId, Transaction, TransactionId},
+    processor::{AccountLog, AuthorizationRecord, DepositRecord, RejectedTransaction},
+};
+
+mod memory;
+#[cfg(feature = "sled")]
+mod sled;
+#[cfg(feature = "sqlite")]
+mod sqlite;
+
+#[cfg(feature = "sled")]
+pub use self::sled::SledStorage;
+pub use memory::MemoryStorage;
+#[cfg(feature = "sqlite")]
+pub use sqlite::SqliteStorage;
+
+#[derive(thiserror::Error, Debug)]
+#[error(transparent)]
+/// A failure of the underlying storage, e.g. an I/O or a decoding error.
+pub struct StorageError(#[from] anyhow::Error);
+
+pub type StorageResult<T> = Result<T, StorageError>;
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+/// Number of records kept by a storage.
+pub struct StorageStats {
+    pub accounts: u64,
+    pub deposits: u64,
+    pub authorizations: u64,
+    pub transactions: u64,
+    pub rejections: u64,
+}
+
+// ----------------------------------------------------------------------------
+
+/// Where a `PaymentProcessor` keeps accounts, their transaction history and the
+/// dispute state of their transactions.
+///
+/// Records are read and written by value, which lets implementations keep them
+/// outside of memory.
+pub trait Storage {
+    /// Returns the log of an account, if it exists.
+    fn account(&self, client_id: ClientId) -> StorageResult<Option<AccountLog>>;
+    /// Creates or replaces the log of an account.
+    fn save_account(&mut self, log: &AccountLog) -> StorageResult<()>;
+    /// Returns the logs of every existing account, in no particular order.
+    fn accounts(&self) -> Box<dyn Iterator<Item = StorageResult<AccountLog>> + '_>;
+
+    /// Returns the dispute state of a deposit, if the deposit exists.
+    fn deposit(
+        &self,
+        client_id: ClientId,
+        tx: TransactionId,
+    ) -> StorageResult<Option<DepositRecord>>;
+    /// Creates or replaces the dispute state of a deposit.
+    fn save_deposit(
+        &mut self,
+        client_id: ClientId,
+        tx: TransactionId,
+        record: &DepositRecord,
+    ) -> StorageResult<()>;
+    /// Returns the dispute state of every deposit of an account.
+    fn deposits(&self, client_id: ClientId) -> StorageResult<Vec<(TransactionId, DepositRecord)>>;
+    /// Forgets a deposit once it cannot be disputed anymore.
+    fn remove_deposit(&mut self, client_id: ClientId, tx: TransactionId) -> StorageResult<()>;
+
+    /// Returns a pending authorization, if it exists.
+    fn authorization(
+        &self,
+        client_id: ClientId,
+        tx: TransactionId,
+    ) -> StorageResult<Option<AuthorizationRecord>>;
+    /// Returns every pending authorization of an account.
+    fn authorizations(
+        &self,
+        client_id: ClientId,
+    ) -> StorageResult<Vec<(TransactionId, AuthorizationRecord)>>;
+    /// Creates or replaces a pending authorization.
+    fn save_authorization(
+        &mut self,
+        client_id: ClientId,
+        tx: TransactionId,
+        record: &AuthorizationRecord,
+    ) -> StorageResult<()>;
+    /// Removes a pending authorization once it has been captured or voided.
+    fn remove_authorization(&mut self, client_id: ClientId, tx: TransactionId)
+        -> StorageResult<()>;
+
+    /// Records an accepted transaction at the end of an account's history.
+    fn append_history(
+        &mut self,
+        client_id: ClientId,
+        transaction: &Transaction,
+    ) -> StorageResult<()>;
+    /// Returns the accepted transactions of an account, oldest first.
+    fn history(&self, client_id: ClientId) -> StorageResult<Vec<Transaction>>;
+
+    /// Records a rejected transaction at the end of an account's rejections.
+    fn append_rejection(
+        &mut self,
+        client_id: ClientId,
+        rejection: &RejectedTransaction,
+    ) -> StorageResult<()>;
+    /// Returns the rejected transactions of an account, oldest first.
+    fn rejections(&self, client_id: ClientId) -> StorageResult<Vec<RejectedTransaction>>;
+
+    /// Counts the records currently kept.
+    fn stats(&self) -> StorageResult<StorageStats>;
+
+    /// Returns the number of input rows whose changes were persisted, which are
+    /// skipped when processing resumes.
+    fn processed_rows(&self) -> StorageResult<u64> {
+        Ok(0)
+    }
+    /// Records that the first `rows` input rows are processed. Called before the
+    /// changes of the last of these rows are made, so that the position is persisted
+    /// along with them.
+    fn save_processed_rows(&mut self, _rows: u64) -> StorageResult<()> {
+        Ok(())
+    }
+
+    /// Called once every write caused by a transaction has been made, i.e. when the
+    /// records are consistent with each other.
+    fn checkpoint(&mut self) -> StorageResult<()> {
+        Ok(())
+    }
+
+    /// Persists every change made so far.
+    fn flush(&mut self) -> StorageResult<()> {
+        Ok(())
+    }
+}
@@ -0,0 +1,176 @@
+use std::collections::HashMap;
+
+use anyhow::Context;
+
+use super::{Storage, StorageResult, StorageStats};
+use crate::{
+    account::{ClientId, Transaction, TransactionId},
+    packed::PackedTransaction,
+    processor::{AccountLog, AuthorizationRecord, DepositRecord, RejectedTransaction},
+};
+
+#[derive(Default)]
+/// Keeps everything in memory until the storage is dropped.
+pub struct MemoryStorage {
+    accounts: HashMap<ClientId, AccountLog>,
+    histories: HashMap<ClientId, Vec<PackedTransaction>>,
+    /// Rejected transactions are not packed, as their amount may be out of range.
+    rejections: HashMap<ClientId, Vec<RejectedTransaction>>,
+    deposits: HashMap<ClientId, HashMap<TransactionId, DepositRecord>>,
+    authorizations: HashMap<ClientId, HashMap<TransactionId, AuthorizationRecord>>,
+}
+
+impl Storage for MemoryStorage {
+    fn account(&self, client_id: ClientId) -> StorageResult<Option<AccountLog>> {
+        Ok(self.accounts.get(&client_id).cloned())
+    }
+
+    fn save_account(&mut self, log: &AccountLog) -> StorageResult<()> {
+        self.accounts.insert(log.state().client_id, log.clone());
+        Ok(())
+    }
+
+    fn accounts(&self) -> Box<dyn Iterator<Item = StorageResult<AccountLog>> + '_> {
+        Box::new(self.accounts.values().cloned().map(Ok))
+    }
+
+    fn deposit(
+        &self,
+        client_id: ClientId,
+        tx: TransactionId,
+    ) -> StorageResult<Option<DepositRecord>> {
+        Ok(self
+            .deposits
+            .get(&client_id)
+            .and_then(|deposits| deposits.get(&tx))
+            .copied())
+    }
+
+    fn save_deposit(
+        &mut self,
+        client_id: ClientId,
+        tx: TransactionId,
+        record: &DepositRecord,
+    ) -> StorageResult<()> {
+        self.deposits
+            .entry(client_id)
+            .or_default()
+            .insert(tx, *record);
+        Ok(())
+    }
+
+    fn deposits(&self, client_id: ClientId) -> StorageResult<Vec<(TransactionId, DepositRecord)>> {
+        Ok(self
+            .deposits
+            .get(&client_id)
+            .map(|deposits| deposits.iter().map(|(tx, record)| (*tx, *record)).collect())
+            .unwrap_or_default())
+    }
+
+    fn remove_deposit(&mut self, client_id: ClientId, tx: TransactionId) -> StorageResult<()> {
+        if let Some(deposits) = self.deposits.get_mut(&client_id) {
+            deposits.remove(&tx);
+        }
+        Ok(())
+    }
+
+    fn authorization(
+        &self,
+        client_id: ClientId,
+        tx: TransactionId,
+    ) -> StorageResult<Option<AuthorizationRecord>> {
+        Ok(self
+            .authorizations
+            .get(&client_id)
+            .and_then(|authorizations| authorizations.get(&tx))
+            .copied())
+    }
+
+    fn authorizations(
+        &self,
+        client_id: ClientId,
+    ) -> StorageResult<Vec<(TransactionId, AuthorizationRecord)>> {
+        Ok(self
+            .authorizations
+            .get(&client_id)
+            .map(|authorizations| {
+                authorizations
+                    .iter()
+                    .map(|(tx, record)| (*tx, *record))
+                    .collect()
+            })
+            .unwrap_or_default())
+    }
+
+    fn save_authorization(
+        &mut self,
+        client_id: ClientId,
+        tx: TransactionId,
+        record: &AuthorizationRecord,
+    ) -> StorageResult<()> {
+        self.authorizations
+            .entry(client_id)
+            .or_default()
+            .insert(tx, *record);
+        Ok(())
+    }
+
+    fn remove_authorization(
+        &mut self,
+        client_id: ClientId,
+        tx: TransactionId,
+    ) -> StorageResult<()> {
+        if let Some(authorizations) = self.authorizations.get_mut(&client_id) {
+            authorizations.remove(&tx);
+        }
+        Ok(())
+    }
+
+    fn append_history(
+        &mut self,
+        client_id: ClientId,
+        transaction: &Transaction,
+    ) -> StorageResult<()> {
+        self.histories
+            .entry(client_id)
+            .or_default()
+            .push(PackedTransaction::try_from(*transaction).context("unable to pack transaction")?);
+        Ok(())
+    }
+
+    fn history(&self, client_id: ClientId) -> StorageResult<Vec<Transaction>> {
+        Ok(self
+            .histories
+            .get(&client_id)
+            .map(|history| history.iter().copied().map(Transaction::from).collect())
+            .unwrap_or_default())
+    }
+
+    fn append_rejection(
+        &mut self,
+        client_id: ClientId,
+        rejection: &RejectedTransaction,
+    ) -> StorageResult<()> {
+        self.rejections
+            .entry(client_id)
+            .or_default()
+            .push(*rejection);
+        Ok(())
+    }
+
+    fn rejections(&self, client_id: ClientId) -> StorageResult<Vec<RejectedTransaction>> {
+        Ok(self.rejections.get(&client_id).cloned().unwrap_or_default())
+    }
+
+    fn stats(&self) -> StorageResult<StorageStats> {
+        let count = |len: usize| len as u64;
+
+        Ok(StorageStats {
+            accounts: count(self.accounts.len()),
+            deposits: count(self.deposits.values().map(HashMap::len).sum()),
+            authorizations: count(self.authorizations.values().map(HashMap::len).sum()),
+            transactions: count(self.histories.values().map(Vec::len).sum()),
+            rejections: count(self.rejections.values().map(Vec::len).sum()),
+        })
+    }
+}
@@ -0,0 +1,977 @@
+use std::{collections::BTreeMap, convert::Infallible, path::Path};
+
+use anyhow::{anyhow, Context};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use sled::transaction::{ConflictableTransactionResult, Transactional};
+
+use super::{Storage, StorageResult, StorageStats};
+use crate::{
+    account::{Account, ClientId, MonetaryValue, Timestamp, Transaction, TransactionId},
+    packed::{CompactMoney, OutOfRange},
+    processor::{AccountLog, AuthorizationRecord, DepositRecord, RejectedTransaction},
+};
+
+/// Number of transactions written per database transaction by default.
+const DEFAULT_BATCH_SIZE: usize = 1000;
+
+/// Version of the encoding of the records, bumped whenever a record changes. Databases
+/// written before it was recorded have no version, and are told apart by the layout of
+/// their records.
+const FORMAT_VERSION: u64 = 1;
+
+/// Keeps everything in an embedded on-disk database, so that only the records in
+/// use are held in memory.
+///
+/// Records are encoded with bincode and keyed by big-endian ids, which keeps the
+/// transactions of an account next to each other and in order. Databases written in an
+/// earlier format are upgraded when opened, and those written in a newer one refused.
+///
+/// Writes are buffered and applied to the trees in a single database transaction
+/// every `batch_size` payment transactions, which is then flushed to disk. Changes
+/// that are not committed yet are lost if the process crashes or if the storage is
+/// dropped without being flushed, but the database stays consistent.
+pub struct SledStorage {
+    db: sled::Db,
+    accounts: BufferedTree,
+    deposits: BufferedTree,
+    authorizations: BufferedTree,
+    histories: BufferedTree,
+    rejections: BufferedTree,
+    /// Holds the format version and the number of processed input rows.
+    metadata: BufferedTree,
+    batch_size: usize,
+    /// Payment transactions written since the last commit.
+    pending: usize,
+}
+
+impl SledStorage {
+    /// Opens the database at the given path, creating it if it does not exist.
+    pub fn open(path: impl AsRef<Path>) -> StorageResult<Self> {
+        let db = sled::open(path).context("unable to open database")?;
+        Self::from_db(db)
+    }
+
+    fn from_db(db: sled::Db) -> StorageResult<Self> {
+        let tree = |name: &str| {
+            db.open_tree(name)
+                .map(BufferedTree::new)
+                .with_context(|| format!("unable to open the {name} tree"))
+        };
+
+        let mut storage = Self {
+            accounts: tree("accounts")?,
+            deposits: tree("deposits")?,
+            authorizations: tree("authorizations")?,
+            histories: tree("histories")?,
+            rejections: tree("rejections")?,
+            metadata: tree("metadata")?,
+            db,
+            batch_size: DEFAULT_BATCH_SIZE,
+            pending: 0,
+        };
+        storage.upgrade()?;
+        Ok(storage)
+    }
+
+    /// Rewrites the records of a database written in an earlier format, and records
+    /// the current format in new databases.
+    fn upgrade(&mut self) -> StorageResult<()> {
+        let recorded: Option<u64> = self.metadata.get(FORMAT_VERSION_KEY)?;
+
+        match recorded {
+            Some(version) if version > FORMAT_VERSION => {
+                return Err(anyhow!(
+                    "database format version {version} is newer than the supported version \
+                     {FORMAT_VERSION}"
+                )
+                .into())
+            }
+            Some(_) => return Ok(()),
+            None => {}
+        }
+
+        match self.unversioned_layout()? {
+            None | Some(UnversionedLayout::Rules) => {}
+            Some(UnversionedLayout::Initial) => {
+                return Err(anyhow!(
+                    "unsupported format version: the database was written before deposits \
+                     were numbered, and cannot be upgraded"
+                )
+                .into())
+            }
+            Some(UnversionedLayout::FullSizeAmounts) => {
+                self.deposits.rewrite(|record: FullSizeDepositRecord| {
+                    Ok(
+                        DepositRecord::try_from(record)
+                            .context("deposit amount is out of range")?,
+                    )
+                })?;
+                self.authorizations
+                    .rewrite(|record: FullSizeAuthorizationRecord| {
+                        Ok(AuthorizationRecord::try_from(record)
+                            .context("authorization amount is out of range")?)
+                    })?;
+                self.accounts
+                    .rewrite(|log: CompactionAccountLog| Ok(AccountLog::from(log)))?;
+            }
+            Some(UnversionedLayout::Packed) => {
+                self.accounts
+                    .rewrite(|log: CompactionAccountLog| Ok(AccountLog::from(log)))?;
+            }
+            Some(UnversionedLayout::Rejections) => {
+                self.accounts
+                    .rewrite(|log: RejectionsAccountLog| Ok(AccountLog::from(log)))?;
+            }
+        }
+
+        self.metadata.insert(FORMAT_VERSION_KEY, &FORMAT_VERSION)?;
+        self.commit()
+    }
+
+    /// Identifies the layout of a database written before the format version was
+    /// recorded, from the size of its account records and the first byte of its
+    /// amounts. Returns `None` for a new database.
+    fn unversioned_layout(&self) -> StorageResult<Option<UnversionedLayout>> {
+        let first = |tree: &BufferedTree| -> StorageResult<Option<sled::IVec>> {
+            Ok(tree
+                .inner
+                .first()
+                .context("unable to read record")?
+                .map(|(_, bytes)| bytes))
+        };
+
+        let Some(account) = first(&self.accounts)? else {
+            return Ok(None);
+        };
+
+        // An account takes 51 bytes and its last timestamp 1 or 9, followed by 24
+        // bytes of compaction counters, 24 of rejection counters and 36 of rule state.
+        let layout = match account.len() {
+            52 | 60 => UnversionedLayout::Initial,
+            76 | 84 => {
+                // Full-size amounts start with the lowest byte of the flags of a
+                // `Decimal`, which is always zero, and packed ones with their scale
+                // plus one.
+                let amount = match first(&self.deposits)? {
+                    Some(deposit) => Some(deposit),
+                    None => first(&self.authorizations)?,
+                };
+
+                match amount {
+                    Some(bytes) if bytes.first() == Some(&0) => UnversionedLayout::FullSizeAmounts,
+                    _ => UnversionedLayout::Packed,
+                }
+            }
+            100 | 108 => UnversionedLayout::Rejections,
+            136 | 144 => UnversionedLayout::Rules,
+            size => {
+                return Err(anyhow!(
+                    "unsupported format version: account records of {size} bytes match no \
+                     known format"
+                )
+                .into())
+            }
+        };
+
+        Ok(Some(layout))
+    }
+
+    /// Commits the written records every `batch_size` payment transactions.
+    pub fn with_batch_size(mut self, batch_size: usize) -> Self {
+        self.batch_size = batch_size.max(1);
+        self
+    }
+
+    /// Returns a key that sorts after every key previously returned for the client.
+    fn sequence_key(&self, client_id: ClientId) -> StorageResult<[u8; 10]> {
+        // Generated ids only ever grow, even across restarts, so they keep records in
+        // insertion order.
+        let sequence = self.db.generate_id().context("unable to generate id")?;
+
+        let mut key = [0; 10];
+        key[..2].copy_from_slice(&client_key(client_id));
+        key[2..].copy_from_slice(&sequence.to_be_bytes());
+        Ok(key)
+    }
+
+    fn trees_mut(&mut self) -> [&mut BufferedTree; 6] {
+        [
+            &mut self.accounts,
+            &mut self.deposits,
+            &mut self.authorizations,
+            &mut self.histories,
+            &mut self.rejections,
+            &mut self.metadata,
+        ]
+    }
+
+    /// Applies the buffered writes of every tree at once and flushes them to disk.
+    fn commit(&mut self) -> StorageResult<()> {
+        let trees = self.trees_mut();
+        let batches: Vec<sled::Batch> = trees.iter().map(|tree| tree.batch()).collect();
+        let inner: Vec<&sled::Tree> = trees.iter().map(|tree| &tree.inner).collect();
+
+        inner
+            .as_slice()
+            .transaction(|views| -> ConflictableTransactionResult<(), Infallible> {
+                for (view, batch) in views.iter().zip(&batches) {
+                    view.apply_batch(batch)?;
+                }
+                Ok(())
+            })
+            .context("unable to commit transaction")?;
+
+        for tree in self.trees_mut() {
+            tree.pending.clear();
+        }
+        self.pending = 0;
+
+        self.db.flush().context("unable to flush database")?;
+        Ok(())
+    }
+}
+
+/// Key of the number of processed input rows in the metadata tree.
+const PROCESSED_ROWS_KEY: &[u8] = b"processed_rows";
+
+/// Key of the format version in the metadata tree.
+const FORMAT_VERSION_KEY: &[u8] = b"format_version";
+
+// ----------------------------------------------------------------------------
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+/// Layouts of the records of databases written before the format version was
+/// recorded, from the oldest.
+enum UnversionedLayout {
+    /// Accounts only hold their balances and last timestamp, and deposits do not record
+    /// their position, which retention needs.
+    Initial,
+    /// Accounts count their deposits for compaction, and deposits and authorizations
+    /// hold full-size amounts.
+    FullSizeAmounts,
+    /// Same as `FullSizeAmounts`, with packed amounts.
+    Packed,
+    /// Accounts count their rejected transactions.
+    Rejections,
+    /// Accounts hold the state of the fraud rules, as in format version 1.
+    Rules,
+}
+
+#[derive(Deserialize)]
+/// Account records of the `FullSizeAmounts` and `Packed` layouts.
+struct CompactionAccountLog {
+    state: Account,
+    last_timestamp: Option<Timestamp>,
+    deposits: u64,
+    retained_deposits: u64,
+    next_compaction: u64,
+}
+
+impl From<CompactionAccountLog> for AccountLog {
+    fn from(log: CompactionAccountLog) -> Self {
+        let client_id = log.state.client_id;
+        Self {
+            state: log.state,
+            last_timestamp: log.last_timestamp,
+            deposits: log.deposits,
+            retained_deposits: log.retained_deposits,
+            next_compaction: log.next_compaction,
+            ..AccountLog::new(client_id)
+        }
+    }
+}
+
+#[derive(Deserialize)]
+/// Account records of the `Rejections` layout.
+struct RejectionsAccountLog {
+    state: Account,
+    last_timestamp: Option<Timestamp>,
+    deposits: u64,
+    retained_deposits: u64,
+    next_compaction: u64,
+    rejected: u64,
+    rejected_amount: MonetaryValue,
+}
+
+impl From<RejectionsAccountLog> for AccountLog {
+    fn from(log: RejectionsAccountLog) -> Self {
+        let client_id = log.state.client_id;
+        Self {
+            state: log.state,
+            last_timestamp: log.last_timestamp,
+            deposits: log.deposits,
+            retained_deposits: log.retained_deposits,
+            next_compaction: log.next_compaction,
+            rejected: log.rejected,
+            rejected_amount: log.rejected_amount,
+            ..AccountLog::new(client_id)
+        }
+    }
+}
+
+#[derive(Deserialize)]
+/// Deposit records of the `FullSizeAmounts` layout.
+struct FullSizeDepositRecord {
+    disputable: MonetaryValue,
+    disputed: Option<MonetaryValue>,
+    timestamp: Option<Timestamp>,
+    sequence: u64,
+}
+
+impl TryFrom<FullSizeDepositRecord> for DepositRecord {
+    type Error = OutOfRange;
+
+    fn try_from(record: FullSizeDepositRecord) -> Result<Self, Self::Error> {
+        Ok(Self {
+            disputable: record.disputable.try_into()?,
+            disputed: record.disputed.map(CompactMoney::try_from).transpose()?,
+            timestamp: record.timestamp,
+            sequence: record.sequence,
+        })
+    }
+}
+
+#[derive(Deserialize)]
+/// Authorization records of the `FullSizeAmounts` layout.
+struct FullSizeAuthorizationRecord {
+    amount: MonetaryValue,
+    timestamp: Option<Timestamp>,
+}
+
+impl TryFrom<FullSizeAuthorizationRecord> for AuthorizationRecord {
+    type Error = OutOfRange;
+
+    fn try_from(record: FullSizeAuthorizationRecord) -> Result<Self, Self::Error> {
+        Ok(Self {
+            amount: record.amount.try_into()?,
+            timestamp: record.timestamp,
+        })
+    }
+}
+
+// ----------------------------------------------------------------------------
+
+fn client_key(client_id: ClientId) -> [u8; 2] {
+    u16::from(client_id).to_be_bytes()
+}
+
+fn transaction_key(client_id: ClientId, tx: TransactionId) -> [u8; 6] {
+    let mut key = [0; 6];
+    key[..2].copy_from_slice(&client_key(client_id));
+    key[2..].copy_from_slice(&u32::from(tx).to_be_bytes());
+    key
+}
+
+fn encode<T: Serialize>(value: &T) -> StorageResult<Vec<u8>> {
+    Ok(bincode::serialize(value).context("unable to encode record")?)
+}
+
+fn decode<T: DeserializeOwned>(bytes: &[u8]) -> StorageResult<T> {
+    Ok(bincode::deserialize(bytes).context("unable to decode record")?)
+}
+
+// ----------------------------------------------------------------------------
+
+/// A tree along with the writes that were not committed to it yet. Reads see the
+/// buffered writes.
+struct BufferedTree {
+    inner: sled::Tree,
+    /// Buffered records by key, `None` removing the record.
+    pending: BTreeMap<Vec<u8>, Option<Vec<u8>>>,
+}
+
+impl BufferedTree {
+    fn new(inner: sled::Tree) -> Self {
+        Self {
+            inner,
+            pending: BTreeMap::new(),
+        }
+    }
+
+    fn get<T: DeserializeOwned>(&self, key: &[u8]) -> StorageResult<Option<T>> {
+        match self.pending.get(key) {
+            Some(bytes) => bytes.as_deref().map(decode).transpose(),
+            None => self
+                .inner
+                .get(key)
+                .context("unable to read record")?
+                .map(|bytes| decode(&bytes))
+                .transpose(),
+        }
+    }
+
+    fn insert<T: Serialize>(&mut self, key: &[u8], value: &T) -> StorageResult<()> {
+        self.pending.insert(key.to_vec(), Some(encode(value)?));
+        Ok(())
+    }
+
+    fn remove(&mut self, key: &[u8]) {
+        self.pending.insert(key.to_vec(), None);
+    }
+
+    /// Returns the encoded records whose key starts with `prefix`, in key order.
+    fn scan_prefix(&self, prefix: &[u8]) -> StorageResult<Vec<(Vec<u8>, Vec<u8>)>> {
+        let mut records = BTreeMap::new();
+
+        for entry in self.inner.scan_prefix(prefix) {
+            let (key, bytes) = entry.context("unable to read record")?;
+            records.insert(key.to_vec(), bytes.to_vec());
+        }
+
+        let buffered = self
+            .pending
+            .range(prefix.to_vec()..)
+            .take_while(|(key, _)| key.starts_with(prefix));
+        for (key, bytes) in buffered {
+            match bytes {
+                Some(bytes) => records.insert(key.clone(), bytes.clone()),
+                None => records.remove(key),
+            };
+        }
+
+        Ok(records.into_iter().collect())
+    }
+
+    /// Returns every record, in no particular order.
+    fn values<T: DeserializeOwned>(&self) -> impl Iterator<Item = StorageResult<T>> + '_ {
+        let committed = self
+            .inner
+            .iter()
+            .filter(|entry| {
+                entry
+                    .as_ref()
+                    .map_or(true, |(key, _)| !self.pending.contains_key(key.as_ref()))
+            })
+            .map(|entry| decode(&entry.context("unable to read record")?.1));
+        let buffered = self.pending.values().flatten().map(|bytes| decode(bytes));
+
+        committed.chain(buffered)
+    }
+
+    fn len(&self) -> StorageResult<u64> {
+        let mut len = self.inner.len() as u64;
+
+        for (key, bytes) in &self.pending {
+            let committed = self
+                .inner
+                .contains_key(key)
+                .context("unable to read record")?;
+
+            match (committed, bytes.is_some()) {
+                (false, true) => len += 1,
+                (true, false) => len -= 1,
+                _ => {}
+            }
+        }
+
+        Ok(len)
+    }
+
+    /// Decodes every committed record in an earlier layout, and buffers it in the
+    /// current one.
+    fn rewrite<Old: DeserializeOwned, New: Serialize>(
+        &mut self,
+        convert: impl Fn(Old) -> StorageResult<New>,
+    ) -> StorageResult<()> {
+        let records: Vec<_> = self
+            .inner
+            .iter()
+            .collect::<Result<_, _>>()
+            .context("unable to read record")?;
+
+        for (key, bytes) in records {
+            let record = convert(decode(&bytes)?)?;
+            self.insert(&key, &record)?;
+        }
+        Ok(())
+    }
+
+    /// Returns the buffered writes as a batch.
+    fn batch(&self) -> sled::Batch {
+        let mut batch = sled::Batch::default();
+
+        for (key, bytes) in &self.pending {
+            match bytes {
+                Some(bytes) => batch.insert(key.as_slice(), bytes.as_slice()),
+                None => batch.remove(key.as_slice()),
+            }
+        }
+
+        batch
+    }
+
+    /// Returns the records keyed by `transaction_key` that belong to a client.
+    fn transactions<T: DeserializeOwned>(
+        &self,
+        client_id: ClientId,
+    ) -> StorageResult<Vec<(TransactionId, T)>> {
+        self.scan_prefix(&client_key(client_id))?
+            .into_iter()
+            .map(|(key, bytes)| {
+                let tx = key[2..]
+                    .try_into()
+                    .map(u32::from_be_bytes)
+                    .context("record key is malformed")?;
+
+                Ok((tx.into(), decode(&bytes)?))
+            })
+            .collect()
+    }
+
+    /// Returns the records keyed by `SledStorage::sequence_key` that belong to a
+    /// client, in insertion order.
+    fn sequence<T: DeserializeOwned>(&self, client_id: ClientId) -> StorageResult<Vec<T>> {
+        self.scan_prefix(&client_key(client_id))?
+            .into_iter()
+            .map(|(_, bytes)| decode(&bytes))
+            .collect()
+    }
+}
+
+// ----------------------------------------------------------------------------
+
+impl Storage for SledStorage {
+    fn account(&self, client_id: ClientId) -> StorageResult<Option<AccountLog>> {
+        self.accounts.get(&client_key(client_id))
+    }
+
+    fn save_account(&mut self, log: &AccountLog) -> StorageResult<()> {
+        self.accounts
+            .insert(&client_key(log.state().client_id), log)
+    }
+
+    fn accounts(&self) -> Box<dyn Iterator<Item = StorageResult<AccountLog>> + '_> {
+        Box::new(self.accounts.values())
+    }
+
+    fn deposit(
+        &self,
+        client_id: ClientId,
+        tx: TransactionId,
+    ) -> StorageResult<Option<DepositRecord>> {
+        self.deposits.get(&transaction_key(client_id, tx))
+    }
+
+    fn save_deposit(
+        &mut self,
+        client_id: ClientId,
+        tx: TransactionId,
+        record: &DepositRecord,
+    ) -> StorageResult<()> {
+        self.deposits
+            .insert(&transaction_key(client_id, tx), record)
+    }
+
+    fn deposits(&self, client_id: ClientId) -> StorageResult<Vec<(TransactionId, DepositRecord)>> {
+        self.deposits.transactions(client_id)
+    }
+
+    fn remove_deposit(&mut self, client_id: ClientId, tx: TransactionId) -> StorageResult<()> {
+        self.deposits.remove(&transaction_key(client_id, tx));
+        Ok(())
+    }
+
+    fn authorization(
+        &self,
+        client_id: ClientId,
+        tx: TransactionId,
+    ) -> StorageResult<Option<AuthorizationRecord>> {
+        self.authorizations.get(&transaction_key(client_id, tx))
+    }
+
+    fn authorizations(
+        &self,
+        client_id: ClientId,
+    ) -> StorageResult<Vec<(TransactionId, AuthorizationRecord)>> {
+        self.authorizations.transactions(client_id)
+    }
+
+    fn save_authorization(
+        &mut self,
+        client_id: ClientId,
+        tx: TransactionId,
+        record: &AuthorizationRecord,
+    ) -> StorageResult<()> {
+        self.authorizations
+            .insert(&transaction_key(client_id, tx), record)
+    }
+
+    fn remove_authorization(
+        &mut self,
+        client_id: ClientId,
+        tx: TransactionId,
+    ) -> StorageResult<()> {
+        self.authorizations.remove(&transaction_key(client_id, tx));
+        Ok(())
+    }
+
+    fn append_history(
+        &mut self,
+        client_id: ClientId,
+        transaction: &Transaction,
+    ) -> StorageResult<()> {
+        let key = self.sequence_key(client_id)?;
+        self.histories.insert(&key, transaction)
+    }
+
+    fn history(&self, client_id: ClientId) -> StorageResult<Vec<Transaction>> {
+        self.histories.sequence(client_id)
+    }
+
+    fn append_rejection(
+        &mut self,
+        client_id: ClientId,
+        rejection: &RejectedTransaction,
+    ) -> StorageResult<()> {
+        let key = self.sequence_key(client_id)?;
+        self.rejections.insert(&key, rejection)
+    }
+
+    fn rejections(&self, client_id: ClientId) -> StorageResult<Vec<RejectedTransaction>> {
+        self.rejections.sequence(client_id)
+    }
+
+    fn stats(&self) -> StorageResult<StorageStats> {
+        Ok(StorageStats {
+            accounts: self.accounts.len()?,
+            deposits: self.deposits.len()?,
+            authorizations: self.authorizations.len()?,
+            transactions: self.histories.len()?,
+            rejections: self.rejections.len()?,
+        })
+    }
+
+    fn processed_rows(&self) -> StorageResult<u64> {
+        Ok(self.metadata.get(PROCESSED_ROWS_KEY)?.unwrap_or(0))
+    }
+
+    fn save_processed_rows(&mut self, rows: u64) -> StorageResult<()> {
+        self.metadata.insert(PROCESSED_ROWS_KEY, &rows)
+    }
+
+    fn checkpoint(&mut self) -> StorageResult<()> {
+        self.pending += 1;
+        if self.pending >= self.batch_size {
+            self.commit()?;
+        }
+        Ok(())
+    }
+
+    fn flush(&mut self) -> StorageResult<()> {
+        self.commit()
+    }
+}
+
+// ----------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use rust_decimal_macros::dec;
+
+    use super::SledStorage;
+    use crate::{
+        account::{Account, MonetaryValue, Timestamp, Transaction, TransactionKind},
+        processor::{AccountLog, PaymentProcessor},
+    };
+
+    fn amount(value: rust_decimal::Decimal) -> MonetaryValue {
+        value.try_into().expect("amount is valid")
+    }
+
+    fn deposit(id: u32, value: rust_decimal::Decimal) -> Transaction {
+        Transaction::new(id, TransactionKind::Deposit(amount(value)))
+    }
+
+    fn temporary_db() -> sled::Db {
+        sled::Config::new()
+            .temporary(true)
+            .open()
+            .expect("a temporary database should open")
+    }
+
+    /// Opens a new storage on the database, which only sees the committed records as
+    /// if the process had been restarted. Reopening the same path right away is not
+    /// reliable, as sled may release its file lock only after a while.
+    fn reopen(db: &sled::Db) -> PaymentProcessor<SledStorage> {
+        PaymentProcessor::with_storage(SledStorage::from_db(db.clone()).expect("trees should open"))
+    }
+
+    #[test]
+    fn process_on_disk() {
+        let mut processor = reopen(&temporary_db());
+
+        assert!(processor.process(1, deposit(1, dec!(2.5))).is_ok());
+        assert!(processor.process(1, deposit(2, dec!(1.0))).is_ok());
+        assert!(processor
+            .process(1, Transaction::new(1, TransactionKind::Dispute(None)))
+            .is_ok());
+
+        let account = processor
+            .account_log(1)
+            .expect("storage should be readable")
+            .expect("an account should have been created");
+
+        assert_eq!(account.state().available, amount(dec!(1.0)));
+        assert_eq!(account.state().held, amount(dec!(2.5)));
+        assert_eq!(
+            processor.history(1).expect("storage should be readable"),
+            vec![
+                deposit(1, dec!(2.5)),
+                deposit(2, dec!(1.0)),
+                Transaction::new(1, TransactionKind::Dispute(None)),
+            ]
+        );
+    }
+
+    #[test]
+    fn resume_from_disk() {
+        let db = temporary_db();
+
+        {
+            let mut processor = reopen(&db);
+
+            assert!(processor.process(1, deposit(1, dec!(2.5))).is_ok());
+            assert!(processor.flush_storage().is_ok());
+        }
+
+        let mut processor = reopen(&db);
+
+        // The deposit made before reopening can still be disputed.
+        assert!(processor
+            .process(1, Transaction::new(1, TransactionKind::Dispute(None)))
+            .is_ok());
+
+        let account = processor
+            .account_log(1)
+            .expect("storage should be readable")
+            .expect("the account should have been kept");
+
+        assert_eq!(account.state().held, amount(dec!(2.5)));
+    }
+
+    #[test]
+    fn commit_in_batches() {
+        let db = temporary_db();
+
+        {
+            let storage = SledStorage::from_db(db.clone())
+                .expect("trees should open")
+                .with_batch_size(2);
+            let mut processor = PaymentProcessor::with_storage(storage);
+
+            for id in 1..=3 {
+                assert!(processor.save_processed_rows(id.into()).is_ok());
+                assert!(processor.process(1, deposit(id, dec!(1))).is_ok());
+            }
+
+            // Buffered writes are visible before being committed.
+            let account = processor
+                .account_log(1)
+                .expect("storage should be readable")
+                .expect("an account should have been created");
+            assert_eq!(account.state().available, amount(dec!(3)));
+        }
+
+        let processor = reopen(&db);
+
+        // Only the first batch was committed, along with everything it wrote.
+        let account = processor
+            .account_log(1)
+            .expect("storage should be readable")
+            .expect("the account should have been kept");
+
+        assert_eq!(account.state().available, amount(dec!(2)));
+        assert_eq!(
+            processor.history(1).expect("storage should be readable"),
+            vec![deposit(1, dec!(1)), deposit(2, dec!(1))]
+        );
+        assert!(processor
+            .storage_stats()
+            .is_ok_and(|stats| stats.deposits == 2));
+        assert!(processor.processed_rows().is_ok_and(|rows| rows == 2));
+    }
+
+    /// Writes a record the way an earlier format encoded it, bypassing the storage.
+    fn insert_raw(db: &sled::Db, tree: &str, key: &[u8], record: &impl serde::Serialize) {
+        db.open_tree(tree)
+            .expect("the tree should open")
+            .insert(key, bincode::serialize(record).expect("encodable"))
+            .expect("the record should be written");
+    }
+
+    fn balance(available: rust_decimal::Decimal) -> Account {
+        let mut account = Account::new(1);
+        account.available = amount(available);
+        account
+    }
+
+    #[test]
+    fn upgrade_previous_format() {
+        let db = temporary_db();
+
+        // An account as it was encoded once deposits were counted for compaction.
+        let log = (
+            balance(dec!(2.5)),
+            Some(Timestamp::from(10)),
+            2u64,
+            2u64,
+            64u64,
+        );
+        insert_raw(&db, "accounts", &1u16.to_be_bytes(), &log);
+
+        let processor = reopen(&db);
+        let account = processor
+            .account_log(1)
+            .expect("storage should be readable")
+            .expect("the account should have been kept");
+
+        assert_eq!(account.state().available, amount(dec!(2.5)));
+        assert_eq!(account.rejected(), 0);
+        assert_eq!(account.rejected_amount(), amount(dec!(0)));
+
+        // The upgraded records were committed along with the new version.
+        let version = db
+            .open_tree("metadata")
+            .expect("the tree should open")
+            .get(super::FORMAT_VERSION_KEY)
+            .expect("the version should be readable")
+            .expect("the version should have been recorded");
+        assert_eq!(
+            bincode::deserialize::<u64>(&version).expect("decodable"),
+            super::FORMAT_VERSION
+        );
+    }
+
+    #[test]
+    fn refuse_initial_format() {
+        let db = temporary_db();
+
+        // Deposits did not record their position yet, which retention relies on.
+        let log = (balance(dec!(2.5)), Some(Timestamp::from(10)));
+        insert_raw(&db, "accounts", &1u16.to_be_bytes(), &log);
+        let deposit = (amount(dec!(2.5)), None::<MonetaryValue>, None::<Timestamp>);
+        insert_raw(
+            &db,
+            "deposits",
+            &super::transaction_key(1.into(), 1.into()),
+            &deposit,
+        );
+
+        let error = SledStorage::from_db(db)
+            .err()
+            .expect("the format should be refused");
+        assert!(format!("{error:#}").contains("unsupported format version"));
+    }
+
+    #[test]
+    fn upgrade_full_size_amounts() {
+        let db = temporary_db();
+
+        // Records as they were encoded before amounts were packed.
+        let mut account = balance(dec!(2.5));
+        account.authorized = amount(dec!(1));
+        insert_raw(
+            &db,
+            "accounts",
+            &1u16.to_be_bytes(),
+            &(account, None::<Timestamp>, 1u64, 1u64, 64u64),
+        );
+        let deposit = (
+            amount(dec!(3.5)),
+            None::<MonetaryValue>,
+            None::<Timestamp>,
+            0u64,
+        );
+        insert_raw(
+            &db,
+            "deposits",
+            &super::transaction_key(1.into(), 1.into()),
+            &deposit,
+        );
+        let authorization = (amount(dec!(1)), Some(Timestamp::from(10)));
+        insert_raw(
+            &db,
+            "authorizations",
+            &super::transaction_key(1.into(), 2.into()),
+            &authorization,
+        );
+
+        let mut processor = reopen(&db);
+
+        assert!(processor
+            .process(
+                1,
+                Transaction::new(1, TransactionKind::Dispute(Some(amount(dec!(2)))))
+            )
+            .is_ok());
+        assert!(processor
+            .process(1, Transaction::new(2, TransactionKind::Capture(None)))
+            .is_ok());
+
+        let account = processor
+            .account_log(1)
+            .expect("storage should be readable")
+            .expect("the account should have been kept");
+
+        assert_eq!(account.state().available, amount(dec!(0.5)));
+        assert_eq!(account.state().held, amount(dec!(2)));
+        assert_eq!(account.state().authorized, amount(dec!(0)));
+    }
+
+    #[test]
+    fn upgrade_rejection_counters() {
+        let db = temporary_db();
+
+        // An account as it was encoded once rejected transactions were counted.
+        let log = (
+            balance(dec!(2.5)),
+            None::<Timestamp>,
+            2u64,
+            2u64,
+            64u64,
+            3u64,
+            amount(dec!(7)),
+        );
+        insert_raw(&db, "accounts", &1u16.to_be_bytes(), &log);
+
+        let account = reopen(&db)
+            .account_log(1)
+            .expect("storage should be readable")
+            .expect("the account should have been kept");
+
+        assert_eq!(account.state().available, amount(dec!(2.5)));
+        assert_eq!(account.rejected(), 3);
+        assert_eq!(account.rejected_amount(), amount(dec!(7)));
+    }
+
+    #[test]
+    fn record_version_of_current_layout() {
+        let db = temporary_db();
+
+        // Accounts already held the state of the rules before the version was recorded.
+        let mut log = AccountLog::new(1);
+        log.state = balance(dec!(2.5));
+        log.disputes = 4;
+        log.recent_debits = 0b101;
+        insert_raw(&db, "accounts", &1u16.to_be_bytes(), &log);
+
+        let account = reopen(&db)
+            .account_log(1)
+            .expect("storage should be readable")
+            .expect("the account should have been kept");
+
+        assert_eq!(account, log);
+    }
+
+    #[test]
+    fn refuse_newer_format() {
+        let db = temporary_db();
+        let newer = bincode::serialize(&(super::FORMAT_VERSION + 1)).expect("encodable");
+        db.open_tree("metadata")
+            .expect("the tree should open")
+            .insert(super::FORMAT_VERSION_KEY, newer)
+            .expect("the version should be written");
+
+        assert!(SledStorage::from_db(db).is_err());
+    }
+}
@@ -0,0 +1,1130 @@
+use std::{path::Path, str::FromStr};
+
+use anyhow::{anyhow, Context};
+use rusqlite::{params, Connection, Row};
+use rust_decimal::Decimal;
+
+use super::{Storage, StorageResult, StorageStats};
+use crate::{
+    account::{Account, ClientId, MonetaryValue, Transaction, TransactionId, TransactionKind},
+    packed::CompactMoney,
+    processor::{AccountLog, AuthorizationRecord, DepositRecord, RejectedTransaction},
+};
+
+/// Number of transactions written per database transaction by default.
+const DEFAULT_BATCH_SIZE: usize = 1000;
+
+/// Tables are created on the first use of a database. Amounts are stored as decimal
+/// strings so that they are never rounded, and can be cast when querying.
+const SCHEMA: &str = "
+    CREATE TABLE IF NOT EXISTS accounts (
+        client INTEGER PRIMARY KEY,
+        available TEXT NOT NULL,
+        held TEXT NOT NULL,
+        authorized TEXT NOT NULL,
+        locked INTEGER NOT NULL,
+        last_timestamp INTEGER,
+        deposits INTEGER NOT NULL,
+        retained_deposits INTEGER NOT NULL,
+        next_compaction INTEGER NOT NULL,
+        rejected INTEGER NOT NULL,
+        rejected_amount TEXT NOT NULL,
+        recent_debits INTEGER NOT NULL,
+        disputes INTEGER NOT NULL,
+        volume_day INTEGER NOT NULL,
+        daily_volume TEXT NOT NULL
+    );
+    CREATE TABLE IF NOT EXISTS transactions (
+        seq INTEGER PRIMARY KEY AUTOINCREMENT,
+        client INTEGER NOT NULL,
+        tx INTEGER NOT NULL,
+        type TEXT NOT NULL,
+        amount TEXT,
+        counterparty INTEGER,
+        timestamp INTEGER
+    );
+    CREATE INDEX IF NOT EXISTS transactions_by_client ON transactions (client, seq);
+    CREATE TABLE IF NOT EXISTS rejections (
+        seq INTEGER PRIMARY KEY AUTOINCREMENT,
+        client INTEGER NOT NULL,
+        tx INTEGER NOT NULL,
+        type TEXT NOT NULL,
+        amount TEXT,
+        counterparty INTEGER,
+        timestamp INTEGER,
+        code TEXT NOT NULL
+    );
+    CREATE INDEX IF NOT EXISTS rejections_by_client ON rejections (client, seq);
+    CREATE TABLE IF NOT EXISTS deposits (
+        client INTEGER NOT NULL,
+        tx INTEGER NOT NULL,
+        disputable TEXT NOT NULL,
+        disputed TEXT,
+        timestamp INTEGER,
+        sequence INTEGER NOT NULL,
+        PRIMARY KEY (client, tx)
+    ) WITHOUT ROWID;
+    CREATE TABLE IF NOT EXISTS authorizations (
+        client INTEGER NOT NULL,
+        tx INTEGER NOT NULL,
+        amount TEXT NOT NULL,
+        timestamp INTEGER,
+        PRIMARY KEY (client, tx)
+    ) WITHOUT ROWID;
+    CREATE TABLE IF NOT EXISTS metadata (
+        key TEXT PRIMARY KEY,
+        value INTEGER NOT NULL
+    ) WITHOUT ROWID;
+";
+
+/// Upgrades the tables of each earlier schema version to the next one, the version
+/// of a database being kept in `PRAGMA user_version`. Tables added since are created by
+/// `SCHEMA`.
+const MIGRATIONS: &[fn(&rusqlite::Transaction) -> StorageResult<()>] = &[upgrade_unversioned];
+
+/// Columns added to `accounts` by version 1, in the order they were introduced.
+/// Databases created before the version was recorded have version 0, but may already
+/// have some of them.
+const UNVERSIONED_COLUMNS: &[(&str, &str)] = &[
+    ("rejected", "INTEGER NOT NULL DEFAULT 0"),
+    ("rejected_amount", "TEXT NOT NULL DEFAULT '0'"),
+    ("recent_debits", "INTEGER NOT NULL DEFAULT 0"),
+    ("disputes", "INTEGER NOT NULL DEFAULT 0"),
+    ("volume_day", "INTEGER NOT NULL DEFAULT 0"),
+    ("daily_volume", "TEXT NOT NULL DEFAULT '0'"),
+];
+
+/// Keeps everything in a SQLite database whose tables can be queried directly:
+///
+/// - `accounts` holds the current state of every account,
+/// - `transactions` holds the accepted transactions, in the order they were applied,
+/// - `rejections` holds the rejected transactions and the code of their error,
+/// - `deposits` holds the dispute state of deposits,
+/// - `authorizations` holds the pending card authorizations,
+/// - `metadata` holds the number of input rows processed so far, as `processed_rows`.
+///
+/// Writes are grouped in database transactions of `batch_size` payment transactions,
+/// which are only committed between two payment transactions. Changes that are not
+/// committed yet are lost if the process crashes or if the storage is dropped without
+/// being flushed, but the database stays consistent and processing can be resumed
+/// from it.
+pub struct SqliteStorage {
+    connection: Connection,
+    batch_size: usize,
+    /// Payment transactions written since the database transaction began.
+    pending: usize,
+}
+
+impl SqliteStorage {
+    /// Opens the database at the given path, creating it if it does not exist.
+    pub fn open(path: impl AsRef<Path>) -> StorageResult<Self> {
+        let connection = Connection::open(path).context("unable to open database")?;
+        Self::from_connection(connection)
+    }
+
+    /// Opens a database that only lives in memory, mostly useful for tests.
+    pub fn in_memory() -> StorageResult<Self> {
+        let connection = Connection::open_in_memory().context("unable to open database")?;
+        Self::from_connection(connection)
+    }
+
+    fn from_connection(mut connection: Connection) -> StorageResult<Self> {
+        migrate(&mut connection)?;
+
+        Ok(Self {
+            connection,
+            batch_size: DEFAULT_BATCH_SIZE,
+            pending: 0,
+        })
+    }
+
+    /// Commits the written records every `batch_size` payment transactions.
+    pub fn with_batch_size(mut self, batch_size: usize) -> Self {
+        self.batch_size = batch_size.max(1);
+        self
+    }
+
+    /// Begins a database transaction unless one is already open.
+    fn begin(&mut self) -> StorageResult<()> {
+        if self.connection.is_autocommit() {
+            self.connection
+                .execute_batch("BEGIN")
+                .context("unable to begin transaction")?;
+        }
+        Ok(())
+    }
+
+    fn commit(&mut self) -> StorageResult<()> {
+        if !self.connection.is_autocommit() {
+            self.connection
+                .execute_batch("COMMIT")
+                .context("unable to commit transaction")?;
+        }
+        self.pending = 0;
+        Ok(())
+    }
+}
+
+impl Drop for SqliteStorage {
+    /// Only `checkpoint` and `flush` commit, since the storage may be dropped in the
+    /// middle of a payment transaction, e.g. while unwinding from a panic.
+    fn drop(&mut self) {
+        if !self.connection.is_autocommit() {
+            let _ = self.connection.execute_batch("ROLLBACK");
+        }
+    }
+}
+
+// ----------------------------------------------------------------------------
+
+/// Creates the tables of a new database, or brings those of an existing one to the
+/// current schema version. Databases of a newer version are refused.
+fn migrate(connection: &mut Connection) -> StorageResult<()> {
+    let transaction = connection
+        .transaction()
+        .context("unable to begin transaction")?;
+
+    let version: usize = transaction
+        .pragma_query_value(None, "user_version", |row| row.get(0))
+        .context("unable to read schema version")?;
+    let created = transaction
+        .query_row(
+            "SELECT count(*) FROM sqlite_master WHERE type = 'table' AND name = 'accounts'",
+            [],
+            |row| row.get::<_, u32>(0),
+        )
+        .context("unable to read schema")?
+        > 0;
+
+    if version > MIGRATIONS.len() {
+        return Err(anyhow!(
+            "database schema version {version} is newer than the supported version {}",
+            MIGRATIONS.len()
+        )
+        .into());
+    }
+    if created {
+        for migration in &MIGRATIONS[version..] {
+            migration(&transaction)?;
+        }
+    }
+
+    transaction
+        .execute_batch(SCHEMA)
+        .context("unable to create schema")?;
+    transaction
+        .pragma_update(None, "user_version", MIGRATIONS.len())
+        .context("unable to write schema version")?;
+    transaction
+        .commit()
+        .context("unable to commit transaction")?;
+    Ok(())
+}
+
+/// Adds the columns of version 1 that a database created before the version was
+/// recorded lacks. Databases whose deposits do not record their position, which
+/// retention needs, are refused.
+fn upgrade_unversioned(transaction: &rusqlite::Transaction) -> StorageResult<()> {
+    let columns = transaction
+        .prepare("SELECT name FROM pragma_table_info('accounts')")
+        .and_then(|mut statement| {
+            statement
+                .query_map([], |row| row.get::<_, String>(0))?
+                .collect::<rusqlite::Result<Vec<_>>>()
+        })
+        .context("unable to read schema")?;
+
+    if !columns.iter().any(|column| column == "deposits") {
+        return Err(anyhow!(
+            "unsupported schema version: the database was created before deposits were \
+             numbered, and cannot be upgraded"
+        )
+        .into());
+    }
+
+    for (column, definition) in UNVERSIONED_COLUMNS {
+        if !columns.iter().any(|existing| existing == column) {
+            transaction
+                .execute_batch(&format!(
+                    "ALTER TABLE accounts ADD COLUMN {column} {definition}"
+                ))
+                .context("unable to upgrade schema")?;
+        }
+    }
+    Ok(())
+}
+
+fn amount_to_sql(amount: MonetaryValue) -> String {
+    Decimal::from(amount).to_string()
+}
+
+fn amount_from_sql(text: &str) -> anyhow::Result<MonetaryValue> {
+    let decimal = Decimal::from_str(text).context("amount is not a decimal")?;
+    Ok(decimal.try_into()?)
+}
+
+fn optional_amount_from_sql(text: Option<String>) -> anyhow::Result<Option<MonetaryValue>> {
+    text.as_deref().map(amount_from_sql).transpose()
+}
+
+/// Splits a transaction kind into its `type`, `amount` and `counterparty` columns.
+fn kind_to_sql(kind: TransactionKind) -> (&'static str, Option<String>, Option<u16>) {
+    use TransactionKind::*;
+
+    let amount = |amount: Option<MonetaryValue>| amount.map(amount_to_sql);
+
+    match kind {
+        Deposit(amount) => ("deposit", Some(amount_to_sql(amount)), None),
+        Withdrawal(amount) => ("withdrawal", Some(amount_to_sql(amount)), None),
+        Dispute(disputed) => ("dispute", amount(disputed), None),
+        Resolve => ("resolve", None, None),
+        Chargeback => ("chargeback", None, None),
+        Transfer { recipient, amount } => (
+            "transfer",
+            Some(amount_to_sql(amount)),
+            Some(recipient.into()),
+        ),
+        Refund(refunded) => ("refund", amount(refunded), None),
+        Authorize(amount) => ("authorize", Some(amount_to_sql(amount)), None),
+        Capture(captured) => ("capture", amount(captured), None),
+        Void => ("void", None, None),
+    }
+}
+
+fn kind_from_sql(
+    r#type: &str,
+    amount: Option<MonetaryValue>,
+    counterparty: Option<u16>,
+) -> anyhow::Result<TransactionKind> {
+    use TransactionKind::*;
+
+    let required = || amount.with_context(|| format!("{type} should contain an amount"));
+
+    Ok(match r#type {
+        "deposit" => Deposit(required()?),
+        "withdrawal" => Withdrawal(required()?),
+        "dispute" => Dispute(amount),
+        "resolve" => Resolve,
+        "chargeback" => Chargeback,
+        "transfer" => Transfer {
+            recipient: counterparty
+                .context("transfer should contain a counterparty")?
+                .into(),
+            amount: required()?,
+        },
+        "refund" => Refund(amount),
+        "authorize" => Authorize(required()?),
+        "capture" => Capture(amount),
+        "void" => Void,
+        other => return Err(anyhow!("unknown transaction type {other}")),
+    })
+}
+
+fn account_from_row(row: &Row) -> rusqlite::Result<anyhow::Result<AccountLog>> {
+    let client: u16 = row.get("client")?;
+    let available: String = row.get("available")?;
+    let held: String = row.get("held")?;
+    let authorized: String = row.get("authorized")?;
+    let locked: bool = row.get("locked")?;
+    let last_timestamp: Option<u64> = row.get("last_timestamp")?;
+    let deposits: u64 = row.get("deposits")?;
+    let retained_deposits: u64 = row.get("retained_deposits")?;
+    let next_compaction: u64 = row.get("next_compaction")?;
+    let rejected: u64 = row.get("rejected")?;
+    let rejected_amount: String = row.get("rejected_amount")?;
+    let recent_debits: u32 = row.get("recent_debits")?;
+    let disputes: u64 = row.get("disputes")?;
+    let volume_day: u64 = row.get("volume_day")?;
+    let daily_volume: String = row.get("daily_volume")?;
+
+    Ok((|| {
+        Ok(AccountLog {
+            state: Account {
+                client_id: client.into(),
+                available: amount_from_sql(&available)?,
+                held: amount_from_sql(&held)?,
+                authorized: amount_from_sql(&authorized)?,
+                locked,
+            },
+            last_timestamp: last_timestamp.map(Into::into),
+            deposits,
+            retained_deposits,
+            next_compaction,
+            rejected,
+            rejected_amount: amount_from_sql(&rejected_amount)?,
+            recent_debits,
+            disputes,
+            volume_day,
+            daily_volume: amount_from_sql(&daily_volume)?,
+        })
+    })())
+}
+
+fn deposit_from_row(row: &Row) -> rusqlite::Result<anyhow::Result<DepositRecord>> {
+    let disputable: String = row.get("disputable")?;
+    let disputed: Option<String> = row.get("disputed")?;
+    let timestamp: Option<u64> = row.get("timestamp")?;
+    let sequence: u64 = row.get("sequence")?;
+
+    Ok((|| {
+        Ok(DepositRecord {
+            disputable: amount_from_sql(&disputable)?.try_into()?,
+            disputed: optional_amount_from_sql(disputed)?
+                .map(CompactMoney::try_from)
+                .transpose()?,
+            timestamp: timestamp.map(Into::into),
+            sequence,
+        })
+    })())
+}
+
+fn authorization_from_row(row: &Row) -> rusqlite::Result<anyhow::Result<AuthorizationRecord>> {
+    let amount: String = row.get("amount")?;
+    let timestamp: Option<u64> = row.get("timestamp")?;
+
+    Ok((|| {
+        Ok(AuthorizationRecord {
+            amount: amount_from_sql(&amount)?.try_into()?,
+            timestamp: timestamp.map(Into::into),
+        })
+    })())
+}
+
+fn transaction_from_row(row: &Row) -> rusqlite::Result<anyhow::Result<Transaction>> {
+    let tx: u32 = row.get("tx")?;
+    let r#type: String = row.get("type")?;
+    let amount: Option<String> = row.get("amount")?;
+    let counterparty: Option<u16> = row.get("counterparty")?;
+    let timestamp: Option<u64> = row.get("timestamp")?;
+
+    Ok((|| {
+        let kind = kind_from_sql(&r#type, optional_amount_from_sql(amount)?, counterparty)?;
+        let transaction = Transaction::new(tx, kind);
+
+        Ok(match timestamp {
+            Some(timestamp) => transaction.with_timestamp(timestamp),
+            None => transaction,
+        })
+    })())
+}
+
+fn rejection_from_row(row: &Row) -> rusqlite::Result<anyhow::Result<RejectedTransaction>> {
+    let code: String = row.get("code")?;
+
+    Ok(transaction_from_row(row)?.and_then(|transaction| {
+        Ok(RejectedTransaction {
+            transaction,
+            code: code.parse()?,
+        })
+    }))
+}
+
+/// Flattens the outcome of a row conversion, which can fail both while reading the
+/// columns and while interpreting them.
+fn flatten<T>(outcome: rusqlite::Result<anyhow::Result<T>>) -> StorageResult<T> {
+    Ok(outcome.context("unable to read record")??)
+}
+
+/// Same as `flatten`, for a query that may not return any row.
+fn flatten_optional<T>(outcome: rusqlite::Result<anyhow::Result<T>>) -> StorageResult<Option<T>> {
+    match outcome {
+        Err(rusqlite::Error::QueryReturnedNoRows) => Ok(None),
+        outcome => flatten(outcome).map(Some),
+    }
+}
+
+// ----------------------------------------------------------------------------
+
+impl Storage for SqliteStorage {
+    fn account(&self, client_id: ClientId) -> StorageResult<Option<AccountLog>> {
+        let account = self
+            .connection
+            .prepare_cached("SELECT * FROM accounts WHERE client = ?1")
+            .context("unable to prepare query")?
+            .query_row(params![u16::from(client_id)], account_from_row);
+
+        flatten_optional(account)
+    }
+
+    fn save_account(&mut self, log: &AccountLog) -> StorageResult<()> {
+        self.begin()?;
+
+        let account = log.state();
+        self.connection
+            .prepare_cached(
+                "INSERT OR REPLACE INTO accounts
+                 VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13, ?14, ?15)",
+            )
+            .context("unable to prepare query")?
+            .execute(params![
+                u16::from(account.client_id),
+                amount_to_sql(account.available),
+                amount_to_sql(account.held),
+                amount_to_sql(account.authorized),
+                account.locked,
+                log.last_timestamp.map(u64::from),
+                log.deposits,
+                log.retained_deposits,
+                log.next_compaction,
+                log.rejected,
+                amount_to_sql(log.rejected_amount),
+                log.recent_debits,
+                log.disputes,
+                log.volume_day,
+                amount_to_sql(log.daily_volume),
+            ])
+            .context("unable to write record")?;
+        Ok(())
+    }
+
+    fn accounts(&self) -> Box<dyn Iterator<Item = StorageResult<AccountLog>> + '_> {
+        // A statement cannot outlive the borrow of the connection it was prepared on,
+        // so accounts are read one at a time by increasing client, each read being a
+        // lookup in the primary key. Reading stops after the first error.
+        let mut next_client = Some(0u32);
+
+        Box::new(std::iter::from_fn(move || {
+            let client = next_client.take()?;
+            let account = self
+                .connection
+                .prepare_cached("SELECT * FROM accounts WHERE client >= ?1 ORDER BY client LIMIT 1")
+                .context("unable to prepare query")
+                .map_err(Into::into)
+                .and_then(|mut statement| {
+                    flatten_optional(statement.query_row(params![client], account_from_row))
+                })
+                .transpose()?;
+
+            if let Ok(log) = &account {
+                next_client = Some(u32::from(u16::from(log.state().client_id)) + 1);
+            }
+            Some(account)
+        }))
+    }
+
+    fn deposit(
+        &self,
+        client_id: ClientId,
+        tx: TransactionId,
+    ) -> StorageResult<Option<DepositRecord>> {
+        let deposit = self
+            .connection
+            .prepare_cached("SELECT * FROM deposits WHERE client = ?1 AND tx = ?2")
+            .context("unable to prepare query")?
+            .query_row(
+                params![u16::from(client_id), u32::from(tx)],
+                deposit_from_row,
+            );
+
+        flatten_optional(deposit)
+    }
+
+    fn save_deposit(
+        &mut self,
+        client_id: ClientId,
+        tx: TransactionId,
+        record: &DepositRecord,
+    ) -> StorageResult<()> {
+        self.begin()?;
+
+        self.connection
+            .prepare_cached("INSERT OR REPLACE INTO deposits VALUES (?1, ?2, ?3, ?4, ?5, ?6)")
+            .context("unable to prepare query")?
+            .execute(params![
+                u16::from(client_id),
+                u32::from(tx),
+                amount_to_sql(record.disputable.into()),
+                record
+                    .disputed
+                    .map(|disputed| amount_to_sql(disputed.into())),
+                record.timestamp.map(u64::from),
+                record.sequence,
+            ])
+            .context("unable to write record")?;
+        Ok(())
+    }
+
+    fn deposits(&self, client_id: ClientId) -> StorageResult<Vec<(TransactionId, DepositRecord)>> {
+        let mut statement = self
+            .connection
+            .prepare_cached("SELECT * FROM deposits WHERE client = ?1")
+            .context("unable to prepare query")?;
+
+        let rows = statement
+            .query_map(params![u16::from(client_id)], |row| {
+                let tx: u32 = row.get("tx")?;
+                Ok(deposit_from_row(row)?.map(|record| (tx.into(), record)))
+            })
+            .context("unable to read records")?;
+
+        rows.map(flatten).collect()
+    }
+
+    fn remove_deposit(&mut self, client_id: ClientId, tx: TransactionId) -> StorageResult<()> {
+        self.begin()?;
+
+        self.connection
+            .prepare_cached("DELETE FROM deposits WHERE client = ?1 AND tx = ?2")
+            .context("unable to prepare query")?
+            .execute(params![u16::from(client_id), u32::from(tx)])
+            .context("unable to remove record")?;
+        Ok(())
+    }
+
+    fn authorization(
+        &self,
+        client_id: ClientId,
+        tx: TransactionId,
+    ) -> StorageResult<Option<AuthorizationRecord>> {
+        let authorization = self
+            .connection
+            .prepare_cached("SELECT * FROM authorizations WHERE client = ?1 AND tx = ?2")
+            .context("unable to prepare query")?
+            .query_row(
+                params![u16::from(client_id), u32::from(tx)],
+                authorization_from_row,
+            );
+
+        flatten_optional(authorization)
+    }
+
+    fn authorizations(
+        &self,
+        client_id: ClientId,
+    ) -> StorageResult<Vec<(TransactionId, AuthorizationRecord)>> {
+        let mut statement = self
+            .connection
+            .prepare_cached("SELECT * FROM authorizations WHERE client = ?1")
+            .context("unable to prepare query")?;
+
+        let rows = statement
+            .query_map(params![u16::from(client_id)], |row| {
+                let tx: u32 = row.get("tx")?;
+                Ok(authorization_from_row(row)?.map(|record| (tx.into(), record)))
+            })
+            .context("unable to read records")?;
+
+        rows.map(flatten).collect()
+    }
+
+    fn save_authorization(
+        &mut self,
+        client_id: ClientId,
+        tx: TransactionId,
+        record: &AuthorizationRecord,
+    ) -> StorageResult<()> {
+        self.begin()?;
+
+        self.connection
+            .prepare_cached("INSERT OR REPLACE INTO authorizations VALUES (?1, ?2, ?3, ?4)")
+            .context("unable to prepare query")?
+            .execute(params![
+                u16::from(client_id),
+                u32::from(tx),
+                amount_to_sql(record.amount.into()),
+                record.timestamp.map(u64::from),
+            ])
+            .context("unable to write record")?;
+        Ok(())
+    }
+
+    fn remove_authorization(
+        &mut self,
+        client_id: ClientId,
+        tx: TransactionId,
+    ) -> StorageResult<()> {
+        self.begin()?;
+
+        self.connection
+            .prepare_cached("DELETE FROM authorizations WHERE client = ?1 AND tx = ?2")
+            .context("unable to prepare query")?
+            .execute(params![u16::from(client_id), u32::from(tx)])
+            .context("unable to remove record")?;
+        Ok(())
+    }
+
+    fn append_history(
+        &mut self,
+        client_id: ClientId,
+        transaction: &Transaction,
+    ) -> StorageResult<()> {
+        self.begin()?;
+
+        let (r#type, amount, counterparty) = kind_to_sql(transaction.kind);
+        self.connection
+            .prepare_cached(
+                "INSERT INTO transactions (client, tx, type, amount, counterparty, timestamp)
+                 VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+            )
+            .context("unable to prepare query")?
+            .execute(params![
+                u16::from(client_id),
+                u32::from(transaction.id),
+                r#type,
+                amount,
+                counterparty,
+                transaction.timestamp.map(u64::from),
+            ])
+            .context("unable to write record")?;
+        Ok(())
+    }
+
+    fn history(&self, client_id: ClientId) -> StorageResult<Vec<Transaction>> {
+        let mut statement = self
+            .connection
+            .prepare_cached("SELECT * FROM transactions WHERE client = ?1 ORDER BY seq")
+            .context("unable to prepare query")?;
+
+        let rows = statement
+            .query_map(params![u16::from(client_id)], transaction_from_row)
+            .context("unable to read records")?;
+
+        rows.map(flatten).collect()
+    }
+
+    fn append_rejection(
+        &mut self,
+        client_id: ClientId,
+        rejection: &RejectedTransaction,
+    ) -> StorageResult<()> {
+        self.begin()?;
+
+        let transaction = rejection.transaction;
+        let (r#type, amount, counterparty) = kind_to_sql(transaction.kind);
+        self.connection
+            .prepare_cached(
+                "INSERT INTO rejections (client, tx, type, amount, counterparty, timestamp, code)
+                 VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+            )
+            .context("unable to prepare query")?
+            .execute(params![
+                u16::from(client_id),
+                u32::from(transaction.id),
+                r#type,
+                amount,
+                counterparty,
+                transaction.timestamp.map(u64::from),
+                rejection.code.as_str(),
+            ])
+            .context("unable to write record")?;
+        Ok(())
+    }
+
+    fn rejections(&self, client_id: ClientId) -> StorageResult<Vec<RejectedTransaction>> {
+        let mut statement = self
+            .connection
+            .prepare_cached("SELECT * FROM rejections WHERE client = ?1 ORDER BY seq")
+            .context("unable to prepare query")?;
+
+        let rows = statement
+            .query_map(params![u16::from(client_id)], rejection_from_row)
+            .context("unable to read records")?;
+
+        rows.map(flatten).collect()
+    }
+
+    fn stats(&self) -> StorageResult<StorageStats> {
+        let count = |table: &str| -> StorageResult<u64> {
+            Ok(self
+                .connection
+                .query_row(&format!("SELECT COUNT(*) FROM {table}"), [], |row| {
+                    row.get(0)
+                })
+                .context("unable to count records")?)
+        };
+
+        Ok(StorageStats {
+            accounts: count("accounts")?,
+            deposits: count("deposits")?,
+            authorizations: count("authorizations")?,
+            transactions: count("transactions")?,
+            rejections: count("rejections")?,
+        })
+    }
+
+    fn processed_rows(&self) -> StorageResult<u64> {
+        let rows = self
+            .connection
+            .prepare_cached("SELECT value FROM metadata WHERE key = 'processed_rows'")
+            .context("unable to prepare query")?
+            .query_row([], |row| row.get(0));
+
+        match rows {
+            Err(rusqlite::Error::QueryReturnedNoRows) => Ok(0),
+            rows => Ok(rows.context("unable to read record")?),
+        }
+    }
+
+    fn save_processed_rows(&mut self, rows: u64) -> StorageResult<()> {
+        self.begin()?;
+
+        self.connection
+            .prepare_cached("INSERT OR REPLACE INTO metadata VALUES ('processed_rows', ?1)")
+            .context("unable to prepare query")?
+            .execute(params![rows])
+            .context("unable to write record")?;
+        Ok(())
+    }
+
+    fn checkpoint(&mut self) -> StorageResult<()> {
+        if self.connection.is_autocommit() {
+            return Ok(());
+        }
+
+        self.pending += 1;
+        if self.pending >= self.batch_size {
+            self.commit()?;
+        }
+        Ok(())
+    }
+
+    fn flush(&mut self) -> StorageResult<()> {
+        self.commit()
+    }
+}
+
+// ----------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use rust_decimal_macros::dec;
+
+    use super::SqliteStorage;
+    use crate::{
+        account::{MonetaryValue, Transaction, TransactionKind},
+        error::ErrorCode,
+        processor::{AccountLog, PaymentProcessor, RejectedTransaction},
+        storage::Storage,
+    };
+
+    fn amount(value: rust_decimal::Decimal) -> MonetaryValue {
+        value.try_into().expect("amount is valid")
+    }
+
+    #[test]
+    fn history_round_trip() {
+        let storage = SqliteStorage::in_memory().expect("the database should open");
+        let mut processor = PaymentProcessor::with_storage(storage);
+
+        let transactions = [
+            Transaction::new(1, TransactionKind::Deposit(amount(dec!(5)))).with_timestamp(10),
+            Transaction::new(2, TransactionKind::Dispute(Some(amount(dec!(1.5))))),
+            Transaction::new(2, TransactionKind::Resolve),
+            Transaction::new(
+                3,
+                TransactionKind::Transfer {
+                    recipient: 2.into(),
+                    amount: amount(dec!(0.25)),
+                },
+            ),
+            Transaction::new(4, TransactionKind::Authorize(amount(dec!(1)))),
+            Transaction::new(4, TransactionKind::Capture(None)),
+        ];
+
+        // The dispute references an unknown deposit and is rejected.
+        let accepted: Vec<bool> = transactions
+            .iter()
+            .map(|transaction| processor.process(1, *transaction).is_ok())
+            .collect();
+        assert_eq!(accepted, [true, false, false, true, true, true]);
+
+        assert_eq!(
+            processor.history(1).expect("storage should be readable"),
+            [
+                transactions[0],
+                transactions[3],
+                transactions[4],
+                transactions[5]
+            ]
+        );
+        assert_eq!(
+            processor.history(2).expect("storage should be readable"),
+            [transactions[3]]
+        );
+
+        let account = processor
+            .account_log(1)
+            .expect("storage should be readable")
+            .expect("an account should have been created");
+
+        assert_eq!(account.state().available, amount(dec!(3.75)));
+        assert_eq!(account.state().authorized, amount(dec!(0)));
+        assert_eq!(account.rejected(), 2);
+        assert_eq!(account.rejected_amount(), amount(dec!(1.5)));
+
+        assert_eq!(
+            processor.rejections(1).expect("storage should be readable"),
+            [
+                RejectedTransaction {
+                    transaction: transactions[1],
+                    code: ErrorCode::UnknownTransaction,
+                },
+                RejectedTransaction {
+                    transaction: transactions[2],
+                    code: ErrorCode::NoDispute,
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn resume_from_database() {
+        let path = std::env::temp_dir().join(format!("kithmonite-{}.sqlite", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+
+        {
+            let storage = SqliteStorage::open(&path)
+                .expect("the database should open")
+                .with_batch_size(2);
+            let mut processor = PaymentProcessor::with_storage(storage);
+
+            for id in 1..=3 {
+                let deposit = TransactionKind::Deposit(amount(dec!(1)));
+                assert!(processor.process(1, Transaction::new(id, deposit)).is_ok());
+            }
+            assert!(processor.flush_storage().is_ok());
+        }
+
+        let storage = SqliteStorage::open(&path).expect("the database should reopen");
+        let mut processor = PaymentProcessor::with_storage(storage);
+
+        // Deposits made before reopening can still be disputed.
+        assert!(processor
+            .process(1, Transaction::new(3, TransactionKind::Dispute(None)))
+            .is_ok());
+
+        let account = processor
+            .account_log(1)
+            .expect("storage should be readable")
+            .expect("the account should have been kept");
+
+        assert_eq!(account.state().available, amount(dec!(2)));
+        assert_eq!(account.state().held, amount(dec!(1)));
+        assert_eq!(
+            processor
+                .history(1)
+                .expect("storage should be readable")
+                .len(),
+            4
+        );
+
+        drop(processor);
+        let _ = std::fs::remove_file(path);
+    }
+
+    #[test]
+    fn rollback_on_panic() {
+        let path =
+            std::env::temp_dir().join(format!("kithmonite-rollback-{}.sqlite", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+
+        let outcome = std::panic::catch_unwind(|| {
+            let mut storage = SqliteStorage::open(&path).expect("the database should open");
+            let mut log = AccountLog::new(1);
+
+            assert!(storage.save_account(&log).is_ok());
+            assert!(storage.flush().is_ok());
+
+            // Interrupted half way through a payment transaction.
+            log.state.available = amount(dec!(5));
+            assert!(storage.save_account(&log).is_ok());
+            panic!("interrupted");
+        });
+        assert!(outcome.is_err());
+
+        let storage = SqliteStorage::open(&path).expect("the database should reopen");
+        let account = storage
+            .account(1.into())
+            .expect("storage should be readable")
+            .expect("the committed account should have been kept");
+
+        assert_eq!(account.state().available, amount(dec!(0)));
+
+        drop(storage);
+        let _ = std::fs::remove_file(path);
+    }
+
+    #[test]
+    fn processed_rows_commit_with_their_changes() {
+        let path = std::env::temp_dir().join(format!(
+            "kithmonite-processed-rows-{}.sqlite",
+            std::process::id()
+        ));
+        let _ = std::fs::remove_file(&path);
+
+        {
+            let storage = SqliteStorage::open(&path)
+                .expect("the database should open")
+                .with_batch_size(2);
+            let mut processor = PaymentProcessor::with_storage(storage);
+
+            for id in 1..=3 {
+                let deposit = TransactionKind::Deposit(amount(dec!(1)));
+                assert!(processor.save_processed_rows(id.into()).is_ok());
+                assert!(processor.process(1, Transaction::new(id, deposit)).is_ok());
+            }
+        }
+
+        let storage = SqliteStorage::open(&path).expect("the database should reopen");
+        let processor = PaymentProcessor::with_storage(storage);
+
+        // The third row was not committed, so it is processed again when resuming.
+        assert!(processor.processed_rows().is_ok_and(|rows| rows == 2));
+
+        let account = processor
+            .account_log(1)
+            .expect("storage should be readable")
+            .expect("the account should have been kept");
+
+        assert_eq!(account.state().available, amount(dec!(2)));
+
+        drop(processor);
+        let _ = std::fs::remove_file(path);
+    }
+
+    #[test]
+    fn stream_accounts() {
+        let mut storage = SqliteStorage::in_memory().expect("the database should open");
+
+        for client in [65535, 0, 7] {
+            assert!(storage.save_account(&AccountLog::new(client)).is_ok());
+        }
+
+        let clients: Vec<u16> = storage
+            .accounts()
+            .map(|log| {
+                log.expect("storage should be readable")
+                    .state()
+                    .client_id
+                    .into()
+            })
+            .collect();
+        assert_eq!(clients, [0, 7, 65535]);
+    }
+
+    /// Creates the accounts table as it was before the version of the schema was
+    /// recorded, with the columns that were added to the first schema.
+    fn unversioned_accounts(added_columns: &str, account: &str) -> rusqlite::Connection {
+        let connection = rusqlite::Connection::open_in_memory().expect("the database should open");
+        connection
+            .execute_batch(&format!(
+                "CREATE TABLE accounts (
+                    client INTEGER PRIMARY KEY,
+                    available TEXT NOT NULL,
+                    held TEXT NOT NULL,
+                    authorized TEXT NOT NULL,
+                    locked INTEGER NOT NULL,
+                    last_timestamp INTEGER
+                    {added_columns}
+                );
+                INSERT INTO accounts VALUES ({account});"
+            ))
+            .expect("the previous schema should be created");
+        connection
+    }
+
+    #[test]
+    fn upgrade_previous_schema() {
+        // Accounts as they were stored once deposits were counted for compaction.
+        let connection = unversioned_accounts(
+            ", deposits INTEGER NOT NULL,
+             retained_deposits INTEGER NOT NULL,
+             next_compaction INTEGER NOT NULL",
+            "1, '2.5', '1', '0', 0, 10, 2, 2, 64",
+        );
+
+        let mut storage =
+            SqliteStorage::from_connection(connection).expect("the database should be upgraded");
+        let account = storage
+            .account(1.into())
+            .expect("storage should be readable")
+            .expect("the account should have been kept");
+
+        assert_eq!(account.state().available, amount(dec!(2.5)));
+        assert_eq!(account.state().held, amount(dec!(1)));
+        assert_eq!(account.rejected(), 0);
+        assert_eq!(account.rejected_amount(), amount(dec!(0)));
+
+        assert!(storage.save_account(&account).is_ok());
+        assert!(storage.flush().is_ok());
+
+        let version: usize = storage
+            .connection
+            .pragma_query_value(None, "user_version", |row| row.get(0))
+            .expect("the version should be readable");
+        assert_eq!(version, super::MIGRATIONS.len());
+    }
+
+    #[test]
+    fn refuse_initial_schema() {
+        // Deposits did not record their position yet, which retention relies on.
+        let connection = unversioned_accounts("", "1, '2.5', '1', '0', 0, 10");
+
+        let error = SqliteStorage::from_connection(connection)
+            .err()
+            .expect("the schema should be refused");
+        assert!(format!("{error:#}").contains("unsupported schema version"));
+    }
+
+    #[test]
+    fn upgrade_rejection_columns() {
+        // Accounts as they were stored once rejected transactions were counted.
+        let connection = unversioned_accounts(
+            ", deposits INTEGER NOT NULL,
+             retained_deposits INTEGER NOT NULL,
+             next_compaction INTEGER NOT NULL,
+             rejected INTEGER NOT NULL,
+             rejected_amount TEXT NOT NULL",
+            "1, '2.5', '1', '0', 0, 10, 2, 2, 64, 3, '7'",
+        );
+
+        let storage =
+            SqliteStorage::from_connection(connection).expect("the database should be upgraded");
+        let account = storage
+            .account(1.into())
+            .expect("storage should be readable")
+            .expect("the account should have been kept");
+
+        assert_eq!(account.state().available, amount(dec!(2.5)));
+        assert_eq!(account.rejected(), 3);
+        assert_eq!(account.rejected_amount(), amount(dec!(7)));
+    }
+
+    #[test]
+    fn record_version_of_current_schema() {
+        // Accounts already held the state of the rules before the version was recorded.
+        let connection = unversioned_accounts(
+            ", deposits INTEGER NOT NULL,
+             retained_deposits INTEGER NOT NULL,
+             next_compaction INTEGER NOT NULL,
+             rejected INTEGER NOT NULL,
+             rejected_amount TEXT NOT NULL,
+             recent_debits INTEGER NOT NULL,
+             disputes INTEGER NOT NULL,
+             volume_day INTEGER NOT NULL,
+             daily_volume TEXT NOT NULL",
+            "1, '2.5', '1', '0', 0, 10, 2, 2, 64, 3, '7', 5, 4, 19000, '1.5'",
+        );
+
+        let storage =
+            SqliteStorage::from_connection(connection).expect("the database should be upgraded");
+        let account = storage
+            .account(1.into())
+            .expect("storage should be readable")
+            .expect("the account should have been kept");
+
+        assert_eq!(account.rejected(), 3);
+        assert_eq!(account.disputes, 4);
+        assert_eq!(account.daily_volume(19000), amount(dec!(1.5)));
+    }
+
+    #[test]
+    fn refuse_newer_schema() {
+        let connection = rusqlite::Connection::open_in_memory().expect("the database should open");
+        connection
+            .pragma_update(None, "user_version", super::MIGRATIONS.len() + 1)
+            .expect("the version should be written");
+
+        assert!(SqliteStorage::from_connection(connection).is_err());
+    }
+}
@@ -0,0 +1,77 @@
+//! Runs the binary on every scenario of `tests/golden`. A scenario is a directory
+//! holding an `input.csv` file, an optional `args` file with one command line argument
+//! per line, and either the `expected.csv` output, compared regardless of the order of
+//! the accounts, or an `expected_error` that the run must fail with. The binary runs in
+//! the scenario directory, so that arguments can name the files it holds.
+
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    process::Command,
+};
+
+/// Header first, then the rows in sorted order.
+fn normalize(csv: &str) -> Vec<&str> {
+    let mut lines = csv.lines();
+    let header = lines.next().into_iter();
+    let mut rows: Vec<&str> = lines.filter(|line| !line.is_empty()).collect();
+    rows.sort_unstable();
+
+    header.chain(rows).collect()
+}
+
+fn run_scenario(scenario: &Path) -> Result<(), String> {
+    let args = fs::read_to_string(scenario.join("args")).unwrap_or_default();
+    let output = Command::new(env!("CARGO_BIN_EXE_kithmonite"))
+        .arg(scenario.join("input.csv"))
+        .args(args.lines().filter(|arg| !arg.is_empty()))
+        .current_dir(scenario)
+        .output()
+        .map_err(|error| format!("unable to run the binary: {error}"))?;
+
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let stderr = String::from_utf8_lossy(&output.stderr);
+
+    if let Ok(expected_error) = fs::read_to_string(scenario.join("expected_error")) {
+        return match output.status.success() {
+            true => Err(format!("expected a failure, got:\n{stdout}")),
+            false if stderr.contains(expected_error.trim()) => Ok(()),
+            false => Err(format!("expected {expected_error:?}, got:\n{stderr}")),
+        };
+    }
+
+    if !output.status.success() {
+        return Err(format!("the run failed:\n{stderr}"));
+    }
+
+    let expected = fs::read_to_string(scenario.join("expected.csv"))
+        .map_err(|error| format!("unable to read the expected output: {error}"))?;
+    if normalize(&stdout) != normalize(&expected) {
+        return Err(format!("expected:\n{expected}got:\n{stdout}"));
+    }
+
+    Ok(())
+}
+
+#[test]
+fn golden_files() {
+    let root = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/golden");
+    let mut scenarios: Vec<PathBuf> = fs::read_dir(&root)
+        .expect("golden files directory should be readable")
+        .map(|entry| entry.expect("golden files should be readable").path())
+        .filter(|path| path.is_dir())
+        .collect();
+    scenarios.sort();
+    assert!(!scenarios.is_empty(), "no scenario in {}", root.display());
+
+    let failures: Vec<String> = scenarios
+        .iter()
+        .filter_map(|scenario| {
+            run_scenario(scenario)
+                .err()
+                .map(|error| format!("{}: {error}", scenario.display()))
+        })
+        .collect();
+
+    assert!(failures.is_empty(), "{}", failures.join("\n\n"));
+}